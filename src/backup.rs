@@ -0,0 +1,830 @@
+use crate::{
+    DATA_FILE_SUFFIX, DataFile, Engine, Errors, FileSystem, IoManger, LogRecord, LogRecordType,
+    Options, Result,
+    data::{Cipher, DATA_FILE_HEADER_SIZE, get_data_file_name, get_hint_file_name, now_millis},
+    db::{check_options, save_seq_no},
+    fio::new_file_system,
+    options::RestoreTarget,
+};
+use bytes::{Buf, BufMut, BytesMut};
+use crc32fast::Hasher;
+use log::error;
+use std::{
+    collections::BTreeMap,
+    path::Path,
+    sync::{Arc, atomic::Ordering},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+const MANIFEST_SEQ_NO_KEY: &[u8] = "seq.no".as_bytes();
+const MANIFEST_CREATED_AT_KEY: &[u8] = "created.at".as_bytes();
+
+/// 备份的清单, 记录备份时的序列号以及每个数据文件备份的大小
+#[derive(Debug, Default)]
+pub(crate) struct BackupManifest {
+    // 备份时批量写入的序列号
+    pub(crate) seq_no: u64,
+    // 备份的时间, 毫秒级的 unix 时间戳
+    pub(crate) created_at: u64,
+    // 每个数据文件备份的部分
+    pub(crate) files: BTreeMap<u32, BackupFile>,
+}
+
+/// 数据文件备份的部分, 从文件开头到 size, crc 用于判断之后的备份中文件是否被 merge 替换
+///
+/// 本次备份中只包含 [offset, size) 的数据, 完整备份的文件 offset 为 0
+/// 增量备份中 offset 之前的数据由之前的备份提供
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct BackupFile {
+    pub(crate) size: u64,
+    pub(crate) crc: u32,
+    pub(crate) offset: u64,
+}
+
+impl Engine {
+    /// 在线备份到 dest_dir, 备份期间可以正常读写, 备份目录可以直接使用 Engine::open 打开
+    ///
+    /// 旧的数据文件不会再被修改, 优先使用硬链接, 活跃文件只拷贝备份开始时已经写入的部分
+    /// dest_dir 不存在时会被创建, 已经存在时必须为空
+    pub fn backup(&self, dest_dir: impl AsRef<Path>) -> Result<()> {
+        self.backup_from(None, dest_dir.as_ref())
+    }
+
+    /// 基于 base_dir 中的备份进行增量备份, 只备份之后新增的数据文件以及数据文件新增的尾部
+    ///
+    /// base_dir 可以是完整备份或者增量备份, 被 merge 替换的数据文件会重新完整备份
+    /// 增量备份不能直接打开, 需要和之前的备份一起通过 Engine::restore 恢复
+    pub fn backup_incremental(
+        &self,
+        base_dir: impl AsRef<Path>,
+        dest_dir: impl AsRef<Path>,
+    ) -> Result<()> {
+        let base = BackupManifest::read(self.fs.as_ref(), base_dir.as_ref())?;
+        self.backup_from(Some(&base), dest_dir.as_ref())
+    }
+
+    /// 从 backup_dirs 中的备份恢复数据库到 opts.dir_path, 恢复之后使用 Engine::open 打开
+    ///
+    /// backup_dirs 依次为一个完整备份以及之后的增量备份, 每个增量备份都基于前一个备份
+    /// 恢复的精度见 RestoreTarget, 按序列号只能恢复到批量写入或事务, 按时间可以恢复到任意一次提交
+    /// opts.dir_path 不存在时会被创建, 已经存在时必须为空, 恢复失败时需要清空之后重试
+    /// 备份和恢复的目录都位于 opts.io_type 对应的文件系统中
+    pub fn restore(
+        opts: &Options,
+        backup_dirs: &[impl AsRef<Path>],
+        target: RestoreTarget,
+    ) -> Result<()> {
+        if let Some(e) = check_options(opts) {
+            return Err(e);
+        }
+        let fs = new_file_system(&opts.io_type);
+        let fs = fs.as_ref();
+        let dir_path = opts.dir_path.as_path();
+        prepare_empty_dir(fs, dir_path, Errors::RestoreDirNotEmpty)?;
+
+        let mut manifest: Option<BackupManifest> = None;
+        for backup_dir in backup_dirs {
+            let backup_dir = backup_dir.as_ref();
+            let next = BackupManifest::read(fs, backup_dir)?;
+            apply_backup(fs, dir_path, backup_dir, manifest.as_ref(), &next)?;
+            let (seq_no, created_at) = (next.seq_no, next.created_at);
+            manifest = Some(next);
+
+            // 已经包含目标序列号或者目标时间的备份之后的备份都不需要
+            match target {
+                RestoreTarget::BatchSeqNo(target_seq_no) if seq_no >= target_seq_no => break,
+                RestoreTarget::Timestamp(time) if created_at >= unix_millis(time) => break,
+                _ => {}
+            }
+        }
+        let manifest = manifest.ok_or(Errors::NoBackupToRestore)?;
+
+        // 校验恢复之后的每个数据文件, 检查增量备份是否基于前一个备份
+        for (file_id, file) in manifest.files.iter() {
+            let file_name = get_data_file_name(dir_path, *file_id);
+            if file_size(fs, &file_name)? != file.size
+                || hash_file_prefix(fs, &file_name, file.size)?.finalize() != file.crc
+            {
+                return Err(Errors::InvalidBackupChain);
+            }
+        }
+
+        let cipher = opts
+            .encryption_key
+            .as_ref()
+            .map(|key| Arc::new(Cipher::new(key, &opts.old_encryption_keys)));
+        let mut seq_no = manifest.seq_no;
+        match target {
+            RestoreTarget::Latest => {}
+            // 组提交时在持有活跃文件的写锁时分配序列号, 数据文件中之前的批次序列号都更小
+            // 找不到该批次时返回 NoBackupToRestore, 比如已经被 merge 重写, 或者序列号没有被分配给提交成功的批次
+            RestoreTarget::BatchSeqNo(target_seq_no) => {
+                if target_seq_no > manifest.seq_no {
+                    return Err(Errors::NoBackupToRestore);
+                }
+                let found = truncate_at(fs, dir_path, &manifest, cipher, |record| {
+                    (record.rec_type == LogRecordType::BATCHFINISHED
+                        && record.seq_no == target_seq_no)
+                        .then_some(Cut::After)
+                })?;
+                if !found {
+                    return Err(Errors::NoBackupToRestore);
+                }
+                seq_no = target_seq_no;
+            }
+            // 丢弃第一条在目标时间之后提交的记录以及之后的所有数据, 没有找到时保留所有数据
+            RestoreTarget::Timestamp(time) => {
+                let target_millis = unix_millis(time);
+                if manifest.created_at < target_millis {
+                    return Err(Errors::NoBackupToRestore);
+                }
+                truncate_at(fs, dir_path, &manifest, cipher, |record| {
+                    (record.commit_at > target_millis).then_some(Cut::Before)
+                })?;
+            }
+        }
+
+        save_seq_no(fs, dir_path, seq_no)
+    }
+
+    // 备份到 dest_dir, base 不为空时跳过 base 中已经备份过的数据
+    fn backup_from(&self, base: Option<&BackupManifest>, dest_dir: &Path) -> Result<()> {
+        let fs = self.fs.as_ref();
+        prepare_empty_dir(fs, dest_dir, Errors::BackupDirNotEmpty)?;
+
+        // 备份期间不能进行 merge, 否则旧的数据文件会被替换
+        let _merging_lock = self.merging_lock.lock();
+        self.check_open()?;
+
+        // 持有活跃文件的锁记录所有数据文件当前的大小, 之后的写入不会包含在备份中
+        let (active_file_id, sizes, seq_no) = {
+            let active_file = self.active_file.read();
+            let older_files = self.older_files.read();
+            let mut sizes: BTreeMap<u32, u64> = older_files
+                .iter()
+                .map(|(file_id, data_file)| (*file_id, data_file.get_write_off()))
+                .collect();
+            sizes.insert(active_file.get_file_id(), active_file.get_write_off());
+            (
+                active_file.get_file_id(),
+                sizes,
+                self.seq_no.load(Ordering::SeqCst),
+            )
+        };
+
+        let dir_path = &self.options.dir_path;
+        let mut manifest = BackupManifest {
+            seq_no,
+            created_at: now_millis(),
+            files: BTreeMap::new(),
+        };
+        for (file_id, size) in sizes {
+            let src = get_data_file_name(dir_path, file_id);
+            let dest = get_data_file_name(dest_dir, file_id);
+
+            // base 中已经备份了文件的前一部分并且没有被 merge 替换, 只备份新增的尾部
+            if let Some(base_file) = base.and_then(|base| base.files.get(&file_id))
+                && base_file.size <= size
+            {
+                let hasher = hash_file_prefix(fs, &src, base_file.size)?;
+                if hasher.clone().finalize() == base_file.crc {
+                    let crc = match base_file.size < size {
+                        true => {
+                            let dest_file = create_file(fs, &dest)?;
+                            copy_file_range(fs, &src, dest_file, base_file.size, size, hasher)?
+                        }
+                        false => base_file.crc,
+                    };
+                    manifest.files.insert(
+                        file_id,
+                        BackupFile {
+                            size,
+                            crc,
+                            offset: base_file.size,
+                        },
+                    );
+                    continue;
+                }
+            }
+
+            let crc = match file_id == active_file_id {
+                true => {
+                    let dest_file = create_file(fs, &dest)?;
+                    copy_file_range(fs, &src, dest_file, 0, size, Hasher::new())?
+                }
+                false => link_or_copy_file(fs, &src, &dest, size)?,
+            };
+            manifest.files.insert(
+                file_id,
+                BackupFile {
+                    size,
+                    crc,
+                    offset: 0,
+                },
+            );
+
+            // merge 产生的 hint 文件同样不会再被修改
+            let hint_src = get_hint_file_name(dir_path, file_id);
+            if fs.is_file(&hint_src) {
+                let hint_size = file_size(fs, &hint_src)?;
+                let hint_dest = get_hint_file_name(dest_dir, file_id);
+                link_or_copy_file(fs, &hint_src, &hint_dest, hint_size)?;
+            }
+        }
+
+        // merge 之后的数据文件中不再保留序列号, 需要一起备份
+        save_seq_no(fs, dest_dir, seq_no)?;
+        manifest.write(fs, dest_dir)
+    }
+}
+
+impl BackupManifest {
+    /// 将清单写入到备份目录中
+    pub(crate) fn write(&self, fs: &dyn FileSystem, dir_path: &Path) -> Result<()> {
+        let manifest_file = DataFile::new_backup_manifest_file(fs, dir_path.to_path_buf())?;
+
+        let mut buf = Vec::new();
+        for (key, value) in [
+            (MANIFEST_SEQ_NO_KEY, self.seq_no),
+            (MANIFEST_CREATED_AT_KEY, self.created_at),
+        ] {
+            let mut record = LogRecord::new(key.to_vec(), value.to_be_bytes().to_vec());
+            buf.extend_from_slice(&record.encode());
+        }
+        for (file_id, file) in self.files.iter() {
+            let mut value = BytesMut::with_capacity(20);
+            value.put_u64(file.size);
+            value.put_u32(file.crc);
+            value.put_u64(file.offset);
+            let file_name = format!("{:09}{}", file_id, DATA_FILE_SUFFIX);
+            let mut record = LogRecord::new(file_name.into_bytes(), value.to_vec());
+            buf.extend_from_slice(&record.encode());
+        }
+
+        manifest_file.write(&buf)?;
+        manifest_file.sync()
+    }
+
+    /// 读取备份目录中的清单, 清单不存在或者无法解析时返回 BackupManifestCorrupted
+    pub(crate) fn read(fs: &dyn FileSystem, dir_path: &Path) -> Result<Self> {
+        let manifest_file = DataFile::open_backup_manifest_file(fs, dir_path.to_path_buf())
+            .map_err(|_| Errors::BackupManifestCorrupted)?;
+
+        let mut manifest = BackupManifest::default();
+        let mut offset = DATA_FILE_HEADER_SIZE;
+        loop {
+            let (record, size) = match manifest_file.read_log_record(offset) {
+                Ok(result) => (result.record, result.size),
+                Err(Errors::ReadDataFileEOF) => break,
+                Err(_) => return Err(Errors::BackupManifestCorrupted),
+            };
+            offset += size;
+
+            let mut value = record.value.as_slice();
+            match record.key.as_slice() {
+                MANIFEST_SEQ_NO_KEY if value.len() == 8 => manifest.seq_no = value.get_u64(),
+                MANIFEST_CREATED_AT_KEY if value.len() == 8 => {
+                    manifest.created_at = value.get_u64()
+                }
+                key => {
+                    let file_id = std::str::from_utf8(key)
+                        .ok()
+                        .and_then(|name| name.strip_suffix(DATA_FILE_SUFFIX))
+                        .and_then(|file_id| file_id.parse::<u32>().ok())
+                        .filter(|_| value.len() == 20)
+                        .ok_or(Errors::BackupManifestCorrupted)?;
+                    let file = BackupFile {
+                        size: value.get_u64(),
+                        crc: value.get_u32(),
+                        offset: value.get_u64(),
+                    };
+                    manifest.files.insert(file_id, file);
+                }
+            }
+        }
+
+        Ok(manifest)
+    }
+}
+
+// 将一个备份应用到恢复目录中, prev 为之前已经应用的备份
+// 完整备份的文件替换已有的文件, 增量备份的尾部追加到之前恢复的文件
+fn apply_backup(
+    fs: &dyn FileSystem,
+    dir_path: &Path,
+    backup_dir: &Path,
+    prev: Option<&BackupManifest>,
+    manifest: &BackupManifest,
+) -> Result<()> {
+    // 删除已经被 merge 移除的数据文件
+    for file_id in prev.iter().flat_map(|prev| prev.files.keys()) {
+        if !manifest.files.contains_key(file_id) {
+            remove_file_if_exists(fs, &get_data_file_name(dir_path, *file_id))?;
+            remove_file_if_exists(fs, &get_hint_file_name(dir_path, *file_id))?;
+        }
+    }
+
+    for (file_id, file) in manifest.files.iter() {
+        let src = get_data_file_name(backup_dir, *file_id);
+        let dest = get_data_file_name(dir_path, *file_id);
+        let dest_hint = get_hint_file_name(dir_path, *file_id);
+
+        // 恢复的文件之后会被写入, 总是拷贝, 不使用硬链接
+        if file.offset == 0 {
+            remove_file_if_exists(fs, &dest)?;
+            remove_file_if_exists(fs, &dest_hint)?;
+            let dest_file = create_file(fs, &dest)?;
+            copy_file_range(fs, &src, dest_file, 0, file.size, Hasher::new())?;
+
+            let src_hint = get_hint_file_name(backup_dir, *file_id);
+            if fs.is_file(&src_hint) {
+                let hint_file = create_file(fs, &dest_hint)?;
+                let hint_size = file_size(fs, &src_hint)?;
+                copy_file_range(fs, &src_hint, hint_file, 0, hint_size, Hasher::new())?;
+            }
+            continue;
+        }
+
+        // 增量备份的文件, 之前的备份中必须已经恢复了 offset 之前的数据
+        if !fs.is_file(&dest) || file_size(fs, &dest)? != file.offset {
+            return Err(Errors::InvalidBackupChain);
+        }
+        if file.offset < file.size {
+            let dest_file = fs.open(&dest)?;
+            let len = file.size - file.offset;
+            copy_file_range(fs, &src, dest_file, 0, len, Hasher::new())?;
+        }
+    }
+
+    Ok(())
+}
+
+// 数据文件的截断位置, 在找到的记录之前或者之后
+enum Cut {
+    Before,
+    After,
+}
+
+// 按照文件 id 的顺序查找第一条 cut_at 返回截断位置的记录, 截断该位置之后的所有数据, 返回是否找到
+fn truncate_at(
+    fs: &dyn FileSystem,
+    dir_path: &Path,
+    manifest: &BackupManifest,
+    cipher: Option<Arc<Cipher>>,
+    mut cut_at: impl FnMut(&LogRecord) -> Option<Cut>,
+) -> Result<bool> {
+    let mut truncated = false;
+    for file_id in manifest.files.keys() {
+        let file_name = get_data_file_name(dir_path, *file_id);
+        let hint_file_name = get_hint_file_name(dir_path, *file_id);
+        if truncated {
+            remove_file_if_exists(fs, &file_name)?;
+            remove_file_if_exists(fs, &hint_file_name)?;
+            continue;
+        }
+
+        let data_file =
+            DataFile::open(fs, dir_path.to_path_buf(), *file_id)?.with_cipher(cipher.clone());
+        let mut offset = DATA_FILE_HEADER_SIZE;
+        loop {
+            let result = match data_file.read_log_record(offset) {
+                Ok(result) => result,
+                Err(Errors::ReadDataFileEOF) => break,
+                Err(e) => return Err(e),
+            };
+            match cut_at(&result.record) {
+                Some(Cut::Before) => {}
+                Some(Cut::After) => offset += result.size,
+                None => {
+                    offset += result.size;
+                    continue;
+                }
+            }
+            truncated = true;
+            break;
+        }
+        if truncated {
+            data_file.truncate(offset)?;
+            data_file.sync()?;
+            // hint 文件中可能包含被截断的数据的位置
+            remove_file_if_exists(fs, &hint_file_name)?;
+        }
+    }
+
+    Ok(truncated)
+}
+
+// 创建备份或者恢复的目录, 已经存在的目录必须为空
+fn prepare_empty_dir(fs: &dyn FileSystem, dir_path: &Path, not_empty: Errors) -> Result<()> {
+    if let Err(e) = fs.create_dir_all(dir_path) {
+        error!("failed to create directory: {}", e);
+        return Err(Errors::FailedToCreateDatabaseDir);
+    }
+
+    match fs.list(dir_path) {
+        Ok(names) => match names.is_empty() {
+            true => Ok(()),
+            false => Err(not_empty),
+        },
+        Err(e) => {
+            error!("failed to read directory: {}", e);
+            Err(Errors::FailedToReadDatabaseDir)
+        }
+    }
+}
+
+fn unix_millis(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_millis() as u64)
+        .unwrap_or(0)
+}
+
+fn file_size(fs: &dyn FileSystem, path: &Path) -> Result<u64> {
+    match fs.file_size(path) {
+        Ok(size) => Ok(size),
+        Err(e) => {
+            error!("failed to read file metadata: {}", e);
+            Err(Errors::FailedToReadFromDataFile)
+        }
+    }
+}
+
+fn remove_file_if_exists(fs: &dyn FileSystem, path: &Path) -> Result<()> {
+    if fs.is_file(path)
+        && let Err(e) = fs.remove_file(path)
+    {
+        error!("failed to remove file: {}", e);
+        return Err(Errors::FailedToWriteToDataFile);
+    }
+    Ok(())
+}
+
+// 不会再被修改的文件优先使用硬链接, 不支持时拷贝, 返回文件前 len 个字节的 crc
+fn link_or_copy_file(fs: &dyn FileSystem, src: &Path, dest: &Path, len: u64) -> Result<u32> {
+    if fs.hard_link(src, dest).is_err() {
+        let dest_file = create_file(fs, dest)?;
+        return copy_file_range(fs, src, dest_file, 0, len, Hasher::new());
+    }
+
+    Ok(hash_file_prefix(fs, src, len)?.finalize())
+}
+
+// 计算文件前 len 个字节的 crc
+fn hash_file_prefix(fs: &dyn FileSystem, path: &Path, len: u64) -> Result<Hasher> {
+    let mut hasher = Hasher::new();
+    read_file_range(fs.open(path)?.as_ref(), 0, len, |buf| {
+        hasher.update(buf);
+        Ok(())
+    })?;
+    Ok(hasher)
+}
+
+// 将 src 中 [offset, len) 的数据追加到 dest_file 并持久化
+// hasher 中已经包含 offset 之前数据的 crc, 返回前 len 个字节的 crc
+fn copy_file_range(
+    fs: &dyn FileSystem,
+    src: &Path,
+    dest_file: Box<dyn IoManger>,
+    offset: u64,
+    len: u64,
+    mut hasher: Hasher,
+) -> Result<u32> {
+    read_file_range(fs.open(src)?.as_ref(), offset, len, |buf| {
+        hasher.update(buf);
+        dest_file.write(buf).map(|_| ()).map_err(|e| {
+            error!("failed to write backup file: {}", e);
+            Errors::FailedToCopyDataFile
+        })
+    })?;
+
+    dest_file.sync()?;
+    Ok(hasher.finalize())
+}
+
+fn create_file(fs: &dyn FileSystem, path: &Path) -> Result<Box<dyn IoManger>> {
+    fs.create(path).map_err(|e| {
+        error!("failed to create backup file: {}", e);
+        Errors::FailedToCopyDataFile
+    })
+}
+
+// 按块读取文件中 [offset, len) 的数据, 文件不足 len 个字节时返回错误
+fn read_file_range(
+    file: &dyn IoManger,
+    offset: u64,
+    len: u64,
+    mut f: impl FnMut(&[u8]) -> Result<()>,
+) -> Result<()> {
+    let mut buf = vec![0; 64 * 1024];
+    let mut pos = offset;
+    while pos < len {
+        let size = buf.len().min((len - pos) as usize);
+        let n = file.read(&mut buf[..size], pos)?;
+        if n == 0 {
+            return Err(Errors::FailedToReadFromDataFile);
+        }
+        f(&buf[..n])?;
+        pos += n as u64;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{IoType, MemoryFileSystem, Options, WriteBatchOptions};
+    use bytes::Bytes;
+    use std::{path::PathBuf, thread, time::Duration};
+
+    #[test]
+    fn engine_backup_should_be_openable() {
+        let fs = MemoryFileSystem::new();
+        let opts = Options {
+            dir_path: PathBuf::from("/tmp/bitcask-backup"),
+            io_type: IoType::Memory(fs.clone()),
+            data_file_size: 32 * 1024,
+            ..Default::default()
+        };
+        let backup_dir = PathBuf::from("/tmp/bitcask-backup-dest");
+
+        let engine = Engine::open(opts.clone()).expect("failed to open engine");
+        let value = |i: usize, round: usize| Bytes::from(format!("value-{}-{}", i, round));
+        for i in 0..1000 {
+            engine
+                .put(Bytes::from(format!("key-{}", i)), value(i, 0))
+                .unwrap();
+        }
+        // merge 之后存在 hint 文件, 也会一起备份
+        engine.merge().expect("failed to merge");
+        let wb = engine
+            .new_write_batch(crate::WriteBatchOptions::default())
+            .unwrap();
+        for i in 0..500 {
+            wb.put(Bytes::from(format!("key-{}", i)), value(i, 1))
+                .unwrap();
+        }
+        wb.commit().unwrap();
+
+        engine.backup(&backup_dir).expect("failed to backup");
+        assert!(DataFile::open_backup_manifest_file(&fs, backup_dir.clone()).is_ok());
+
+        // 备份之后的写入不会出现在备份中
+        engine.put(Bytes::from("key-0"), value(0, 2)).unwrap();
+        engine.delete(Bytes::from("key-999")).unwrap();
+
+        // 备份目录不为空时返回错误
+        assert_eq!(
+            engine.backup(&backup_dir).err(),
+            Some(Errors::BackupDirNotEmpty)
+        );
+        assert_eq!(
+            engine.backup(&opts.dir_path).err(),
+            Some(Errors::BackupDirNotEmpty)
+        );
+
+        let backup = Engine::open(Options {
+            dir_path: backup_dir.clone(),
+            ..opts.clone()
+        })
+        .expect("failed to open backup");
+        assert_eq!(backup.stat().unwrap().key_num, 1000);
+        for i in 0..1000 {
+            assert_eq!(
+                backup.get(Bytes::from(format!("key-{}", i))).unwrap(),
+                value(i, if i < 500 { 1 } else { 0 })
+            );
+        }
+        assert_eq!(backup.seq_no.load(Ordering::SeqCst), 1);
+
+        // 原来的数据库不受影响
+        assert_eq!(engine.get(Bytes::from("key-0")).unwrap(), value(0, 2));
+        engine.merge().expect("failed to merge");
+        assert_eq!(backup.get(Bytes::from("key-999")).unwrap(), value(999, 0));
+
+        drop(backup);
+        drop(engine);
+    }
+
+    #[test]
+    fn engine_incremental_backup_and_restore_should_work() {
+        let fs = MemoryFileSystem::new();
+        let opts = Options {
+            dir_path: PathBuf::from("/tmp/bitcask-backup-incr"),
+            io_type: IoType::Memory(fs.clone()),
+            data_file_size: 8 * 1024,
+            ..Default::default()
+        };
+        let backup_dirs: Vec<PathBuf> = (0..3)
+            .map(|i| PathBuf::from(format!("/tmp/bitcask-backup-incr-{}", i)))
+            .collect();
+        let restore_dir = PathBuf::from("/tmp/bitcask-backup-incr-restore");
+
+        let engine = Engine::open(opts.clone()).expect("failed to open engine");
+        let value = |i: usize, round: usize| Bytes::from(format!("value-{}-{}", i, round));
+        let write_batch = |round: usize| {
+            let wb = engine
+                .new_write_batch(WriteBatchOptions::default())
+                .unwrap();
+            for i in 0..100 {
+                wb.put(Bytes::from(format!("key-{}", i)), value(i, round))
+                    .unwrap();
+            }
+            wb.commit().unwrap();
+        };
+
+        for i in 0..500 {
+            engine
+                .put(Bytes::from(format!("key-{}", i)), value(i, 0))
+                .unwrap();
+        }
+        engine.backup(&backup_dirs[0]).expect("failed to backup");
+
+        // 序列号为 1 的批次, 之后的普通写入在数据文件中位于该批次之后
+        write_batch(1);
+        thread::sleep(Duration::from_millis(10));
+        let after_first_batch = SystemTime::now();
+        thread::sleep(Duration::from_millis(10));
+        for i in 500..800 {
+            engine
+                .put(Bytes::from(format!("key-{}", i)), value(i, 0))
+                .unwrap();
+        }
+        engine
+            .backup_incremental(&backup_dirs[0], &backup_dirs[1])
+            .expect("failed to backup incrementally");
+
+        // 增量备份中只包含新增的数据文件以及活跃文件新增的尾部
+        let base = BackupManifest::read(&fs, &backup_dirs[0]).unwrap();
+        let manifest = BackupManifest::read(&fs, &backup_dirs[1]).unwrap();
+        assert_eq!(manifest.seq_no, 1);
+        for (file_id, file) in manifest.files.iter() {
+            match base.files.get(file_id) {
+                Some(base_file) => assert_eq!(file.offset, base_file.size),
+                None => assert_eq!(file.offset, 0),
+            }
+        }
+        assert!(manifest.files.len() > base.files.len());
+
+        thread::sleep(Duration::from_millis(10));
+        let after_second_backup = SystemTime::now();
+        thread::sleep(Duration::from_millis(10));
+
+        write_batch(2);
+        engine
+            .backup_incremental(&backup_dirs[1], &backup_dirs[2])
+            .expect("failed to backup incrementally");
+        assert_eq!(engine.stat().unwrap().seq_no, 2);
+
+        let restore = |dirs: &[PathBuf], target: RestoreTarget| {
+            let _ = fs.remove_dir_all(&restore_dir);
+            let restore_opts = Options {
+                dir_path: restore_dir.clone(),
+                ..opts.clone()
+            };
+            Engine::restore(&restore_opts, dirs, target)?;
+            Engine::open(restore_opts)
+        };
+        let check = |restored: &Engine, round: usize, with_tail: bool| {
+            for i in 0..800 {
+                let res = restored.get(Bytes::from(format!("key-{}", i)));
+                match i {
+                    0..100 => assert_eq!(res.unwrap(), value(i, round)),
+                    100..500 => assert_eq!(res.unwrap(), value(i, 0)),
+                    _ if with_tail => assert_eq!(res.unwrap(), value(i, 0)),
+                    _ => assert_eq!(res.err(), Some(Errors::KeyNotFound)),
+                }
+            }
+        };
+
+        let restored = restore(&backup_dirs, RestoreTarget::Latest).unwrap();
+        check(&restored, 2, true);
+        assert_eq!(restored.seq_no.load(Ordering::SeqCst), 2);
+        drop(restored);
+
+        let restored = restore(&backup_dirs, RestoreTarget::BatchSeqNo(2)).unwrap();
+        check(&restored, 2, true);
+        drop(restored);
+
+        // 恢复到序列号为 1 的批次提交时, 之后的普通写入也被丢弃
+        let restored = restore(&backup_dirs, RestoreTarget::BatchSeqNo(1)).unwrap();
+        check(&restored, 1, false);
+        assert_eq!(restored.seq_no.load(Ordering::SeqCst), 1);
+        // 恢复之后的数据库可以正常写入
+        write_batch(3);
+        drop(restored);
+
+        // 备份中没有的批次无法恢复
+        for seq_no in [0, 3] {
+            assert_eq!(
+                restore(&backup_dirs, RestoreTarget::BatchSeqNo(seq_no)).err(),
+                Some(Errors::NoBackupToRestore)
+            );
+        }
+
+        // 按时间恢复时, 该时间之后提交的普通写入也被丢弃
+        let restored = restore(&backup_dirs, RestoreTarget::Timestamp(after_first_batch)).unwrap();
+        check(&restored, 1, false);
+        drop(restored);
+
+        let restored =
+            restore(&backup_dirs, RestoreTarget::Timestamp(after_second_backup)).unwrap();
+        check(&restored, 1, true);
+        drop(restored);
+
+        // 所有的记录都在该时间之后提交
+        let restored = restore(&backup_dirs, RestoreTarget::Timestamp(UNIX_EPOCH)).unwrap();
+        assert_eq!(restored.stat().unwrap().key_num, 0);
+        drop(restored);
+
+        // 最后一个备份之后的时间无法恢复
+        let after_last_backup = SystemTime::now() + Duration::from_secs(1);
+        assert_eq!(
+            restore(&backup_dirs, RestoreTarget::Timestamp(after_last_backup)).err(),
+            Some(Errors::NoBackupToRestore)
+        );
+
+        // 跳过中间的增量备份时无法恢复
+        assert_eq!(
+            restore(
+                &[backup_dirs[0].clone(), backup_dirs[2].clone()],
+                RestoreTarget::Latest
+            )
+            .err(),
+            Some(Errors::InvalidBackupChain)
+        );
+
+        // 恢复的目录不为空时返回错误
+        assert_eq!(
+            Engine::restore(&opts, &backup_dirs, RestoreTarget::Latest).err(),
+            Some(Errors::RestoreDirNotEmpty)
+        );
+
+        drop(engine);
+    }
+
+    #[test]
+    fn engine_incremental_backup_after_merge_should_work() {
+        let fs = MemoryFileSystem::new();
+        let opts = Options {
+            dir_path: PathBuf::from("/tmp/bitcask-backup-incr-merge"),
+            io_type: IoType::Memory(fs.clone()),
+            data_file_size: 32 * 1024,
+            ..Default::default()
+        };
+        let backup_dirs: Vec<PathBuf> = (0..2)
+            .map(|i| PathBuf::from(format!("/tmp/bitcask-backup-incr-merge-{}", i)))
+            .collect();
+        let restore_dir = PathBuf::from("/tmp/bitcask-backup-incr-merge-restore");
+
+        let engine = Engine::open(opts.clone()).expect("failed to open engine");
+        let value = |i: usize, round: usize| Bytes::from(format!("value-{}-{}", i, round));
+        for i in 0..1000 {
+            engine
+                .put(Bytes::from(format!("key-{}", i)), value(i, 0))
+                .unwrap();
+        }
+        engine.backup(&backup_dirs[0]).expect("failed to backup");
+
+        // merge 替换了之前备份过的数据文件, 增量备份中需要重新完整备份
+        for i in 0..500 {
+            engine.delete(Bytes::from(format!("key-{}", i))).unwrap();
+        }
+        engine.merge().expect("failed to merge");
+        engine.put(Bytes::from("key-0"), value(0, 1)).unwrap();
+        engine
+            .backup_incremental(&backup_dirs[0], &backup_dirs[1])
+            .expect("failed to backup incrementally");
+
+        let base = BackupManifest::read(&fs, &backup_dirs[0]).unwrap();
+        let manifest = BackupManifest::read(&fs, &backup_dirs[1]).unwrap();
+        assert!(
+            manifest
+                .files
+                .iter()
+                .any(|(file_id, file)| base.files.contains_key(file_id) && file.offset == 0)
+        );
+
+        let restore_opts = Options {
+            dir_path: restore_dir.clone(),
+            ..opts.clone()
+        };
+        Engine::restore(&restore_opts, &backup_dirs, RestoreTarget::Latest)
+            .expect("failed to restore");
+        let restored = Engine::open(restore_opts).expect("failed to open restored engine");
+        assert_eq!(restored.stat().unwrap().key_num, 501);
+        assert_eq!(restored.get(Bytes::from("key-0")).unwrap(), value(0, 1));
+        assert_eq!(
+            restored.get(Bytes::from("key-1")).err(),
+            Some(Errors::KeyNotFound)
+        );
+        for i in 500..1000 {
+            assert_eq!(
+                restored.get(Bytes::from(format!("key-{}", i))).unwrap(),
+                value(i, 0)
+            );
+        }
+
+        drop(restored);
+        drop(engine);
+    }
+}
@@ -0,0 +1,302 @@
+use crate::{
+    Engine, Errors, LogRecord, LogRecordType, Result,
+    data::{NEVER_EXPIRE, NO_COMMIT_TIME, NON_BATCH_SEQ_NO},
+    options::WriteBatchOptions,
+};
+use bytes::Bytes;
+use parking_lot::Mutex;
+use std::collections::HashMap;
+
+const BATCH_FIN_KEY: &[u8] = "batch.finished".as_bytes();
+
+/// 批量写入数据, 保证原子性
+pub struct WriteBatch<'a> {
+    // 暂存用户写入的数据
+    pending_writes: Mutex<HashMap<Vec<u8>, LogRecord>>,
+    engine: &'a Engine,
+    options: WriteBatchOptions,
+}
+
+impl Engine {
+    /// 初始化 WriteBatch
+    pub fn new_write_batch(&self, options: WriteBatchOptions) -> Result<WriteBatch<'_>> {
+        self.check_open()?;
+        Ok(WriteBatch {
+            pending_writes: Mutex::new(HashMap::new()),
+            engine: self,
+            options,
+        })
+    }
+
+    /// 在一批记录的最后追加完成标记, 重启时只有读到完成标记的批次才有效
+    /// 序列号在组提交写入之前分配, 见 group_commit_batch
+    pub(crate) fn batch_log_records<'a>(
+        &self,
+        records: impl Iterator<Item = &'a LogRecord>,
+    ) -> Vec<LogRecord> {
+        let mut records: Vec<LogRecord> = records.cloned().collect();
+        records.push(LogRecord {
+            key: BATCH_FIN_KEY.to_vec(),
+            value: Default::default(),
+            rec_type: LogRecordType::BATCHFINISHED,
+            seq_no: NON_BATCH_SEQ_NO,
+            expire_at: NEVER_EXPIRE,
+            commit_at: NO_COMMIT_TIME,
+        });
+        records
+    }
+}
+
+impl WriteBatch<'_> {
+    /// 批量操作写数据
+    pub fn put(&self, key: Bytes, value: Bytes) -> Result<()> {
+        if key.is_empty() {
+            return Err(Errors::KeyIsEmpty);
+        }
+
+        // 暂存数据
+        let record = LogRecord::new(key.to_vec(), value.to_vec());
+        let mut pending_writes = self.pending_writes.lock();
+        pending_writes.insert(key.to_vec(), record);
+
+        Ok(())
+    }
+
+    /// 批量操作删除数据
+    pub fn delete(&self, key: Bytes) -> Result<()> {
+        if key.is_empty() {
+            return Err(Errors::KeyIsEmpty);
+        }
+
+        let mut pending_writes = self.pending_writes.lock();
+        // 如果数据不存在或者已经过期则直接返回, 同时丢弃暂存的写入
+        if self
+            .engine
+            .index
+            .get(key.to_vec())
+            .is_none_or(|pos| pos.is_expired())
+        {
+            pending_writes.remove(&key.to_vec());
+            return Ok(());
+        }
+
+        // 暂存数据
+        let record = LogRecord {
+            key: key.to_vec(),
+            value: Default::default(),
+            rec_type: LogRecordType::DELETED,
+            seq_no: NON_BATCH_SEQ_NO,
+            expire_at: NEVER_EXPIRE,
+            commit_at: NO_COMMIT_TIME,
+        };
+        pending_writes.insert(key.to_vec(), record);
+
+        Ok(())
+    }
+
+    /// 提交数据, 将数据写到文件当中, 并更新内存索引
+    pub fn commit(&self) -> Result<()> {
+        let mut pending_writes = self.pending_writes.lock();
+        if pending_writes.is_empty() {
+            return Ok(());
+        }
+        if pending_writes.len() > self.options.max_batch_num {
+            return Err(Errors::ExceedMaxBatchNum);
+        }
+
+        // 批次中的数据以及完成标记作为一个写入请求提交, 在数据文件中是连续的
+        let records = self.engine.batch_log_records(pending_writes.values());
+
+        // 根据配置决定是否持久化, 写入之后会更新内存索引
+        self.engine
+            .group_commit_batch(records, self.options.sync_writes, None)?;
+        pending_writes.clear();
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{IoType, MemoryFileSystem, Options, data::DATA_FILE_HEADER_SIZE};
+    use std::{fs, path::PathBuf, sync::atomic::Ordering, thread};
+
+    #[test]
+    fn write_batch_commit_should_work() {
+        let opts = Options {
+            dir_path: PathBuf::from("/tmp/bitcask-batch-commit"),
+            ..Default::default()
+        };
+        let _ = fs::remove_dir_all(&opts.dir_path);
+
+        let engine = Engine::open(opts.clone()).expect("failed to open engine");
+        engine
+            .put(Bytes::from("c"), Bytes::from("c-value"))
+            .unwrap();
+
+        let wb = engine
+            .new_write_batch(WriteBatchOptions::default())
+            .expect("failed to create write batch");
+        wb.put(Bytes::from("a"), Bytes::from("a-value")).unwrap();
+        wb.put(Bytes::from("b"), Bytes::from("b-value")).unwrap();
+        wb.delete(Bytes::from("c")).unwrap();
+        // 删除不存在的 key
+        wb.delete(Bytes::from("d")).unwrap();
+
+        // 提交之前数据不可见
+        assert_eq!(
+            engine.get(Bytes::from("a")).err(),
+            Some(Errors::KeyNotFound)
+        );
+        assert_eq!(
+            engine.get(Bytes::from("c")).unwrap(),
+            Bytes::from("c-value")
+        );
+
+        wb.commit().expect("failed to commit");
+        assert_eq!(
+            engine.get(Bytes::from("a")).unwrap(),
+            Bytes::from("a-value")
+        );
+        assert_eq!(
+            engine.get(Bytes::from("b")).unwrap(),
+            Bytes::from("b-value")
+        );
+        assert_eq!(
+            engine.get(Bytes::from("c")).err(),
+            Some(Errors::KeyNotFound)
+        );
+        assert_eq!(engine.seq_no.load(Ordering::SeqCst), 1);
+
+        // 再次提交空的批次
+        assert!(wb.commit().is_ok());
+        drop(wb);
+        drop(engine);
+
+        // 重启之后数据仍然存在, 序列号也会恢复
+        let engine = Engine::open(opts.clone()).expect("failed to reopen engine");
+        assert_eq!(
+            engine.get(Bytes::from("a")).unwrap(),
+            Bytes::from("a-value")
+        );
+        assert_eq!(
+            engine.get(Bytes::from("b")).unwrap(),
+            Bytes::from("b-value")
+        );
+        assert_eq!(
+            engine.get(Bytes::from("c")).err(),
+            Some(Errors::KeyNotFound)
+        );
+        assert_eq!(engine.seq_no.load(Ordering::SeqCst), 1);
+
+        fs::remove_dir_all(opts.dir_path).expect("failed to remove path");
+    }
+
+    #[test]
+    fn write_batch_concurrent_commit_should_write_in_seq_order() {
+        let opts = Options {
+            dir_path: PathBuf::from("/tmp/bitcask-batch-seq-order"),
+            ..Default::default()
+        };
+        let _ = fs::remove_dir_all(&opts.dir_path);
+
+        let engine = Engine::open(opts.clone()).expect("failed to open engine");
+        thread::scope(|s| {
+            for t in 0..4 {
+                let engine = &engine;
+                s.spawn(move || {
+                    for i in 0..50 {
+                        let wb = engine
+                            .new_write_batch(WriteBatchOptions::default())
+                            .unwrap();
+                        let key = Bytes::from(format!("key-{}-{}", t, i));
+                        wb.put(key, Bytes::from("value")).unwrap();
+                        wb.commit().unwrap();
+                    }
+                });
+            }
+        });
+        assert_eq!(engine.seq_no.load(Ordering::SeqCst), 200);
+
+        // 数据文件中批次的顺序和序列号的顺序一致
+        let active_file = engine.active_file.read();
+        let mut offset = DATA_FILE_HEADER_SIZE;
+        let mut seq_nos = Vec::new();
+        while offset < active_file.get_write_off() {
+            let res = active_file.read_log_record(offset).unwrap();
+            if res.record.rec_type == LogRecordType::BATCHFINISHED {
+                seq_nos.push(res.record.seq_no);
+            }
+            offset += res.size;
+        }
+        assert_eq!(seq_nos, (1..=200).collect::<Vec<u64>>());
+        drop(active_file);
+
+        drop(engine);
+        fs::remove_dir_all(opts.dir_path).expect("failed to remove path");
+    }
+
+    #[test]
+    fn write_batch_without_finish_record_should_be_discarded() {
+        let opts = Options {
+            dir_path: PathBuf::from("/tmp/bitcask-batch-unfinished"),
+            io_type: IoType::Memory(MemoryFileSystem::new()),
+            ..Default::default()
+        };
+
+        let engine = Engine::open(opts.clone()).expect("failed to open engine");
+        engine.put(Bytes::from("a"), Bytes::from("old")).unwrap();
+
+        // 模拟批量写入到一半时崩溃, 只写入了数据, 没有写入完成标记
+        {
+            let mut active_file = engine.active_file.write();
+            let mut records: Vec<LogRecord> = ["a", "b"]
+                .iter()
+                .map(|key| {
+                    let mut record = LogRecord::new(key.as_bytes().to_vec(), b"new".to_vec());
+                    record.seq_no = 1;
+                    record
+                })
+                .collect();
+            engine
+                .write_log_records(&mut active_file, &mut records)
+                .unwrap();
+        }
+        drop(engine);
+
+        let engine = Engine::open(opts.clone()).expect("failed to reopen engine");
+        assert_eq!(engine.get(Bytes::from("a")).unwrap(), Bytes::from("old"));
+        assert_eq!(
+            engine.get(Bytes::from("b")).err(),
+            Some(Errors::KeyNotFound)
+        );
+
+        // 未完成的批量写入是失效数据
+        let dead_bytes: u64 = engine.dead_bytes.read().values().sum();
+        assert!(dead_bytes > 0);
+    }
+
+    #[test]
+    fn write_batch_exceed_max_num_should_fail() {
+        let opts = Options {
+            dir_path: PathBuf::from("/tmp/bitcask-batch-max-num"),
+            ..Default::default()
+        };
+        let _ = fs::remove_dir_all(&opts.dir_path);
+
+        let engine = Engine::open(opts.clone()).expect("failed to open engine");
+        let wb = engine
+            .new_write_batch(WriteBatchOptions {
+                max_batch_num: 2,
+                sync_writes: false,
+            })
+            .unwrap();
+        for key in ["a", "b", "c"] {
+            wb.put(Bytes::from(key), Bytes::from("value")).unwrap();
+        }
+        assert_eq!(wb.commit().err(), Some(Errors::ExceedMaxBatchNum));
+
+        fs::remove_dir_all(opts.dir_path).expect("failed to remove path");
+    }
+}
@@ -0,0 +1,308 @@
+use crate::{Engine, Errors, Result, data::DATA_FILE_HEADER_SIZE, options::CompactionOptions};
+use log::error;
+use parking_lot::{Condvar, Mutex};
+use std::{
+    sync::{
+        Arc, Weak,
+        atomic::{AtomicBool, Ordering},
+    },
+    thread::{self, JoinHandle},
+    time::{Duration, Instant},
+};
+
+/// 后台 compaction 的状态, 由 Engine 和后台线程共享
+pub(crate) struct Compaction {
+    options: CompactionOptions,
+    // 是否暂停后台 compaction
+    paused: AtomicBool,
+    // 是否停止后台线程, 通过条件变量唤醒等待中的线程
+    stopped: Mutex<bool>,
+    cond: Condvar,
+    // 后台线程的句柄
+    handle: Mutex<Option<JoinHandle<()>>>,
+    // merge 的限速器
+    rate_limiter: Mutex<RateLimiter>,
+}
+
+/// 按照每秒字节数限制读写速度
+struct RateLimiter {
+    bytes_per_sec: u64,
+    // 本次限速的开始时间
+    start: Instant,
+    // 开始之后已经处理的字节数
+    consumed: u64,
+}
+
+impl Engine {
+    /// 启动后台 compaction 线程, 定期检查失效数据, 达到 Options 中配置的阈值时执行 merge
+    /// 后台线程只持有 Engine 的弱引用, 不会阻止 Engine 被释放
+    /// 配置中没有启用 compaction 时返回 CompactionNotEnabled
+    pub fn start_compaction(self: &Arc<Self>) -> Result<()> {
+        if !self.compaction.options.enabled {
+            return Err(Errors::CompactionNotEnabled);
+        }
+
+        let mut handle = self.compaction.handle.lock();
+        if handle.is_some() || *self.compaction.stopped.lock() {
+            return Ok(());
+        }
+
+        let engine = Arc::downgrade(self);
+        let compaction = self.compaction.clone();
+        *handle = Some(thread::spawn(move || run_compaction(engine, compaction)));
+
+        Ok(())
+    }
+
+    /// 暂停后台 compaction, 正在进行的后台 merge 也会暂停, 直到恢复
+    pub fn pause_compaction(&self) {
+        self.compaction.paused.store(true, Ordering::SeqCst);
+    }
+
+    /// 恢复后台 compaction
+    pub fn resume_compaction(&self) {
+        self.compaction.paused.store(false, Ordering::SeqCst);
+        let _stopped = self.compaction.stopped.lock();
+        self.compaction.cond.notify_all();
+    }
+
+    /// 失效数据是否达到了触发 merge 的阈值
+    pub(crate) fn need_compaction(&self) -> bool {
+        let options = &self.compaction.options;
+        let dead_bytes = self.dead_bytes.read().clone();
+
+        let total: u64 = dead_bytes.values().sum();
+        if options.dead_bytes_threshold > 0 && total >= options.dead_bytes_threshold {
+            return true;
+        }
+
+        // 只考虑旧的数据文件, 活跃文件还在写入中
+        let older_files = self.older_files.read();
+        dead_bytes.iter().any(|(file_id, dead)| {
+            older_files.get(file_id).is_some_and(|data_file| {
+                let size = data_file
+                    .get_write_off()
+                    .saturating_sub(DATA_FILE_HEADER_SIZE);
+                size > 0 && *dead as f64 / size as f64 >= options.dead_ratio_threshold
+            })
+        })
+    }
+}
+
+// 后台线程, 按照配置的时间间隔检查是否需要 merge, Engine 被释放或者停止时退出
+fn run_compaction(engine: Weak<Engine>, compaction: Arc<Compaction>) {
+    loop {
+        {
+            let mut stopped = compaction.stopped.lock();
+            if !*stopped {
+                compaction
+                    .cond
+                    .wait_for(&mut stopped, compaction.options.check_interval);
+            }
+            if *stopped {
+                return;
+            }
+        }
+
+        if compaction.is_paused() {
+            continue;
+        }
+
+        let engine = match engine.upgrade() {
+            Some(engine) => engine,
+            None => return,
+        };
+        if engine.need_compaction() {
+            match engine.merge_with(true) {
+                Ok(()) | Err(Errors::MergeInProgress) | Err(Errors::MergeCancelled) => {}
+                Err(e) => error!("background compaction failed: {}", e),
+            }
+        }
+    }
+}
+
+impl Compaction {
+    pub(crate) fn new(options: CompactionOptions) -> Self {
+        let rate_limiter = RateLimiter::new(options.rate_limit_bytes_per_sec);
+        Self {
+            options,
+            paused: AtomicBool::new(false),
+            stopped: Mutex::new(false),
+            cond: Condvar::new(),
+            handle: Mutex::new(None),
+            rate_limiter: Mutex::new(rate_limiter),
+        }
+    }
+
+    fn is_paused(&self) -> bool {
+        self.paused.load(Ordering::SeqCst)
+    }
+
+    /// 停止后台线程并等待其退出
+    pub(crate) fn stop(&self) {
+        *self.stopped.lock() = true;
+        self.cond.notify_all();
+
+        // Engine 可能在后台线程中被释放, 此时不能等待自己退出
+        if let Some(handle) = self.handle.lock().take()
+            && handle.thread().id() != thread::current().id()
+            && handle.join().is_err()
+        {
+            error!("background compaction thread panicked");
+        }
+    }
+
+    /// 开始一次新的后台 merge 时重置限速器
+    pub(crate) fn reset_rate_limiter(&self) {
+        self.rate_limiter.lock().reset();
+    }
+
+    /// 后台 merge 每处理一条记录调用一次, 超过限速或者暂停时等待
+    /// 等待期间停止后台 compaction 时返回 false
+    pub(crate) fn throttle(&self, size: u64) -> bool {
+        let mut wait = self.rate_limiter.lock().consume(size);
+        let mut stopped = self.stopped.lock();
+        loop {
+            if *stopped {
+                return false;
+            }
+            if !self.is_paused() && wait.is_zero() {
+                return true;
+            }
+
+            if self.is_paused() {
+                self.cond.wait(&mut stopped);
+                // 暂停期间的时间不计入限速
+                self.rate_limiter.lock().reset();
+                wait = Duration::ZERO;
+            } else {
+                let deadline = Instant::now() + wait;
+                self.cond.wait_until(&mut stopped, deadline);
+                wait = deadline.saturating_duration_since(Instant::now());
+            }
+        }
+    }
+}
+
+impl RateLimiter {
+    fn new(bytes_per_sec: u64) -> Self {
+        Self {
+            bytes_per_sec,
+            start: Instant::now(),
+            consumed: 0,
+        }
+    }
+
+    fn reset(&mut self) {
+        self.start = Instant::now();
+        self.consumed = 0;
+    }
+
+    /// 记录处理的字节数, 返回需要等待的时间
+    fn consume(&mut self, size: u64) -> Duration {
+        if self.bytes_per_sec == 0 {
+            return Duration::ZERO;
+        }
+
+        self.consumed += size;
+        let expected = Duration::from_secs_f64(self.consumed as f64 / self.bytes_per_sec as f64);
+        expected.saturating_sub(self.start.elapsed())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Options;
+    use bytes::Bytes;
+    use std::{fs, path::PathBuf};
+
+    fn wait_until(timeout: Duration, f: impl Fn() -> bool) -> bool {
+        let deadline = Instant::now() + timeout;
+        while Instant::now() < deadline {
+            if f() {
+                return true;
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+        f()
+    }
+
+    #[test]
+    fn rate_limiter_should_work() {
+        let mut limiter = RateLimiter::new(0);
+        assert!(limiter.consume(u32::MAX as u64).is_zero());
+
+        let mut limiter = RateLimiter::new(1000);
+        assert!(limiter.consume(500) > Duration::from_millis(400));
+        limiter.reset();
+        assert!(limiter.consume(100) <= Duration::from_millis(100));
+    }
+
+    #[test]
+    fn background_compaction_should_merge_dead_data() {
+        let opts = Options {
+            dir_path: PathBuf::from("/tmp/bitcask-compaction"),
+            data_file_size: 1024,
+            compaction: CompactionOptions {
+                enabled: true,
+                check_interval: Duration::from_millis(20),
+                dead_ratio_threshold: 0.5,
+                dead_bytes_threshold: 0,
+                rate_limit_bytes_per_sec: 0,
+            },
+            ..Default::default()
+        };
+        let _ = fs::remove_dir_all(&opts.dir_path);
+
+        // 没有启用时不能启动后台 compaction
+        let disabled = Arc::new(
+            Engine::open(Options {
+                compaction: CompactionOptions {
+                    enabled: false,
+                    ..opts.compaction.clone()
+                },
+                ..opts.clone()
+            })
+            .expect("failed to open engine"),
+        );
+        assert_eq!(
+            disabled.start_compaction().err(),
+            Some(Errors::CompactionNotEnabled)
+        );
+        drop(disabled);
+
+        let engine = Arc::new(Engine::open(opts.clone()).expect("failed to open engine"));
+        engine.pause_compaction();
+        engine.start_compaction().unwrap();
+
+        for round in 0..3 {
+            for i in 0..50 {
+                engine
+                    .put(
+                        Bytes::from(format!("key-{}", i)),
+                        Bytes::from(format!("value-{}-{}", i, round)),
+                    )
+                    .unwrap();
+            }
+        }
+        assert!(engine.need_compaction());
+
+        // 暂停期间不会执行 merge
+        thread::sleep(Duration::from_millis(100));
+        assert!(engine.need_compaction());
+
+        // 恢复之后失效数据被清理
+        engine.resume_compaction();
+        assert!(wait_until(Duration::from_secs(5), || !engine.need_compaction()));
+        for i in 0..50 {
+            assert_eq!(
+                engine.get(Bytes::from(format!("key-{}", i))).unwrap(),
+                Bytes::from(format!("value-{}-2", i))
+            );
+        }
+
+        drop(engine);
+        fs::remove_dir_all(opts.dir_path).expect("failed to remove path");
+    }
+}
@@ -0,0 +1,90 @@
+use crate::{Errors, Result};
+use chacha20poly1305::{
+    AeadCore, KeyInit, XChaCha20Poly1305, XNonce,
+    aead::{Aead, OsRng},
+};
+use log::error;
+
+/// 加密密钥的长度
+pub const ENCRYPTION_KEY_SIZE: usize = 32;
+
+/// nonce 的长度, 每条记录随机生成
+const NONCE_SIZE: usize = 24;
+
+/// 基于 XChaCha20-Poly1305 的认证加密, 使用当前密钥加密, 解密时依次尝试当前密钥和旧的密钥
+pub(crate) struct Cipher {
+    // 第一个是当前的密钥, 其余是轮换之前的旧密钥
+    keys: Vec<XChaCha20Poly1305>,
+}
+
+impl Cipher {
+    pub(crate) fn new(
+        key: &[u8; ENCRYPTION_KEY_SIZE],
+        old_keys: &[[u8; ENCRYPTION_KEY_SIZE]],
+    ) -> Self {
+        let keys = std::iter::once(key)
+            .chain(old_keys.iter())
+            .map(|key| XChaCha20Poly1305::new(key.into()))
+            .collect();
+        Self { keys }
+    }
+
+    /// 加密数据, 返回 nonce 和密文
+    pub(crate) fn encrypt(&self, plaintext: &[u8]) -> Result<Vec<u8>> {
+        let nonce = XChaCha20Poly1305::generate_nonce(&mut OsRng);
+        match self.keys[0].encrypt(&nonce, plaintext) {
+            Ok(ciphertext) => {
+                let mut buf = Vec::with_capacity(NONCE_SIZE + ciphertext.len());
+                buf.extend_from_slice(&nonce);
+                buf.extend_from_slice(&ciphertext);
+                Ok(buf)
+            }
+            Err(e) => {
+                error!("failed to encrypt log record: {}", e);
+                Err(Errors::FailedToWriteToDataFile)
+            }
+        }
+    }
+
+    /// 解密数据, 所有的密钥都无法通过认证时说明密钥错误
+    pub(crate) fn decrypt(&self, data: &[u8]) -> Result<Vec<u8>> {
+        if data.len() < NONCE_SIZE {
+            return Err(Errors::InvalidLogRecordCrc);
+        }
+
+        let (nonce, ciphertext) = data.split_at(NONCE_SIZE);
+        let nonce = XNonce::from_slice(nonce);
+        self.keys
+            .iter()
+            .find_map(|key| key.decrypt(nonce, ciphertext).ok())
+            .ok_or(Errors::InvalidEncryptionKey)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cipher_encrypt_and_decrypt_should_work() {
+        let old_cipher = Cipher::new(&[1; ENCRYPTION_KEY_SIZE], &[]);
+        let cipher = Cipher::new(&[2; ENCRYPTION_KEY_SIZE], &[[1; ENCRYPTION_KEY_SIZE]]);
+
+        let data = cipher.encrypt(b"bitcask").unwrap();
+        assert_eq!(data.len(), NONCE_SIZE + 7 + 16);
+        assert_eq!(cipher.decrypt(&data).unwrap(), b"bitcask".to_vec());
+
+        // 相同的数据每次加密的结果不同
+        assert_ne!(cipher.encrypt(b"bitcask").unwrap(), data);
+
+        // 轮换之后仍然可以解密旧密钥加密的数据
+        let old_data = old_cipher.encrypt(b"old").unwrap();
+        assert_eq!(cipher.decrypt(&old_data).unwrap(), b"old".to_vec());
+
+        // 密钥错误
+        assert_eq!(
+            old_cipher.decrypt(&data).err(),
+            Some(Errors::InvalidEncryptionKey)
+        );
+    }
+}
@@ -0,0 +1,79 @@
+use crate::{Errors, Result, options::CompressionType};
+use log::error;
+
+/// zstd 的压缩级别
+const ZSTD_COMPRESSION_LEVEL: i32 = 3;
+
+impl TryFrom<u8> for CompressionType {
+    type Error = u8;
+
+    fn try_from(value: u8) -> std::result::Result<Self, Self::Error> {
+        match value {
+            0 => Ok(CompressionType::None),
+            1 => Ok(CompressionType::Lz4),
+            2 => Ok(CompressionType::Zstd),
+            v => Err(v),
+        }
+    }
+}
+
+/// 压缩 value, 压缩之后没有变小时保留原始数据, 返回实际使用的压缩类型
+pub(crate) fn compress(value: &[u8], compression: CompressionType) -> (CompressionType, Vec<u8>) {
+    let compressed = match compression {
+        CompressionType::None => None,
+        CompressionType::Lz4 => Some(lz4_flex::compress_prepend_size(value)),
+        CompressionType::Zstd => zstd::bulk::compress(value, ZSTD_COMPRESSION_LEVEL)
+            .inspect_err(|e| error!("failed to compress value with zstd: {}", e))
+            .ok(),
+    };
+
+    match compressed {
+        Some(compressed) if compressed.len() < value.len() => (compression, compressed),
+        _ => (CompressionType::None, value.to_vec()),
+    }
+}
+
+/// 根据记录头部的压缩类型解压 value
+pub(crate) fn decompress(value: Vec<u8>, compression: CompressionType) -> Result<Vec<u8>> {
+    match compression {
+        CompressionType::None => Ok(value),
+        CompressionType::Lz4 => lz4_flex::decompress_size_prepended(&value).map_err(|e| {
+            error!("failed to decompress value with lz4: {}", e);
+            Errors::FailedToDecompressValue
+        }),
+        CompressionType::Zstd => zstd::stream::decode_all(value.as_slice()).map_err(|e| {
+            error!("failed to decompress value with zstd: {}", e);
+            Errors::FailedToDecompressValue
+        }),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn compress_and_decompress_should_work() {
+        let value = "bitcask-value-".repeat(64).into_bytes();
+        for compression in [
+            CompressionType::None,
+            CompressionType::Lz4,
+            CompressionType::Zstd,
+        ] {
+            let (actual, compressed) = compress(&value, compression);
+            assert_eq!(actual, compression);
+            if compression != CompressionType::None {
+                assert!(compressed.len() < value.len());
+            }
+            assert_eq!(decompress(compressed, actual).unwrap(), value);
+
+            // 压缩之后没有变小时不压缩
+            let (actual, compressed) = compress(b"abc", compression);
+            assert_eq!(actual, CompressionType::None);
+            assert_eq!(compressed, b"abc".to_vec());
+        }
+
+        assert!(decompress(b"invalid".to_vec(), CompressionType::Zstd).is_err());
+        assert_eq!(CompressionType::try_from(3), Err(3));
+    }
+}
@@ -1,43 +1,364 @@
-use super::ReadLogRecord;
-use crate::{IoManger, Result};
+use super::{
+    LogRecord, LogRecordPos, LogRecordType, ReadLogRecord,
+    cipher::Cipher,
+    compression::decompress,
+    log_record::{
+        CRC_SIZE, NEVER_EXPIRE, NO_COMMIT_TIME, NON_BATCH_SEQ_NO, max_log_record_header_size,
+    },
+};
+use crate::{Errors, FileSystem, IoManger, MemoryIo, Result, options::CompressionType};
+use bytes::{Buf, BufMut, BytesMut};
 use parking_lot::RwLock;
-use std::{path::PathBuf, sync::Arc};
+use prost::{decode_length_delimiter, encode_length_delimiter, encoding::decode_varint};
+use std::{
+    path::{Path, PathBuf},
+    sync::Arc,
+};
 
 pub const DATA_FILE_SUFFIX: &str = ".data";
 
-#[allow(unused)]
+/// hint 索引文件的后缀, 和数据文件一一对应
+pub const HINT_FILE_SUFFIX: &str = ".hint";
+
+/// merge 完成标识文件的名称
+pub const MERGE_FINISHED_FILE_NAME: &str = "merge-finished";
+
+/// 序列号文件的名称, 关闭数据库时保存当前的序列号
+pub const SEQ_NO_FILE_NAME: &str = "seq-no";
+
+/// 备份清单文件的名称, 记录备份时每个数据文件的大小
+pub const BACKUP_MANIFEST_FILE_NAME: &str = "backup-manifest";
+
+/// 数据文件头部的魔数
+const DATA_FILE_MAGIC: &[u8; 4] = b"BCSK";
+
+/// 当前数据文件的格式版本, 记录格式发生变化时递增
+/// 1: 初始格式
+/// 2: 记录头部增加批量写入的 seq no
+/// 3: 记录头部增加过期时间
+/// 4: 记录头部增加 value 的压缩类型
+/// 5: 支持加密的记录
+/// 6: merge 为快照保留的旧版本使用单独的记录类型
+/// 7: 记录头部增加提交时间
+pub const DATA_FILE_FORMAT_VERSION: u32 = 7;
+
+/// 加密记录的类型, 和 LogRecordType 区分开
+const ENCRYPTED_RECORD_TYPE: u8 = 0x80;
+
+/// 加密记录头部的最大长度, crc + type + 密文长度
+const MAX_ENCRYPTED_HEADER_SIZE: usize = CRC_SIZE + 1 + 5;
+
+/// 数据文件头部的长度, 魔数 + 格式版本, 第一条记录从该位置开始
+pub const DATA_FILE_HEADER_SIZE: u64 = 8;
+
+/// 跳过损坏的数据时每次读取到内存中的大小
+const SCAN_CHUNK_SIZE: usize = 64 * 1024;
+
 pub struct DataFile {
     // 数据文件id
     file_id: Arc<RwLock<u32>>,
     // 当前写偏移, 记录改数据文件写到哪个位置
     write_off: Arc<RwLock<u64>>,
+    // 数据文件的格式版本
+    version: u32,
     // IO 管理接口
     io_manager: Box<dyn IoManger>,
+    // 记录的加密, 为空时写入明文的记录
+    cipher: Option<Arc<Cipher>>,
 }
 
-#[allow(unused)]
 impl DataFile {
-    pub fn new(dir_path: PathBuf, file_id: u32) -> Result<Self> {
-        todo!()
+    /// 创建一个新的数据文件
+    pub fn new(fs: &dyn FileSystem, dir_path: PathBuf, file_id: u32) -> Result<Self> {
+        // 根据 dir_path 和 file_id 构造出完整的文件名称
+        let file_name = get_data_file_name(&dir_path, file_id);
+        // 初始化 io manager
+        let io_manager = fs.create(&file_name)?;
+
+        Self::with_io_manager(file_id, io_manager)
+    }
+
+    /// 打开一个已存在的数据文件, 保留其中的数据
+    pub fn open(fs: &dyn FileSystem, dir_path: PathBuf, file_id: u32) -> Result<Self> {
+        let file_name = get_data_file_name(&dir_path, file_id);
+        let io_manager = fs.open(&file_name)?;
+
+        Self::with_io_manager(file_id, io_manager)
+    }
+
+    /// 使用 mmap 只读打开一个已存在的数据文件, 只能用于不会再写入的旧数据文件
+    /// 空文件还没有写入头部, 使用标准文件 IO 打开
+    pub fn open_mmap(fs: &dyn FileSystem, dir_path: PathBuf, file_id: u32) -> Result<Self> {
+        let file_name = get_data_file_name(&dir_path, file_id);
+        let io_manager = fs.open_mmap(&file_name)?;
+        if io_manager.size()? == 0 {
+            return Self::open(fs, dir_path, file_id);
+        }
+
+        Self::with_io_manager(file_id, io_manager)
+    }
+
+    /// 打开写入头部时中断的数据文件, 清空文件中不完整的头部并重新写入, 返回数据文件以及丢弃的数据大小
+    /// 文件中的数据不是当前格式的头部的一部分时返回 InvalidDataFileHeader
+    pub(crate) fn open_torn_header(
+        fs: &dyn FileSystem,
+        dir_path: PathBuf,
+        file_id: u32,
+    ) -> Result<(Self, u64)> {
+        let file_name = get_data_file_name(&dir_path, file_id);
+        let io_manager = fs.open(&file_name)?;
+        let file_size = io_manager.size()?;
+        let header = data_file_header();
+        if file_size >= DATA_FILE_HEADER_SIZE {
+            return Err(Errors::InvalidDataFileHeader);
+        }
+        let mut buf = vec![0u8; file_size as usize];
+        let n = io_manager.read(&mut buf, 0)?;
+        if buf[..n] != header[..n] {
+            return Err(Errors::InvalidDataFileHeader);
+        }
+
+        io_manager.truncate(0)?;
+        let data_file = Self::with_io_manager(file_id, io_manager)?;
+        data_file.sync()?;
+        Ok((data_file, file_size))
+    }
+
+    /// 创建 hint 索引文件, 存储 key 以及对应数据的位置信息
+    pub fn new_hint_file(fs: &dyn FileSystem, dir_path: PathBuf, file_id: u32) -> Result<Self> {
+        let file_name = get_hint_file_name(&dir_path, file_id);
+        let io_manager = fs.create(&file_name)?;
+
+        Self::with_io_manager(file_id, io_manager)
+    }
+
+    /// 打开已存在的 hint 索引文件
+    pub fn open_hint_file(fs: &dyn FileSystem, dir_path: PathBuf, file_id: u32) -> Result<Self> {
+        let file_name = get_hint_file_name(&dir_path, file_id);
+        let io_manager = fs.open(&file_name)?;
+
+        Self::with_io_manager(file_id, io_manager)
+    }
+
+    /// 写入一条 hint 记录
+    pub fn write_hint_record(&self, key: Vec<u8>, pos: LogRecordPos) -> Result<()> {
+        let mut hint_record = LogRecord::new(key, pos.encode());
+        self.write(&self.encode_log_record(&mut hint_record, CompressionType::None)?)?;
+        Ok(())
+    }
+
+    /// 创建 merge 完成标识文件
+    pub fn new_merge_fin_file(fs: &dyn FileSystem, dir_path: PathBuf) -> Result<Self> {
+        let file_name = dir_path.join(MERGE_FINISHED_FILE_NAME);
+        let io_manager = fs.create(&file_name)?;
+
+        Self::with_io_manager(0, io_manager)
+    }
+
+    /// 打开已存在的 merge 完成标识文件
+    pub fn open_merge_fin_file(fs: &dyn FileSystem, dir_path: PathBuf) -> Result<Self> {
+        let file_name = dir_path.join(MERGE_FINISHED_FILE_NAME);
+        let io_manager = fs.open(&file_name)?;
+
+        Self::with_io_manager(0, io_manager)
+    }
+
+    /// 创建序列号文件
+    pub fn new_seq_no_file(fs: &dyn FileSystem, dir_path: PathBuf) -> Result<Self> {
+        let file_name = dir_path.join(SEQ_NO_FILE_NAME);
+        let io_manager = fs.create(&file_name)?;
+
+        Self::with_io_manager(0, io_manager)
+    }
+
+    /// 打开已存在的序列号文件
+    pub fn open_seq_no_file(fs: &dyn FileSystem, dir_path: PathBuf) -> Result<Self> {
+        let file_name = dir_path.join(SEQ_NO_FILE_NAME);
+        let io_manager = fs.open(&file_name)?;
+
+        Self::with_io_manager(0, io_manager)
+    }
+
+    /// 创建备份清单文件
+    pub fn new_backup_manifest_file(fs: &dyn FileSystem, dir_path: PathBuf) -> Result<Self> {
+        let file_name = dir_path.join(BACKUP_MANIFEST_FILE_NAME);
+        let io_manager = fs.create(&file_name)?;
+
+        Self::with_io_manager(0, io_manager)
+    }
+
+    /// 打开已存在的备份清单文件
+    pub fn open_backup_manifest_file(fs: &dyn FileSystem, dir_path: PathBuf) -> Result<Self> {
+        let file_name = dir_path.join(BACKUP_MANIFEST_FILE_NAME);
+        let io_manager = fs.open(&file_name)?;
+
+        Self::with_io_manager(0, io_manager)
+    }
+
+    /// 基于给定的 IO 管理接口构建数据文件, 新文件写入头部, 已有文件校验头部
+    /// 可以用于内存 IO 或者故障注入 IO 等不对应磁盘文件的数据文件
+    pub fn with_io_manager(file_id: u32, io_manager: Box<dyn IoManger>) -> Result<Self> {
+        let file_size = io_manager.size()?;
+        let version = if file_size == 0 {
+            io_manager.write(&data_file_header())?;
+            DATA_FILE_FORMAT_VERSION
+        } else {
+            read_data_file_header(io_manager.as_ref())?
+        };
+
+        Ok(Self {
+            file_id: Arc::new(RwLock::new(file_id)),
+            write_off: Arc::new(RwLock::new(io_manager.size()?)),
+            version,
+            io_manager,
+            cipher: None,
+        })
+    }
+
+    /// 设置记录的加密, 之后写入的记录使用当前密钥加密, 读取时可以解密当前以及旧密钥加密的记录
+    pub(crate) fn with_cipher(mut self, cipher: Option<Arc<Cipher>>) -> Self {
+        self.cipher = cipher;
+        self
+    }
+
+    /// 对记录进行编码, 设置了加密时将编码之后的记录加密
+    ///
+    /// +----------+----------+--------------------+---------------+
+    /// | crc 校验 | type 类型 | 密文长度            | nonce + 密文  |
+    /// +----------+----------+--------------------+---------------+
+    ///   4 字节     1 字节      变长 (最大 5 字节)     变长
+    ///
+    /// 加密记录的 type 固定为 ENCRYPTED_RECORD_TYPE, 解密之后是一条完整的明文记录
+    pub(crate) fn encode_log_record(
+        &self,
+        log_record: &mut LogRecord,
+        compression: CompressionType,
+    ) -> Result<Vec<u8>> {
+        let enc_record = log_record.encode_with_compression(compression);
+        let cipher = match &self.cipher {
+            Some(cipher) => cipher,
+            None => return Ok(enc_record),
+        };
+
+        let payload = cipher.encrypt(&enc_record)?;
+        let mut buf = BytesMut::with_capacity(MAX_ENCRYPTED_HEADER_SIZE + payload.len());
+        buf.put_u32(0);
+        buf.put_u8(ENCRYPTED_RECORD_TYPE);
+        encode_length_delimiter(payload.len(), &mut buf).unwrap();
+        buf.extend_from_slice(&payload);
+
+        let crc = crc32fast::hash(&buf[CRC_SIZE..]);
+        buf[..CRC_SIZE].copy_from_slice(&crc.to_be_bytes());
+
+        Ok(buf.to_vec())
     }
 
     pub fn get_write_off(&self) -> u64 {
         *self.write_off.read()
     }
 
+    /// 数据文件在存储上的实际大小, 可能包含末尾写入不完整的数据
+    pub(crate) fn file_size(&self) -> Result<u64> {
+        self.io_manager.size()
+    }
+
     pub fn get_file_id(&self) -> u32 {
         *self.file_id.read()
     }
 
+    pub fn get_version(&self) -> u32 {
+        self.version
+    }
+
+    /// 根据 offset 从数据文件中读取 LogRecord
     pub fn read_log_record(&self, offset: u64) -> Result<ReadLogRecord> {
-        todo!()
+        // 格式版本 5 之后的数据文件中可能有加密的记录
+        if self.version >= 5
+            && let Some(payload) = read_encrypted_payload(self.io_manager.as_ref(), offset)?
+        {
+            let (payload, size) = payload;
+            let cipher = self.cipher.as_ref().ok_or(Errors::InvalidEncryptionKey)?;
+
+            // 解密之后是一条完整的明文记录, 大小以加密记录为准
+            let io_manager = MemoryIo::new();
+            io_manager.write(&cipher.decrypt(&payload)?)?;
+            let mut read_record = decode_log_record(&io_manager, self.version, 0)?;
+            read_record.size = size;
+            return Ok(read_record);
+        }
+
+        decode_log_record(self.io_manager.as_ref(), self.version, offset)
     }
 
     pub fn write(&self, buf: &[u8]) -> Result<usize> {
-        todo!()
+        let mut write_off = self.write_off.write();
+        let n_bytes = match self.io_manager.write(buf) {
+            Ok(n_bytes) => n_bytes,
+            Err(e) => {
+                // 写入失败时可能已经写入了部分数据, 截断回写入之前的位置, 否则之后的记录会接在不完整的数据后面
+                // 无法截断时以文件的实际大小为准, 不完整的数据在重新打开时按损坏的记录处理
+                if self.io_manager.truncate(*write_off).is_err()
+                    && let Ok(size) = self.io_manager.size()
+                {
+                    *write_off = size;
+                }
+                return Err(e);
+            }
+        };
+        // 更新 write_off 字段
+        *write_off += n_bytes as u64;
+
+        Ok(n_bytes)
     }
 
     pub fn sync(&self) -> Result<()> {
+        self.io_manager.sync()
+    }
+
+    /// 从 offset 开始查找下一条可以完整读取的记录, 没有找到时返回文件的大小
+    ///
+    /// 损坏的数据按块读取到内存中逐个字节检查, 只有 header 中的类型和长度都合法的位置才会完整读取并校验 crc
+    pub(crate) fn next_valid_record(&self, mut offset: u64) -> Result<u64> {
+        let file_size = self.io_manager.size()?;
+        let header_size = max_log_record_header_size().max(MAX_ENCRYPTED_HEADER_SIZE);
+        let mut buf = vec![0u8; SCAN_CHUNK_SIZE + header_size];
+        while offset < file_size {
+            let len = (buf.len() as u64).min(file_size - offset) as usize;
+            let mut n = 0;
+            while n < len {
+                match self.io_manager.read(&mut buf[n..len], offset + n as u64)? {
+                    0 => break,
+                    read => n += read,
+                }
+            }
+
+            // 块的末尾多读取了一个 header 的长度, 除了文件的末尾每个检查的位置都有完整的 header
+            let scan_len = match offset + (n as u64) < file_size {
+                true if n > header_size => n - header_size,
+                _ => n,
+            };
+            for i in 0..scan_len {
+                let pos = offset + i as u64;
+                if is_plausible_record(&buf[i..n], self.version, file_size - pos)
+                    && self.read_log_record(pos).is_ok()
+                {
+                    return Ok(pos);
+                }
+            }
+            if scan_len == 0 {
+                break;
+            }
+            offset += scan_len as u64;
+        }
+
+        Ok(file_size)
+    }
+
+    /// 将数据文件截断到 len, 丢弃之后写入的数据
+    pub(crate) fn truncate(&self, len: u64) -> Result<()> {
+        let mut write_off = self.write_off.write();
+        self.io_manager.truncate(len)?;
+        *write_off = len;
         Ok(())
     }
 
@@ -46,3 +367,542 @@ impl DataFile {
         *write_guard = offset;
     }
 }
+
+/// 获取数据文件名称
+pub(crate) fn get_data_file_name(dir_path: &Path, file_id: u32) -> PathBuf {
+    let name = format!("{:09}", file_id) + DATA_FILE_SUFFIX;
+    dir_path.join(name)
+}
+
+/// 获取 hint 索引文件名称
+pub(crate) fn get_hint_file_name(dir_path: &Path, file_id: u32) -> PathBuf {
+    let name = format!("{:09}", file_id) + HINT_FILE_SUFFIX;
+    dir_path.join(name)
+}
+
+/// 根据 offset 从 IO 中读取一条明文的 LogRecord, version 是数据的格式版本
+fn decode_log_record(
+    io_manager: &dyn IoManger,
+    version: u32,
+    offset: u64,
+) -> Result<ReadLogRecord> {
+    let file_size = io_manager.size()?;
+    if offset >= file_size {
+        return Err(Errors::ReadDataFileEOF);
+    }
+
+    // 先读取出 header 部分的数据, 文件末尾可能不足 header 的最大长度
+    let header_len = (max_log_record_header_size() as u64).min(file_size - offset) as usize;
+    let mut header_buf = BytesMut::zeroed(header_len);
+    io_manager.read(&mut header_buf, offset)?;
+
+    // crc 和 type 都读不完整, 说明最后一次写入被截断
+    if header_len < CRC_SIZE + 1 {
+        return Err(Errors::IncompleteLogRecord);
+    }
+
+    let mut header = &header_buf[..];
+    let crc = header.get_u32();
+    let rec_type = header.get_u8();
+
+    // 格式版本 4 之前的数据文件中没有压缩类型
+    let compression = match version {
+        1..=3 => CompressionType::None as u8,
+        _ if !header.has_remaining() => return Err(Errors::IncompleteLogRecord),
+        _ => header.get_u8(),
+    };
+
+    // 解码变长字段失败时, header 被文件末尾截断说明写入不完整, 否则说明数据损坏
+    let truncated = header_len < max_log_record_header_size();
+    let decode_err = |_| match truncated {
+        true => Errors::IncompleteLogRecord,
+        false => Errors::InvalidLogRecordCrc,
+    };
+
+    // 格式版本 1 的数据文件中没有 seq no
+    let seq_no = match version {
+        1 => NON_BATCH_SEQ_NO,
+        _ => decode_varint(&mut header).map_err(decode_err)?,
+    };
+
+    // 格式版本 3 之前的数据文件中没有过期时间
+    let expire_at = match version {
+        1 | 2 => NEVER_EXPIRE,
+        _ => decode_varint(&mut header).map_err(decode_err)?,
+    };
+
+    // 格式版本 7 之前的数据文件中没有提交时间
+    let commit_at = match version {
+        1..=6 => NO_COMMIT_TIME,
+        _ => decode_varint(&mut header).map_err(decode_err)?,
+    };
+
+    // 解码 key 和 value 的长度
+    let key_size = decode_length_delimiter(&mut header).map_err(decode_err)?;
+    let value_size = decode_length_delimiter(&mut header).map_err(decode_err)?;
+
+    // 获取实际的 header 大小
+    let actual_header_size = header_len - header.remaining();
+    let record_size = (actual_header_size + key_size + value_size) as u64;
+    if offset + record_size > file_size {
+        return Err(Errors::IncompleteLogRecord);
+    }
+
+    // 读取实际的 key 和 value 数据
+    let mut kv_buf = BytesMut::zeroed(key_size + value_size);
+    io_manager.read(&mut kv_buf, offset + actual_header_size as u64)?;
+
+    // 校验 crc, crc 覆盖 crc 之后的 header 以及 key/value
+    let mut hasher = crc32fast::Hasher::new();
+    hasher.update(&header_buf[CRC_SIZE..actual_header_size]);
+    hasher.update(&kv_buf);
+    if hasher.finalize() != crc {
+        return Err(Errors::InvalidLogRecordCrc);
+    }
+
+    let rec_type = LogRecordType::try_from(rec_type).map_err(|_| Errors::InvalidLogRecordCrc)?;
+    let compression =
+        CompressionType::try_from(compression).map_err(Errors::UnsupportedCompressionType)?;
+    let value = decompress(kv_buf.split_off(key_size).to_vec(), compression)?;
+    let record = LogRecord {
+        key: kv_buf.to_vec(),
+        value,
+        rec_type,
+        seq_no,
+        expire_at,
+        commit_at,
+    };
+
+    Ok(ReadLogRecord {
+        record,
+        size: record_size,
+    })
+}
+
+/// buf 的开头是否可能是一条记录, 只检查 header 中的类型和长度, 不校验 crc
+/// remaining 是该位置到文件末尾的长度, 记录不能超过文件的末尾
+fn is_plausible_record(buf: &[u8], version: u32, remaining: u64) -> bool {
+    if buf.len() < CRC_SIZE + 1 {
+        return false;
+    }
+    let mut header = &buf[CRC_SIZE..];
+    let rec_type = header.get_u8();
+
+    let payload_size = if version >= 5 && rec_type == ENCRYPTED_RECORD_TYPE {
+        match decode_length_delimiter(&mut header) {
+            Ok(payload_size) => payload_size as u64,
+            Err(_) => return false,
+        }
+    } else {
+        if LogRecordType::try_from(rec_type).is_err() {
+            return false;
+        }
+        if version >= 4
+            && (!header.has_remaining() || CompressionType::try_from(header.get_u8()).is_err())
+        {
+            return false;
+        }
+        if (version >= 2 && decode_varint(&mut header).is_err())
+            || (version >= 3 && decode_varint(&mut header).is_err())
+            || (version >= 7 && decode_varint(&mut header).is_err())
+        {
+            return false;
+        }
+        match (
+            decode_length_delimiter(&mut header),
+            decode_length_delimiter(&mut header),
+        ) {
+            (Ok(key_size), Ok(value_size)) => key_size as u64 + value_size as u64,
+            _ => return false,
+        }
+    };
+
+    let header_size = (buf.len() - header.remaining()) as u64;
+    header_size + payload_size <= remaining
+}
+
+/// 读取 offset 处的加密记录, 返回 nonce + 密文以及整条记录的大小, 不是加密记录时返回 None
+fn read_encrypted_payload(
+    io_manager: &dyn IoManger,
+    offset: u64,
+) -> Result<Option<(Vec<u8>, u64)>> {
+    let file_size = io_manager.size()?;
+    if offset >= file_size {
+        return Err(Errors::ReadDataFileEOF);
+    }
+
+    let header_len = (MAX_ENCRYPTED_HEADER_SIZE as u64).min(file_size - offset) as usize;
+    let mut header_buf = BytesMut::zeroed(header_len);
+    io_manager.read(&mut header_buf, offset)?;
+    if header_len < CRC_SIZE + 1 || header_buf[CRC_SIZE] != ENCRYPTED_RECORD_TYPE {
+        return Ok(None);
+    }
+
+    let mut header = &header_buf[CRC_SIZE + 1..];
+    let payload_size = decode_length_delimiter(&mut header).map_err(|_| {
+        match header_len < MAX_ENCRYPTED_HEADER_SIZE {
+            true => Errors::IncompleteLogRecord,
+            false => Errors::InvalidLogRecordCrc,
+        }
+    })?;
+
+    let actual_header_size = header_len - header.remaining();
+    let record_size = (actual_header_size + payload_size) as u64;
+    if offset + record_size > file_size {
+        return Err(Errors::IncompleteLogRecord);
+    }
+
+    let mut payload = BytesMut::zeroed(payload_size);
+    io_manager.read(&mut payload, offset + actual_header_size as u64)?;
+
+    // 校验 crc, 区分数据损坏和密钥错误
+    let mut hasher = crc32fast::Hasher::new();
+    hasher.update(&header_buf[CRC_SIZE..actual_header_size]);
+    hasher.update(&payload);
+    if hasher.finalize() != (&header_buf[..CRC_SIZE]).get_u32() {
+        return Err(Errors::InvalidLogRecordCrc);
+    }
+
+    Ok(Some((payload.to_vec(), record_size)))
+}
+
+/// 新的数据文件的头部, 魔数 + 当前的格式版本
+fn data_file_header() -> BytesMut {
+    let mut header = BytesMut::with_capacity(DATA_FILE_HEADER_SIZE as usize);
+    header.extend_from_slice(DATA_FILE_MAGIC);
+    header.put_u32(DATA_FILE_FORMAT_VERSION);
+    header
+}
+
+/// 读取并校验数据文件头部, 返回文件的格式版本
+fn read_data_file_header(io_manager: &dyn IoManger) -> Result<u32> {
+    let mut header = BytesMut::zeroed(DATA_FILE_HEADER_SIZE as usize);
+    let n = io_manager.read(&mut header, 0)?;
+    if n < DATA_FILE_HEADER_SIZE as usize || &header[..DATA_FILE_MAGIC.len()] != DATA_FILE_MAGIC {
+        return Err(Errors::InvalidDataFileHeader);
+    }
+
+    header.advance(DATA_FILE_MAGIC.len());
+    let version = header.get_u32();
+    if version == 0 || version > DATA_FILE_FORMAT_VERSION {
+        return Err(Errors::UnsupportedDataFileVersion(version));
+    }
+
+    Ok(version)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::StdFileSystem;
+    use std::{
+        fs,
+        sync::atomic::{AtomicUsize, Ordering},
+    };
+
+    #[test]
+    fn data_file_new_should_work() {
+        let dir_path = PathBuf::from("/tmp/bitcask-data-file-new");
+        fs::create_dir_all(&dir_path).expect("create dir should work");
+
+        let data_file = DataFile::new(&StdFileSystem, dir_path.clone(), 0);
+        assert!(data_file.is_ok());
+
+        let data_file = data_file.unwrap();
+        assert_eq!(data_file.get_file_id(), 0);
+        assert_eq!(data_file.get_version(), DATA_FILE_FORMAT_VERSION);
+        assert_eq!(data_file.get_write_off(), DATA_FILE_HEADER_SIZE);
+
+        fs::remove_dir_all(dir_path).expect("remove dir should work");
+    }
+
+    #[test]
+    fn data_file_size_error_should_not_overwrite_header() {
+        // 无法获取文件大小的 IO, 写入时记录写入的数据
+        struct BrokenSizeIo(MemoryIo);
+        impl IoManger for BrokenSizeIo {
+            fn read(&self, buf: &mut [u8], offset: u64) -> Result<usize> {
+                self.0.read(buf, offset)
+            }
+            fn write(&self, buf: &[u8]) -> Result<usize> {
+                self.0.write(buf)
+            }
+            fn sync(&self) -> Result<()> {
+                self.0.sync()
+            }
+            fn size(&self) -> Result<u64> {
+                Err(Errors::FailedToReadFromDataFile)
+            }
+            fn truncate(&self, size: u64) -> Result<()> {
+                self.0.truncate(size)
+            }
+        }
+
+        let memory_io = MemoryIo::new();
+        let res = DataFile::with_io_manager(0, Box::new(BrokenSizeIo(memory_io.clone())));
+        assert_eq!(res.err(), Some(Errors::FailedToReadFromDataFile));
+        // 不会当作空文件写入新的头部
+        assert_eq!(memory_io.size().unwrap(), 0);
+    }
+
+    #[test]
+    fn data_file_open_should_work() {
+        let dir_path = PathBuf::from("/tmp/bitcask-data-file-open");
+        fs::create_dir_all(&dir_path).expect("create dir should work");
+
+        // 打开不存在的数据文件
+        assert!(DataFile::open(&StdFileSystem, dir_path.clone(), 1).is_err());
+
+        let data_file = DataFile::new(&StdFileSystem, dir_path.clone(), 1).unwrap();
+        let mut rec = LogRecord::new("name".as_bytes().to_vec(), "bitcask".as_bytes().to_vec());
+        let enc = rec.encode();
+        data_file.write(&enc).unwrap();
+        drop(data_file);
+
+        // 已存在的数据文件不能再次创建
+        assert!(DataFile::new(&StdFileSystem, dir_path.clone(), 1).is_err());
+
+        // 重新打开后数据仍然存在
+        let data_file = DataFile::open(&StdFileSystem, dir_path.clone(), 1).unwrap();
+        assert_eq!(
+            data_file.get_write_off(),
+            DATA_FILE_HEADER_SIZE + enc.len() as u64
+        );
+        let res = data_file.read_log_record(DATA_FILE_HEADER_SIZE).unwrap();
+        assert_eq!(res.record.value, rec.value);
+
+        fs::remove_dir_all(dir_path).expect("remove dir should work");
+    }
+
+    #[test]
+    fn data_file_write_and_sync_should_work() {
+        let dir_path = PathBuf::from("/tmp/bitcask-data-file-write");
+        fs::create_dir_all(&dir_path).expect("create dir should work");
+
+        let data_file = DataFile::new(&StdFileSystem, dir_path.clone(), 100).unwrap();
+
+        let res = data_file.write("aaa".as_bytes());
+        assert_eq!(res.ok().unwrap(), 3);
+        assert_eq!(data_file.get_write_off(), DATA_FILE_HEADER_SIZE + 3);
+
+        assert!(data_file.sync().is_ok());
+
+        fs::remove_dir_all(dir_path).expect("remove dir should work");
+    }
+
+    #[test]
+    fn data_file_read_log_record_should_work() {
+        let dir_path = PathBuf::from("/tmp/bitcask-data-file-read");
+        fs::create_dir_all(&dir_path).expect("create dir should work");
+
+        let data_file = DataFile::new(&StdFileSystem, dir_path.clone(), 700).unwrap();
+
+        // 从空文件中读取
+        let res = data_file.read_log_record(DATA_FILE_HEADER_SIZE);
+        assert_eq!(res.err().unwrap(), Errors::ReadDataFileEOF);
+
+        // 写入一条正常的记录
+        let mut rec1 = LogRecord::new("name".as_bytes().to_vec(), "bitcask".as_bytes().to_vec());
+        let enc1 = rec1.encode();
+        data_file.write(&enc1).unwrap();
+
+        let res = data_file.read_log_record(DATA_FILE_HEADER_SIZE).unwrap();
+        assert_eq!(res.record.key, rec1.key);
+        assert_eq!(res.record.value, rec1.value);
+        assert_eq!(res.record.rec_type, LogRecordType::NORMAL);
+        assert_eq!(res.size, enc1.len() as u64);
+
+        // 写入一条删除的记录
+        let mut rec2 = LogRecord {
+            key: "name".as_bytes().to_vec(),
+            value: Default::default(),
+            rec_type: LogRecordType::DELETED,
+            seq_no: NON_BATCH_SEQ_NO,
+            expire_at: NEVER_EXPIRE,
+            commit_at: NO_COMMIT_TIME,
+        };
+        let enc2 = rec2.encode();
+        data_file.write(&enc2).unwrap();
+
+        let offset = DATA_FILE_HEADER_SIZE + enc1.len() as u64;
+        let res = data_file.read_log_record(offset).unwrap();
+        assert_eq!(res.record.key, rec2.key);
+        assert!(res.record.value.is_empty());
+        assert_eq!(res.record.rec_type, LogRecordType::DELETED);
+
+        // 读取到文件末尾
+        let res = data_file.read_log_record(offset + enc2.len() as u64);
+        assert_eq!(res.err().unwrap(), Errors::ReadDataFileEOF);
+
+        fs::remove_dir_all(dir_path).expect("remove dir should work");
+    }
+
+    #[test]
+    fn data_file_read_corrupted_log_record_should_fail() {
+        let dir_path = PathBuf::from("/tmp/bitcask-data-file-corrupted");
+        fs::create_dir_all(&dir_path).expect("create dir should work");
+
+        let data_file = DataFile::new(&StdFileSystem, dir_path.clone(), 0).unwrap();
+
+        // value 中的字节被篡改
+        let mut rec = LogRecord::new("name".as_bytes().to_vec(), "bitcask".as_bytes().to_vec());
+        let mut enc = rec.encode();
+        let last = enc.len() - 1;
+        enc[last] ^= 0xff;
+        data_file.write(&enc).unwrap();
+
+        let res = data_file.read_log_record(DATA_FILE_HEADER_SIZE);
+        assert_eq!(res.err().unwrap(), Errors::InvalidLogRecordCrc);
+
+        // 最后一条记录只写入了一部分
+        let offset = data_file.get_write_off();
+        let enc = rec.encode();
+        data_file.write(&enc[..enc.len() - 3]).unwrap();
+
+        let res = data_file.read_log_record(offset);
+        assert_eq!(res.err().unwrap(), Errors::IncompleteLogRecord);
+
+        fs::remove_dir_all(dir_path).expect("remove dir should work");
+    }
+
+    #[test]
+    fn data_file_next_valid_record_should_scan_in_chunks() {
+        // 统计读取次数的 IO
+        struct CountingIo(MemoryIo, Arc<AtomicUsize>);
+        impl IoManger for CountingIo {
+            fn read(&self, buf: &mut [u8], offset: u64) -> Result<usize> {
+                self.1.fetch_add(1, Ordering::SeqCst);
+                self.0.read(buf, offset)
+            }
+            fn write(&self, buf: &[u8]) -> Result<usize> {
+                self.0.write(buf)
+            }
+            fn sync(&self) -> Result<()> {
+                self.0.sync()
+            }
+            fn size(&self) -> Result<u64> {
+                self.0.size()
+            }
+            fn truncate(&self, size: u64) -> Result<()> {
+                self.0.truncate(size)
+            }
+        }
+
+        let reads = Arc::new(AtomicUsize::new(0));
+        let io_manager = CountingIo(MemoryIo::new(), reads.clone());
+        let data_file = DataFile::with_io_manager(0, Box::new(io_manager)).unwrap();
+        let enc = LogRecord::new(b"a".to_vec(), b"value".to_vec()).encode();
+        data_file.write(&enc).unwrap();
+
+        // 跨越多个块的损坏数据, 之后是一条完整的记录
+        let corrupted_off = data_file.get_write_off();
+        let corrupted: Vec<u8> = (0..200 * 1024).map(|i| (i % 7) as u8 * 40).collect();
+        data_file.write(&corrupted).unwrap();
+        let next_off = data_file.get_write_off();
+        data_file.write(&enc).unwrap();
+
+        reads.store(0, Ordering::SeqCst);
+        assert_eq!(
+            data_file.next_valid_record(corrupted_off).unwrap(),
+            next_off
+        );
+        // 按块读取, 不会对每个字节单独读取
+        assert!(reads.load(Ordering::SeqCst) < 100);
+
+        // 没有有效的记录时返回文件的大小
+        let file_size = data_file.file_size().unwrap();
+        assert_eq!(
+            data_file.next_valid_record(next_off + 1).unwrap(),
+            file_size
+        );
+    }
+
+    #[test]
+    fn data_file_read_version_1_should_work() {
+        let dir_path = PathBuf::from("/tmp/bitcask-data-file-v1");
+        fs::create_dir_all(&dir_path).expect("create dir should work");
+
+        // 手动构造一个格式版本 1 的数据文件, 记录头部中没有 seq no
+        let mut buf = BytesMut::new();
+        buf.extend_from_slice(DATA_FILE_MAGIC);
+        buf.put_u32(1);
+        let mut record = BytesMut::new();
+        record.put_u8(LogRecordType::NORMAL as u8);
+        prost::encode_length_delimiter(4, &mut record).unwrap();
+        prost::encode_length_delimiter(7, &mut record).unwrap();
+        record.extend_from_slice(b"namebitcask");
+        buf.put_u32(crc32fast::hash(&record));
+        buf.extend_from_slice(&record);
+        fs::write(get_data_file_name(&dir_path, 0), &buf).unwrap();
+
+        let data_file = DataFile::open(&StdFileSystem, dir_path.clone(), 0).unwrap();
+        assert_eq!(data_file.get_version(), 1);
+
+        let res = data_file.read_log_record(DATA_FILE_HEADER_SIZE).unwrap();
+        assert_eq!(res.record.key, b"name".to_vec());
+        assert_eq!(res.record.value, b"bitcask".to_vec());
+        assert_eq!(res.record.seq_no, NON_BATCH_SEQ_NO);
+        assert_eq!(res.size, 4 + 1 + 1 + 1 + 11);
+
+        fs::remove_dir_all(dir_path).expect("remove dir should work");
+    }
+
+    #[test]
+    fn data_file_read_encrypted_log_record_should_work() {
+        let dir_path = PathBuf::from("/tmp/bitcask-data-file-encrypted");
+        fs::create_dir_all(&dir_path).expect("create dir should work");
+
+        let cipher = Arc::new(Cipher::new(&[1; 32], &[]));
+        let data_file = DataFile::new(&StdFileSystem, dir_path.clone(), 0)
+            .unwrap()
+            .with_cipher(Some(cipher.clone()));
+
+        // 加密之前写入的明文记录仍然可以读取
+        let mut rec1 = LogRecord::new("name".as_bytes().to_vec(), "bitcask".as_bytes().to_vec());
+        let enc1 = rec1.encode();
+        data_file.write(&enc1).unwrap();
+
+        let mut rec2 = LogRecord::new("name".as_bytes().to_vec(), "encrypted".as_bytes().to_vec());
+        let enc2 = data_file
+            .encode_log_record(&mut rec2, CompressionType::None)
+            .unwrap();
+        assert!(!enc2.windows(9).any(|w| w == b"encrypted"));
+        data_file.write(&enc2).unwrap();
+
+        let res = data_file.read_log_record(DATA_FILE_HEADER_SIZE).unwrap();
+        assert_eq!(res.record.value, rec1.value);
+        let offset = DATA_FILE_HEADER_SIZE + res.size;
+        let res = data_file.read_log_record(offset).unwrap();
+        assert_eq!(res.record.key, rec2.key);
+        assert_eq!(res.record.value, rec2.value);
+        assert_eq!(res.size, enc2.len() as u64);
+        drop(data_file);
+
+        // 没有密钥或者密钥错误
+        let data_file = DataFile::open(&StdFileSystem, dir_path.clone(), 0).unwrap();
+        assert_eq!(
+            data_file.read_log_record(offset).err(),
+            Some(Errors::InvalidEncryptionKey)
+        );
+        let data_file = data_file.with_cipher(Some(Arc::new(Cipher::new(&[2; 32], &[]))));
+        assert_eq!(
+            data_file.read_log_record(offset).err(),
+            Some(Errors::InvalidEncryptionKey)
+        );
+
+        // 数据损坏时返回 crc 错误
+        let data_file = DataFile::open(&StdFileSystem, dir_path.clone(), 0)
+            .unwrap()
+            .with_cipher(Some(cipher));
+        let mut corrupted = enc2.clone();
+        let last = corrupted.len() - 1;
+        corrupted[last] ^= 0xff;
+        data_file.write(&corrupted).unwrap();
+        assert_eq!(
+            data_file.read_log_record(offset + enc2.len() as u64).err(),
+            Some(Errors::InvalidLogRecordCrc)
+        );
+
+        fs::remove_dir_all(dir_path).expect("remove dir should work");
+    }
+}
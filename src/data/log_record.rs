@@ -1,10 +1,29 @@
-#[derive(PartialEq, Clone)]
+use super::compression::compress;
+use crate::{Errors, Result, options::CompressionType};
+use bytes::{BufMut, BytesMut};
+use prost::{
+    decode_length_delimiter, encode_length_delimiter,
+    encoding::{decode_varint, encode_varint, encoded_len_varint},
+    length_delimiter_len,
+};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// LogRecord 头部中 crc 校验值的长度
+pub(crate) const CRC_SIZE: usize = std::mem::size_of::<u32>();
+
+#[derive(PartialEq, Clone, Copy, Debug)]
 pub enum LogRecordType {
     // 正常 put 的数据
     NORMAL = 1,
 
     // 被删除的数据标记
     DELETED = 2,
+
+    // 批量写入完成的标记
+    BATCHFINISHED = 3,
+
+    // merge 时为快照保留的旧版本, 只能通过快照读取, 重放数据文件时不加载到索引中
+    SNAPSHOT = 4,
 }
 
 /// LogRecord 写入到数据文件的记录
@@ -14,13 +33,24 @@ pub struct LogRecord {
     pub key: Vec<u8>,
     pub value: Vec<u8>,
     pub rec_type: LogRecordType,
+    // 批量写入的序列号, 非批量写入的记录为 NON_BATCH_SEQ_NO
+    pub seq_no: u64,
+    // 过期时间, 毫秒级的 unix 时间戳, 永不过期的记录为 NEVER_EXPIRE
+    pub expire_at: u64,
+    // 提交时间, 毫秒级的 unix 时间戳, 组提交时设置, 用于按时间恢复备份
+    // 不是通过组提交写入的记录以及格式版本 7 之前的记录为 NO_COMMIT_TIME
+    pub commit_at: u64,
 }
 
 // 数据位置索引信息, 描述数据存储到哪个位置
-#[derive(Clone, Copy)]
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
 pub struct LogRecordPos {
     file_id: u32,
     offset: u64,
+    // 数据在磁盘上占据的大小
+    size: u32,
+    // 数据的过期时间, 和 LogRecord 中的一致
+    expire_at: u64,
 }
 
 /// 从数据文件中读取的 log_record 信息, 包含其 size
@@ -30,8 +60,19 @@ pub struct ReadLogRecord {
 }
 
 impl LogRecordPos {
-    pub fn new(file_id: u32, offset: u64) -> Self {
-        Self { file_id, offset }
+    pub fn new(file_id: u32, offset: u64, size: u32) -> Self {
+        Self {
+            file_id,
+            offset,
+            size,
+            expire_at: NEVER_EXPIRE,
+        }
+    }
+
+    /// 设置数据的过期时间
+    pub fn with_expire_at(mut self, expire_at: u64) -> Self {
+        self.expire_at = expire_at;
+        self
     }
 
     pub fn get_file_id(&self) -> u32 {
@@ -41,6 +82,46 @@ impl LogRecordPos {
     pub fn get_offset(&self) -> u64 {
         self.offset
     }
+
+    pub fn get_size(&self) -> u32 {
+        self.size
+    }
+
+    pub fn get_expire_at(&self) -> u64 {
+        self.expire_at
+    }
+
+    /// 数据是否已经过期
+    pub fn is_expired(&self) -> bool {
+        is_expired(self.expire_at)
+    }
+
+    /// 对位置信息进行编码, 写入到 hint 文件中
+    pub fn encode(&self) -> Vec<u8> {
+        let mut buf = BytesMut::new();
+        encode_length_delimiter(self.file_id as usize, &mut buf).unwrap();
+        encode_length_delimiter(self.offset as usize, &mut buf).unwrap();
+        encode_length_delimiter(self.size as usize, &mut buf).unwrap();
+        encode_varint(self.expire_at, &mut buf);
+        buf.to_vec()
+    }
+
+    /// 从 hint 文件的记录中解码位置信息
+    pub fn decode(mut buf: &[u8]) -> Result<Self> {
+        let mut decode =
+            || decode_length_delimiter(&mut buf).map_err(|_| Errors::DataDirectoryCorrupted);
+        let file_id = decode()? as u32;
+        let offset = decode()? as u64;
+        let size = decode()? as u32;
+
+        // 格式版本 3 之前的 hint 记录中没有过期时间
+        let expire_at = match buf.is_empty() {
+            true => NEVER_EXPIRE,
+            false => decode_varint(&mut buf).map_err(|_| Errors::DataDirectoryCorrupted)?,
+        };
+
+        Ok(Self::new(file_id, offset, size).with_expire_at(expire_at))
+    }
 }
 
 impl LogRecord {
@@ -49,10 +130,179 @@ impl LogRecord {
             key,
             value,
             rec_type: LogRecordType::NORMAL,
+            seq_no: NON_BATCH_SEQ_NO,
+            expire_at: NEVER_EXPIRE,
+            commit_at: NO_COMMIT_TIME,
         }
     }
 
+    /// 对 LogRecord 进行编码, 返回字节数组, value 不压缩
     pub fn encode(&mut self) -> Vec<u8> {
-        todo!()
+        self.encode_with_compression(CompressionType::None)
+    }
+
+    /// 对 LogRecord 进行编码, 使用给定的压缩类型压缩 value, 返回字节数组
+    ///
+    /// +----------+----------+------------+--------------------+--------------------+--------------------+-------------------+-------------------+-------+-------+
+    /// | crc 校验 | type 类型 | 压缩类型    | seq no             | expire at          | commit at          | key size          | value size        |  key  | value |
+    /// +----------+----------+------------+--------------------+--------------------+--------------------+-------------------+-------------------+-------+-------+
+    ///   4 字节     1 字节      1 字节       变长 (最大 10 字节)   变长 (最大 10 字节)   变长 (最大 10 字节)   变长 (最大 5 字节)   变长 (最大 5 字节)    变长     变长
+    ///
+    /// crc 校验值覆盖 crc 之后的头部以及 key/value, value size 是压缩之后的大小
+    /// 格式版本 1 的数据文件中没有 seq no, 格式版本 3 之前的数据文件中没有 expire at,
+    /// 格式版本 4 之前的数据文件中没有压缩类型, 格式版本 7 之前的数据文件中没有 commit at
+    pub fn encode_with_compression(&mut self, compression: CompressionType) -> Vec<u8> {
+        let (compression, value) = compress(&self.value, compression);
+        let header_size = self.header_size(value.len());
+        let mut buf = BytesMut::with_capacity(header_size + self.key.len() + value.len());
+
+        // 先预留出 crc 的位置, 待其余数据写入后再回填
+        buf.put_u32(0);
+        buf.put_u8(self.rec_type as u8);
+        buf.put_u8(compression as u8);
+        encode_varint(self.seq_no, &mut buf);
+        encode_varint(self.expire_at, &mut buf);
+        encode_varint(self.commit_at, &mut buf);
+        encode_length_delimiter(self.key.len(), &mut buf).unwrap();
+        encode_length_delimiter(value.len(), &mut buf).unwrap();
+        buf.extend_from_slice(&self.key);
+        buf.extend_from_slice(&value);
+
+        let crc = crc32fast::hash(&buf[CRC_SIZE..]);
+        buf[..CRC_SIZE].copy_from_slice(&crc.to_be_bytes());
+
+        buf.to_vec()
+    }
+
+    /// 编码后头部的实际长度
+    fn header_size(&self, value_size: usize) -> usize {
+        CRC_SIZE
+            + std::mem::size_of::<u8>() * 2
+            + encoded_len_varint(self.seq_no)
+            + encoded_len_varint(self.expire_at)
+            + encoded_len_varint(self.commit_at)
+            + length_delimiter_len(self.key.len())
+            + length_delimiter_len(value_size)
+    }
+}
+
+impl TryFrom<u8> for LogRecordType {
+    type Error = u8;
+
+    fn try_from(value: u8) -> std::result::Result<Self, Self::Error> {
+        match value {
+            1 => Ok(LogRecordType::NORMAL),
+            2 => Ok(LogRecordType::DELETED),
+            3 => Ok(LogRecordType::BATCHFINISHED),
+            4 => Ok(LogRecordType::SNAPSHOT),
+            v => Err(v),
+        }
+    }
+}
+
+/// 非批量写入的记录使用的序列号
+pub(crate) const NON_BATCH_SEQ_NO: u64 = 0;
+
+/// 永不过期的记录使用的过期时间
+pub(crate) const NEVER_EXPIRE: u64 = 0;
+
+/// 没有提交时间的记录使用的提交时间
+pub(crate) const NO_COMMIT_TIME: u64 = 0;
+
+/// 当前时间, 毫秒级的 unix 时间戳
+pub(crate) fn now_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or_default()
+}
+
+/// 给定的过期时间是否已经过期
+pub(crate) fn is_expired(expire_at: u64) -> bool {
+    expire_at != NEVER_EXPIRE && expire_at <= now_millis()
+}
+
+/// 获取 LogRecord 头部的最大长度
+pub(crate) fn max_log_record_header_size() -> usize {
+    CRC_SIZE
+        + std::mem::size_of::<u8>() * 2
+        + encoded_len_varint(u64::MAX) * 3
+        + length_delimiter_len(u32::MAX as usize) * 2
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn log_record_encode_should_work() {
+        // 正常的一条 LogRecord 编码
+        let mut rec = LogRecord::new("name".as_bytes().to_vec(), "bitcask".as_bytes().to_vec());
+        let enc = rec.encode();
+        assert_eq!(enc.len(), 4 + 1 + 1 + 1 + 1 + 1 + 1 + 1 + 4 + 7);
+        assert_eq!(enc[4], LogRecordType::NORMAL as u8);
+        assert_eq!(enc[5], CompressionType::None as u8);
+
+        let crc = u32::from_be_bytes(enc[..4].try_into().unwrap());
+        assert_eq!(crc, crc32fast::hash(&enc[4..]));
+
+        // value 为空的情况
+        let mut rec = LogRecord::new("name".as_bytes().to_vec(), Default::default());
+        let enc = rec.encode();
+        assert_eq!(enc.len(), 4 + 1 + 1 + 1 + 1 + 1 + 1 + 1 + 4);
+
+        // 类型为 DELETED 的情况
+        let mut rec = LogRecord {
+            key: "name".as_bytes().to_vec(),
+            value: Default::default(),
+            rec_type: LogRecordType::DELETED,
+            seq_no: NON_BATCH_SEQ_NO,
+            expire_at: NEVER_EXPIRE,
+            commit_at: NO_COMMIT_TIME,
+        };
+        let enc = rec.encode();
+        assert_eq!(enc[4], LogRecordType::DELETED as u8);
+
+        // 批量写入的记录带有序列号
+        let mut rec = LogRecord::new("name".as_bytes().to_vec(), Default::default());
+        rec.seq_no = 300;
+        let enc = rec.encode();
+        assert_eq!(enc.len(), 4 + 1 + 1 + 2 + 1 + 1 + 1 + 1 + 4);
+
+        // 带有过期时间的记录
+        let mut rec = LogRecord::new("name".as_bytes().to_vec(), Default::default());
+        rec.expire_at = 300;
+        let enc = rec.encode();
+        assert_eq!(enc.len(), 4 + 1 + 1 + 1 + 2 + 1 + 1 + 1 + 4);
+
+        // 带有提交时间的记录
+        let mut rec = LogRecord::new("name".as_bytes().to_vec(), Default::default());
+        rec.commit_at = 300;
+        let enc = rec.encode();
+        assert_eq!(enc.len(), 4 + 1 + 1 + 1 + 1 + 2 + 1 + 1 + 4);
+
+        // 压缩 value 的记录
+        let value = "bitcask".repeat(32).into_bytes();
+        let mut rec = LogRecord::new("name".as_bytes().to_vec(), value.clone());
+        let enc = rec.encode_with_compression(CompressionType::Lz4);
+        assert_eq!(enc[5], CompressionType::Lz4 as u8);
+        assert!(enc.len() < 4 + 1 + 1 + 1 + 1 + 1 + 1 + 2 + 4 + value.len());
+        assert_eq!(rec.value, value);
+    }
+
+    #[test]
+    fn log_record_pos_encode_and_decode_should_work() {
+        let pos = LogRecordPos::new(7, 1024 * 1024, 256);
+        let enc = pos.encode();
+        assert_eq!(LogRecordPos::decode(&enc).unwrap(), pos);
+
+        // 带有过期时间
+        let pos = pos.with_expire_at(now_millis());
+        let enc = pos.encode();
+        assert_eq!(LogRecordPos::decode(&enc).unwrap(), pos);
+        assert!(pos.is_expired());
+
+        // 数据不完整
+        assert!(LogRecordPos::decode(&enc[..enc.len() - 1]).is_err());
     }
 }
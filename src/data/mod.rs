@@ -1,5 +1,14 @@
+mod cipher;
+mod compression;
 mod data_file;
 mod log_record;
 
-pub use data_file::{DATA_FILE_SUFFIX, DataFile};
+pub(crate) use cipher::Cipher;
+pub use cipher::ENCRYPTION_KEY_SIZE;
+pub(crate) use data_file::{
+    DATA_FILE_FORMAT_VERSION, MERGE_FINISHED_FILE_NAME, SEQ_NO_FILE_NAME, get_data_file_name,
+    get_hint_file_name,
+};
+pub use data_file::{DATA_FILE_HEADER_SIZE, DATA_FILE_SUFFIX, DataFile};
 pub use log_record::{LogRecord, LogRecordPos, LogRecordType, ReadLogRecord};
+pub(crate) use log_record::{NEVER_EXPIRE, NO_COMMIT_TIME, NON_BATCH_SEQ_NO, now_millis};
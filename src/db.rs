@@ -1,24 +1,105 @@
 use crate::{
-    DATA_FILE_SUFFIX, DataFile, Errors, Indexer, LogRecord, LogRecordPos, LogRecordType, Options,
-    Result, index,
+    DATA_FILE_SUFFIX, DataFile, DirLock, Errors, FileSystem, Indexer, LogRecord, LogRecordPos,
+    LogRecordType, Options, Result,
+    compaction::Compaction,
+    data::{
+        Cipher, DATA_FILE_FORMAT_VERSION, DATA_FILE_HEADER_SIZE, NEVER_EXPIRE, NO_COMMIT_TIME,
+        NON_BATCH_SEQ_NO, SEQ_NO_FILE_NAME, now_millis,
+    },
+    fio::new_file_system,
+    flusher::Flusher,
+    group_commit::WriteRequest,
+    index,
+    merge::load_merge_files,
+    options::{IteratorOptions, RecoveryPolicy, SyncPolicy},
+    snapshot::CommitTracker,
+};
+use bytes::{Buf, BufMut, Bytes, BytesMut};
+use log::{error, warn};
+use parking_lot::{Mutex, RwLock};
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    sync::{
+        Arc,
+        atomic::{AtomicBool, AtomicU64, Ordering},
+    },
+    time::Duration,
 };
-use bytes::Bytes;
-use log::warn;
-use parking_lot::RwLock;
-use std::{collections::HashMap, fs, path::PathBuf, sync::Arc};
 
-const INITIAL_FILE_ID: u32 = 0;
+pub(crate) const INITIAL_FILE_ID: u32 = 0;
+const SEQ_NO_KEY: &[u8] = "seq.no".as_bytes();
 
 pub struct Engine {
-    options: Arc<Options>,
+    pub(crate) options: Arc<Options>,
     // 当前活跃数据文件
-    active_file: Arc<RwLock<DataFile>>,
+    pub(crate) active_file: Arc<RwLock<DataFile>>,
     // 旧的数据文件文件
-    older_files: Arc<RwLock<HashMap<u32, DataFile>>>,
+    pub(crate) older_files: Arc<RwLock<HashMap<u32, DataFile>>>,
     // 数据内存索引
-    index: Box<dyn Indexer>,
+    pub(crate) index: Box<dyn Indexer>,
     // 文件 id 信息
     file_ids: Vec<u32>,
+    // 每个数据文件中已经失效的数据大小, 被覆盖或删除的记录以及删除标记本身
+    pub(crate) dead_bytes: Arc<RwLock<HashMap<u32, u64>>>,
+    // 防止多个线程同时 merge
+    pub(crate) merging_lock: Mutex<()>,
+    // 批量写入的序列号, 全局递增
+    pub(crate) seq_no: Arc<AtomicU64>,
+    // 数据目录所在的文件系统, 根据 Options::io_type 创建
+    pub(crate) fs: Arc<dyn FileSystem>,
+    // 数据目录的文件锁, 保证同一时刻只有一个 Engine 使用该目录, 关闭数据库时释放
+    lock_file: Box<dyn DirLock>,
+    // 数据库是否已经关闭
+    closed: AtomicBool,
+    // merge 替换数据文件或者撤销失败的写入时出错, 磁盘上的数据文件和内存中的不再一致, 需要重新打开
+    pub(crate) poisoned: AtomicBool,
+    // 后台 compaction 的状态
+    pub(crate) compaction: Arc<Compaction>,
+    // 等待组提交的写入请求
+    pub(crate) write_queue: Mutex<Vec<Arc<WriteRequest>>>,
+    // 上一次持久化之后累计写入的字节数
+    bytes_since_sync: AtomicU64,
+    // 定时持久化活跃文件的后台线程, SyncPolicy::Interval 时启动
+    flusher: Option<Flusher>,
+    // 数据文件记录的加密, 没有配置密钥时为空
+    pub(crate) cipher: Option<Arc<Cipher>>,
+    // 提交序号, 事务的冲突检测以及快照的修改历史
+    pub(crate) commits: CommitTracker,
+    // 打开数据库时根据 RecoveryPolicy 丢弃的数据
+    pub(crate) discarded: Vec<DiscardedData>,
+}
+
+/// 存储引擎的统计信息
+#[derive(Debug, Default)]
+pub struct Stat {
+    // key 的总数量
+    pub key_num: usize,
+    // 数据文件的数量
+    pub data_file_num: usize,
+    // 可以通过 merge 回收的数据大小
+    pub reclaimable_size: u64,
+    // 每个数据文件中可以回收的数据大小
+    pub reclaimable_size_per_file: HashMap<u32, u64>,
+    // 数据目录占据的磁盘空间大小
+    pub disk_size: u64,
+    // 最后分配的批量写入或事务的序列号, 可以用于按序列号恢复
+    pub seq_no: u64,
+}
+
+/// 打开数据库时因为记录损坏或者写入不完整而丢弃的数据
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DiscardedData {
+    // 数据所在的文件 id
+    pub file_id: u32,
+    // 丢弃的数据在文件中的起始位置
+    pub offset: u64,
+    // 丢弃的数据大小
+    pub size: u64,
+    // 读取记录时的错误, IncompleteLogRecord 或者 InvalidLogRecordCrc, 活跃文件的头部不完整时为 InvalidDataFileHeader
+    pub reason: Errors,
+    // 是否已经从数据文件中截断, 跳过的数据仍然保留在文件中
+    pub truncated: bool,
 }
 
 impl Engine {
@@ -30,17 +111,37 @@ impl Engine {
         }
 
         let options = opts.clone();
+        let fs = new_file_system(&options.io_type);
         // 判断数据目录是否存在, 如果不存在的话则创建这个目录
         let dir_path = options.dir_path.clone();
-        if !dir_path.is_dir() {
-            if let Err(e) = fs::create_dir_all(&dir_path) {
-                warn!("create database directory failed: {}", e);
-                return Err(Errors::FailedToCreateDatabaseDir);
-            }
+        if !fs.is_dir(&dir_path)
+            && let Err(e) = fs.create_dir_all(&dir_path)
+        {
+            warn!("create database directory failed: {}", e);
+            return Err(Errors::FailedToCreateDatabaseDir);
         }
 
+        // 获取数据目录的文件锁, 在 Engine 的整个生命周期内持有
+        let lock_file = fs.lock_dir(&dir_path)?;
+
+        // 加载 merge 数据目录, 完成上一次中断的文件替换
+        load_merge_files(fs.as_ref(), &dir_path)?;
+
+        // 根据配置的密钥构建记录的加密
+        let cipher = options
+            .encryption_key
+            .as_ref()
+            .map(|key| Arc::new(Cipher::new(key, &options.old_encryption_keys)));
+
         // 加载数据文件
-        let mut data_files = load_data_files(dir_path.clone())?;
+        let mut discarded = Vec::new();
+        let mut data_files = load_data_files(
+            fs.as_ref(),
+            dir_path.clone(),
+            &options,
+            cipher.clone(),
+            &mut discarded,
+        )?;
 
         // 设置 file id 信息
         let mut file_ids = Vec::new();
@@ -48,38 +149,59 @@ impl Engine {
             file_ids.push(v.get_file_id());
         }
 
-        // 把老的数据文件保存到 older_files 中
-        let mut older_files = HashMap::new();
-        if data_files.len() > 1 {
-            let mut idx = 0;
-            let size = data_files.len();
-            while let Some(file) = data_files.pop() {
-                if idx < size - 1 {
-                    older_files.insert(file.get_file_id(), file);
-                } else {
-                    break;
-                }
-                idx += 1;
-            }
-        }
-
         // 拿到当前活跃文件, 列表中最后一个文件
         let active_file = match data_files.pop() {
             Some(file) => file,
-            None => DataFile::new(dir_path.clone(), INITIAL_FILE_ID)?,
+            None => DataFile::new(fs.as_ref(), dir_path.clone(), INITIAL_FILE_ID)?
+                .with_cipher(cipher.clone()),
         };
 
+        // 把老的数据文件保存到 older_files 中
+        let mut older_files = HashMap::new();
+        for file in data_files {
+            older_files.insert(file.get_file_id(), file);
+        }
+
+        // merge 之后的数据文件中不再保留序列号, 需要从序列号文件中恢复
+        let seq_no = load_seq_no(fs.as_ref(), &dir_path)?;
+
         // 构建存储引擎
         let mut engine = Engine {
             options: Arc::new(opts),
             active_file: Arc::new(RwLock::new(active_file)),
             older_files: Arc::new(RwLock::new(older_files)),
-            index: Box::new(index::new_indexer(options.index_type)),
+            index: index::new_indexer(options.index_type),
             file_ids,
+            dead_bytes: Arc::new(RwLock::new(HashMap::new())),
+            merging_lock: Mutex::new(()),
+            seq_no: Arc::new(AtomicU64::new(seq_no)),
+            fs,
+            lock_file,
+            closed: AtomicBool::new(false),
+            poisoned: AtomicBool::new(false),
+            compaction: Arc::new(Compaction::new(options.compaction.clone())),
+            write_queue: Mutex::new(Vec::new()),
+            bytes_since_sync: AtomicU64::new(0),
+            flusher: None,
+            cipher,
+            commits: CommitTracker::new(),
+            discarded,
         };
 
         engine.load_index_from_data_files()?;
 
+        // 活跃文件是旧的格式版本时打开一个新的活跃文件, 新的数据总是以最新的格式写入
+        {
+            let mut active_file = engine.active_file.write();
+            if active_file.get_version() != DATA_FILE_FORMAT_VERSION {
+                engine.rotate_active_file(&mut active_file)?;
+            }
+        }
+
+        if let SyncPolicy::Interval(interval) = engine.options.sync_policy {
+            engine.flusher = Some(Flusher::start(engine.active_file.clone(), interval));
+        }
+
         Ok(engine)
     }
 
@@ -90,20 +212,45 @@ impl Engine {
             return Err(Errors::KeyIsEmpty);
         }
 
-        // 构建 LogRecord
-        let mut record = LogRecord::new(key.to_vec(), value.to_vec());
+        // 构建 LogRecord, 和其他并发的写入一起提交
+        let record = LogRecord::new(key.to_vec(), value.to_vec());
+        self.group_commit(vec![record], false, false)?;
 
-        // 追加写到活跃数据文件中
-        let log_record_pos = self.append_log_record(&mut record)?;
+        Ok(())
+    }
 
-        // 更新内存索引
-        if !self.index.put(key.to_vec(), log_record_pos) {
-            return Err(Errors::IndexUpdateFailed);
+    /// 存储 key/value 数据, 并在 ttl 之后过期, 过期之后的数据和被删除的数据一样处理
+    pub fn put_with_ttl(&self, key: Bytes, value: Bytes, ttl: Duration) -> Result<()> {
+        if key.is_empty() {
+            return Err(Errors::KeyIsEmpty);
         }
 
+        let mut record = LogRecord::new(key.to_vec(), value.to_vec());
+        // 超出 u64 范围的 ttl 按最大的过期时间处理, 不能截断成一个很小的值
+        let ttl_millis = u64::try_from(ttl.as_millis()).unwrap_or(u64::MAX);
+        record.expire_at = now_millis().saturating_add(ttl_millis);
+        self.group_commit(vec![record], false, false)?;
+
         Ok(())
     }
 
+    /// 获取 key 剩余的存活时间, 永不过期的 key 返回 None
+    pub fn ttl(&self, key: Bytes) -> Result<Option<Duration>> {
+        if key.is_empty() {
+            return Err(Errors::KeyIsEmpty);
+        }
+        self.check_open()?;
+
+        match self.index.get(key.to_vec()) {
+            Some(pos) if pos.is_expired() => Err(Errors::KeyNotFound),
+            Some(pos) if pos.get_expire_at() == NEVER_EXPIRE => Ok(None),
+            Some(pos) => Ok(Some(Duration::from_millis(
+                pos.get_expire_at().saturating_sub(now_millis()),
+            ))),
+            None => Err(Errors::KeyNotFound),
+        }
+    }
+
     /// 根据 key 删除对应的数据
     pub fn delete(&self, key: Bytes) -> Result<()> {
         // 判断 key 的有效性
@@ -111,26 +258,16 @@ impl Engine {
             return Err(Errors::KeyIsEmpty);
         }
 
-        // 从内存索引当中取出对应的数据, 不存在的话直接返回
-        if self.index.get(key.to_vec()).is_none() {
-            return Ok(());
-        }
-
-        // 构建 LogRecord, 标识算是被删除的
-        let mut record = LogRecord {
+        // 构建 LogRecord, 标识算是被删除的, 提交时 key 不存在则不写入
+        let record = LogRecord {
             key: key.to_vec(),
             value: Default::default(),
             rec_type: LogRecordType::DELETED,
+            seq_no: NON_BATCH_SEQ_NO,
+            expire_at: NEVER_EXPIRE,
+            commit_at: NO_COMMIT_TIME,
         };
-
-        // 写入到数据文件中
-        self.append_log_record(&mut record)?;
-
-        // 更新内存索引中对应的 key
-        let flag = self.index.delete(key.to_vec());
-        if !flag {
-            return Err(Errors::IndexUpdateFailed);
-        }
+        self.group_commit(vec![record], true, false)?;
 
         Ok(())
     }
@@ -141,13 +278,30 @@ impl Engine {
             return Err(Errors::KeyIsEmpty);
         }
 
+        self.get_value(&key)
+    }
+
+    // 根据 key 从数据文件中读取最新的 value
+    pub(crate) fn get_value(&self, key: &[u8]) -> Result<Bytes> {
+        self.get_value_at(key, None)
+    }
+
+    // 根据 key 从数据文件中读取提交序号 seq 时的 value, seq 为 None 时读取最新的 value
+    pub(crate) fn get_value_at(&self, key: &[u8], seq: Option<u64>) -> Result<Bytes> {
+        // 先获取数据文件的锁再查询索引, 避免 merge 替换数据文件时读到失效的位置
+        let active_file = self.active_file.read();
+        let older_files = self.older_files.read();
+        self.check_open()?;
+
         // 从内存索引中获取 key 对应的数据信息
-        let log_record = match self.index.get(key.to_vec()) {
+        let log_record = match self.get_pos_at(key, seq) {
+            // 过期的数据和被删除的数据一样处理
+            Some(pos) if pos.is_expired() => {
+                self.remove_expired(key, pos);
+                return Err(Errors::KeyNotFound);
+            }
             // 从对应的数据文件中获取对应的 LogRecord
             Some(pos) => {
-                let active_file = self.active_file.read();
-                let older_files = self.older_files.read();
-
                 if active_file.get_file_id() == pos.get_file_id() {
                     active_file.read_log_record(pos.get_offset())?.record
                 } else {
@@ -160,6 +314,11 @@ impl Engine {
             None => return Err(Errors::KeyNotFound),
         };
 
+        // 位置已经被 merge 重用时会读到其他 key 的记录, 不能当作该 key 的数据返回
+        if log_record.key != key {
+            return Err(Errors::IndexKeyMismatch);
+        }
+
         // 判断 LogRecord 的类型
         if log_record.rec_type == LogRecordType::DELETED {
             return Err(Errors::KeyNotFound);
@@ -168,42 +327,226 @@ impl Engine {
         Ok(log_record.value.into())
     }
 
-    // 追加写数据到当前活跃文件中
-    fn append_log_record(&self, log_record: &mut LogRecord) -> Result<LogRecordPos> {
-        let dir_path = self.options.dir_path.clone();
+    /// 获取存储引擎的统计信息
+    pub fn stat(&self) -> Result<Stat> {
+        self.check_open()?;
+        // 过期的 key 不计入 key 的数量, 先从索引中删除
+        let mut expired = Vec::new();
+        let mut index_iter = self.index.iterator(IteratorOptions::default());
+        while let Some((key, pos)) = index_iter.next() {
+            if pos.is_expired() {
+                expired.push((key.clone(), *pos));
+            }
+        }
+        drop(index_iter);
 
-        // 输入数据进行编码
-        let enc_record = log_record.encode();
-        let record_len = enc_record.len() as u64;
+        let data_file_num = {
+            let _active_file = self.active_file.read();
+            let older_files = self.older_files.read();
+            for (key, pos) in expired {
+                self.remove_expired(&key, pos);
+            }
+            older_files.len() + 1
+        };
+        let reclaimable_size_per_file = self.dead_bytes.read().clone();
 
-        // 获取到当前活跃文件
-        let mut active_file = self.active_file.write();
-        // 判断当前活跃文件是否达到了阀值
-        if active_file.get_write_off() + record_len > self.options.data_file_size {
-            // 将当前活跃文件进行持久化
-            active_file.sync()?;
+        Ok(Stat {
+            key_num: self.index.len(),
+            data_file_num,
+            reclaimable_size: reclaimable_size_per_file.values().sum(),
+            reclaimable_size_per_file,
+            disk_size: dir_disk_size(self.fs.as_ref(), &self.options.dir_path)?,
+            seq_no: self.seq_no.load(Ordering::SeqCst),
+        })
+    }
+
+    /// 持久化当前活跃文件
+    pub fn sync(&self) -> Result<()> {
+        let active_file = self.active_file.read();
+        self.check_open()?;
+        active_file.sync()?;
+        self.bytes_since_sync.store(0, Ordering::SeqCst);
+        Ok(())
+    }
+
+    /// 数据库已经关闭时返回 EngineClosed, 读写之前检查, 写入时需要持有活跃文件的锁
+    /// 关闭时持有活跃文件的写锁, 关闭完成之后不会再有写入到达数据文件
+    /// merge 替换数据文件失败之后返回 EnginePoisoned, 重新打开时会完成替换
+    pub(crate) fn check_open(&self) -> Result<()> {
+        if self.closed.load(Ordering::SeqCst) {
+            return Err(Errors::EngineClosed);
+        }
+        if self.poisoned.load(Ordering::SeqCst) {
+            return Err(Errors::EnginePoisoned);
+        }
+        Ok(())
+    }
 
-            let current_fid = active_file.get_file_id();
-            // 将旧的文件放入到 map 中
-            let mut older_files = self.older_files.write();
-            let old_file = DataFile::new(dir_path.clone(), current_fid)?;
-            older_files.insert(current_fid, old_file);
+    /// 关闭数据库, 持久化活跃文件和序列号, 并释放数据目录的文件锁
+    /// 关闭之后的读写都返回 EngineClosed, 重复关闭直接返回
+    pub fn close(&self) -> Result<()> {
+        if self.closed.swap(true, Ordering::SeqCst) {
+            return Ok(());
+        }
 
-            // 打开一个新的文件
-            let new_file = DataFile::new(dir_path.clone(), current_fid + 1)?;
-            *active_file = new_file;
+        // 停止后台线程, 并等待正在进行的 merge 结束
+        self.compaction.stop();
+        if let Some(flusher) = &self.flusher {
+            flusher.stop();
+        }
+        let _merging_lock = self.merging_lock.lock();
+        let active_file = self.active_file.write();
+
+        // 持久化当前活跃文件, 并保存当前的序列号
+        let res = active_file.sync().and_then(|_| self.save_seq_no());
+
+        // 释放数据目录的文件锁, 持久化失败时同样释放, 之后可以重新打开并从数据文件中恢复
+        // 已经关闭的数据库不能再次关闭, 失败的原因只在这一次返回
+        let unlock_res = self.lock_file.unlock();
+        res.and(unlock_res)
+    }
+
+    // 将当前的序列号写入到序列号文件中, 覆盖之前保存的值
+    fn save_seq_no(&self) -> Result<()> {
+        save_seq_no(
+            self.fs.as_ref(),
+            &self.options.dir_path,
+            self.seq_no.load(Ordering::SeqCst),
+        )
+    }
+
+    // 将多条记录编码之后合并为一次写入, 活跃文件写满时先写入已编码的部分再切换文件
+    // 不会进行持久化, 调用方需要持有活跃文件的写锁
+    pub(crate) fn write_log_records(
+        &self,
+        active_file: &mut DataFile,
+        log_records: &mut [LogRecord],
+    ) -> Result<Vec<LogRecordPos>> {
+        let mut positions = Vec::with_capacity(log_records.len());
+        let mut buf = Vec::new();
+        for log_record in log_records.iter_mut() {
+            // 输入数据进行编码, 根据配置压缩 value 以及加密
+            let enc_record = active_file.encode_log_record(log_record, self.options.compression)?;
+            let record_len = enc_record.len() as u64;
+
+            // 判断当前活跃文件是否达到了阀值
+            let write_off = active_file.get_write_off() + buf.len() as u64;
+            if write_off + record_len > self.options.data_file_size {
+                if !buf.is_empty() {
+                    active_file.write(&buf)?;
+                    buf.clear();
+                }
+                self.rotate_active_file(active_file)?;
+            }
+
+            positions.push(
+                LogRecordPos::new(
+                    active_file.get_file_id(),
+                    active_file.get_write_off() + buf.len() as u64,
+                    record_len as u32,
+                )
+                .with_expire_at(log_record.expire_at),
+            );
+            buf.extend_from_slice(&enc_record);
         }
 
         // 追加写数据到当前活跃文件中
-        let write_off = active_file.get_write_off();
-        active_file.write(&enc_record)?;
+        if !buf.is_empty() {
+            active_file.write(&buf)?;
+        }
+
+        Ok(positions)
+    }
+
+    // 丢弃从 start_file_id 的 start_offset 开始写入的数据, 用于写入失败时撤销一组已经写入的记录
+    // 写入期间切换出的文件中只有这一组的记录, 截断到文件头部, 调用方需要持有活跃文件的写锁
+    pub(crate) fn rollback_log_records(
+        &self,
+        active_file: &DataFile,
+        start_file_id: u32,
+        start_offset: u64,
+    ) -> Result<()> {
+        let older_files = self.older_files.read();
+        for file_id in start_file_id..=active_file.get_file_id() {
+            let data_file = match file_id == active_file.get_file_id() {
+                true => active_file,
+                false => older_files.get(&file_id).ok_or(Errors::DataFileNotFound)?,
+            };
+            let len = match file_id == start_file_id {
+                true => start_offset,
+                false => DATA_FILE_HEADER_SIZE,
+            };
+            if data_file.get_write_off() > len {
+                data_file.truncate(len)?;
+                data_file.sync()?;
+            }
+        }
+
+        Ok(())
+    }
 
-        // 根据配置项决定是否持久化
-        if self.options.sync_write {
+    // 根据持久化策略决定写入 written 字节之后是否持久化, force 为 true 时总是持久化
+    pub(crate) fn sync_by_policy(
+        &self,
+        active_file: &DataFile,
+        written: u64,
+        force: bool,
+    ) -> Result<()> {
+        let need_sync = force
+            || match self.options.sync_policy {
+                SyncPolicy::Always => true,
+                SyncPolicy::EveryNBytes(bytes_per_sync) => {
+                    self.bytes_since_sync.fetch_add(written, Ordering::SeqCst) + written
+                        >= bytes_per_sync
+                }
+                SyncPolicy::Interval(_) | SyncPolicy::Never => false,
+            };
+        if need_sync {
             active_file.sync()?;
+            self.bytes_since_sync.store(0, Ordering::SeqCst);
         }
 
-        Ok(LogRecordPos::new(active_file.get_file_id(), write_off))
+        Ok(())
+    }
+
+    /// 持久化当前活跃文件并将其转为旧的数据文件, 然后打开一个新的活跃文件
+    pub(crate) fn rotate_active_file(&self, active_file: &mut DataFile) -> Result<()> {
+        // 将当前活跃文件进行持久化
+        active_file.sync()?;
+
+        let current_fid = active_file.get_file_id();
+        // 打开一个新的文件, 并将旧的文件放入到 map 中
+        let new_file = DataFile::new(
+            self.fs.as_ref(),
+            self.options.dir_path.clone(),
+            current_fid + 1,
+        )?
+        .with_cipher(self.cipher.clone());
+        let old_file = std::mem::replace(active_file, new_file);
+        let mut older_files = self.older_files.write();
+        older_files.insert(current_fid, old_file);
+
+        Ok(())
+    }
+
+    /// 打开 dir_path 中一个旧的数据文件, 根据配置决定是否使用 mmap
+    pub(crate) fn open_older_file(&self, dir_path: PathBuf, file_id: u32) -> Result<DataFile> {
+        let data_file = match self.options.mmap_older_files {
+            true => DataFile::open_mmap(self.fs.as_ref(), dir_path, file_id)?,
+            false => DataFile::open(self.fs.as_ref(), dir_path, file_id)?,
+        };
+        Ok(data_file.with_cipher(self.cipher.clone()))
+    }
+
+    /// 累加 pos 所在数据文件中失效的数据大小
+    pub(crate) fn add_dead_bytes(&self, pos: LogRecordPos) {
+        let mut dead_bytes = self.dead_bytes.write();
+        *dead_bytes.entry(pos.get_file_id()).or_default() += pos.get_size() as u64;
+    }
+
+    /// 打开数据库时根据 RecoveryPolicy 截断或者跳过的数据
+    pub fn discarded_data(&self) -> &[DiscardedData] {
+        &self.discarded
     }
 
     /// 遍历数据文件中的内容, 并依次处理其中的数据
@@ -216,41 +559,105 @@ impl Engine {
         let active_file = self.active_file.read();
         let older_files = self.older_files.read();
 
+        // 暂存批量写入的数据, 按照序列号分组
+        let mut batch_records: HashMap<u64, Vec<(Vec<u8>, LogRecordType, LogRecordPos)>> =
+            HashMap::new();
+        let mut current_seq_no = NON_BATCH_SEQ_NO;
+        let mut discarded = Vec::new();
+
         // 遍历每个文件 id, 取出对应的数据文件, 并加载其中的数据
         for (i, file_id) in self.file_ids.iter().enumerate() {
-            let mut offset = 0;
-            loop {
-                let log_record_res = match *file_id == active_file.get_file_id() {
-                    true => active_file.read_log_record(offset),
-                    false => {
-                        let data_file = older_files.get(file_id).unwrap();
-                        data_file.read_log_record(offset)
-                    }
-                };
+            // 旧的数据文件优先从 hint 文件中加载索引, 不需要读取完整的数据
+            if *file_id != active_file.get_file_id() && self.load_index_from_hint_file(*file_id)? {
+                continue;
+            }
 
-                let (log_record, size) = match log_record_res {
+            let is_active = *file_id == active_file.get_file_id();
+            let data_file = match is_active {
+                true => &*active_file,
+                false => older_files.get(file_id).unwrap(),
+            };
+
+            // 跳过数据文件头部, 从第一条记录开始读取
+            let mut offset = DATA_FILE_HEADER_SIZE;
+            loop {
+                let (log_record, size) = match data_file.read_log_record(offset) {
                     Ok(result) => (result.record, result.size),
-                    Err(e) => {
-                        if e == Errors::ReadDataFileEOF {
-                            break;
+                    Err(Errors::ReadDataFileEOF) => break,
+                    Err(e) if is_corrupted(&e) => {
+                        let file_size = data_file.file_size()?;
+                        match self.options.recovery_policy {
+                            // 丢弃活跃文件中最后一条有效记录之后的所有数据
+                            // 之后还有有效的记录时不是写入中断, 截断会丢失已经提交的数据, 打开失败
+                            RecoveryPolicy::Truncate
+                                if is_active
+                                    && data_file.next_valid_record(offset + 1)? == file_size =>
+                            {
+                                warn!("truncate data file {} at offset {}: {}", file_id, offset, e);
+                                data_file.truncate(offset)?;
+                                data_file.sync()?;
+                                discarded.push(DiscardedData {
+                                    file_id: *file_id,
+                                    offset,
+                                    size: file_size - offset,
+                                    reason: e,
+                                    truncated: true,
+                                });
+                                break;
+                            }
+                            // 从下一条有效的记录继续读取, 跳过的数据作为失效数据在 merge 时回收
+                            RecoveryPolicy::Skip => {
+                                let next = data_file.next_valid_record(offset + 1)?;
+                                warn!(
+                                    "skip corrupted data in file {} from offset {} to {}: {}",
+                                    file_id, offset, next, e
+                                );
+                                let size = next - offset;
+                                self.add_dead_bytes(LogRecordPos::new(
+                                    *file_id,
+                                    offset,
+                                    size.min(u32::MAX as u64) as u32,
+                                ));
+                                discarded.push(DiscardedData {
+                                    file_id: *file_id,
+                                    offset,
+                                    size,
+                                    reason: e,
+                                    truncated: false,
+                                });
+                                offset = next;
+                                continue;
+                            }
+                            _ => return Err(e),
                         }
-                        return Err(e);
                     }
+                    Err(e) => return Err(e),
                 };
 
-                let log_record_pos = LogRecordPos::new(*file_id, offset);
+                let log_record_pos = LogRecordPos::new(*file_id, offset, size as u32)
+                    .with_expire_at(log_record.expire_at);
 
-                let flag = match log_record.rec_type {
-                    LogRecordType::NORMAL => {
-                        self.index.put(log_record.key.to_vec(), log_record_pos)
+                if log_record.seq_no == NON_BATCH_SEQ_NO {
+                    self.update_index(log_record.key, log_record.rec_type, log_record_pos);
+                } else if log_record.rec_type == LogRecordType::BATCHFINISHED {
+                    // 批量写入已经完成, 将这一批的数据更新到内存索引中
+                    let records = batch_records.remove(&log_record.seq_no);
+                    for (key, rec_type, pos) in records.unwrap_or_default() {
+                        self.update_index(key, rec_type, pos);
                     }
-                    LogRecordType::DELETED => self.index.delete(log_record.key.to_vec()),
-                };
-
-                if !flag {
-                    return Err(Errors::IndexUpdateFailed);
+                    self.update_index(log_record.key, log_record.rec_type, log_record_pos);
+                } else {
+                    // 批量写入的数据先暂存起来, 读到完成标记之后才能更新索引
+                    batch_records.entry(log_record.seq_no).or_default().push((
+                        log_record.key,
+                        log_record.rec_type,
+                        log_record_pos,
+                    ));
                 }
 
+                // 更新序列号
+                current_seq_no = current_seq_no.max(log_record.seq_no);
+
                 // 　递增 offset, 下一次读取的时候从新的位置开始
                 offset += size;
             }
@@ -261,31 +668,157 @@ impl Engine {
             }
         }
 
+        // 没有完成标记的批量写入不会生效, 其中的数据都是失效数据
+        for (_, _, pos) in batch_records.into_values().flatten() {
+            self.add_dead_bytes(pos);
+        }
+
+        // 更新当前的序列号, 不小于序列号文件中保存的值
+        self.seq_no.fetch_max(current_seq_no, Ordering::SeqCst);
+
+        self.discarded.extend(discarded);
+
         Ok(())
     }
+
+    /// key 在提交序号 seq 时的位置, seq 之后被修改过时从修改历史中获取, seq 为 None 时返回最新的位置
+    pub(crate) fn get_pos_at(&self, key: &[u8], seq: Option<u64>) -> Option<LogRecordPos> {
+        match seq.and_then(|seq| self.commits.version_at(key, seq)) {
+            Some(pos) => pos,
+            None => self.index.get(key.to_vec()),
+        }
+    }
+
+    /// 从内存索引中删除已经过期的 key, 过期的数据成为失效数据
+    /// 调用时需要持有数据文件的锁, 索引中的位置已经被新的写入或者 merge 修改时不删除
+    pub(crate) fn remove_expired(&self, key: &[u8], pos: LogRecordPos) {
+        if self.index.get(key.to_vec()) == Some(pos) && self.index.delete(key.to_vec()).is_some() {
+            self.add_dead_bytes(pos);
+        }
+    }
+
+    /// 根据记录的类型更新内存索引, 并统计失效的数据大小
+    pub(crate) fn update_index(&self, key: Vec<u8>, rec_type: LogRecordType, pos: LogRecordPos) {
+        let old_pos = match rec_type {
+            // 加载或者写入时已经过期的数据不需要加入索引
+            LogRecordType::NORMAL if pos.is_expired() => {
+                self.add_dead_bytes(pos);
+                self.index.delete(key)
+            }
+            LogRecordType::NORMAL => self.index.put(key, pos),
+            // 删除标记本身也不需要在 merge 时保留
+            LogRecordType::DELETED => {
+                self.add_dead_bytes(pos);
+                self.index.delete(key)
+            }
+            // 为快照保留的旧版本在重启之后没有快照可以读取
+            LogRecordType::BATCHFINISHED | LogRecordType::SNAPSHOT => {
+                self.add_dead_bytes(pos);
+                None
+            }
+        };
+
+        // 被覆盖或删除的旧数据成为失效数据
+        if let Some(old_pos) = old_pos {
+            self.add_dead_bytes(old_pos);
+        }
+    }
+}
+
+impl Drop for Engine {
+    fn drop(&mut self) {
+        if let Err(e) = self.close() {
+            error!("failed to close database: {}", e);
+        }
+    }
+}
+
+// 记录损坏或者写入不完整, 可以根据 RecoveryPolicy 丢弃
+pub(crate) fn is_corrupted(e: &Errors) -> bool {
+    matches!(e, Errors::IncompleteLogRecord | Errors::InvalidLogRecordCrc)
+}
+
+// 统计数据目录中所有文件的大小
+fn dir_disk_size(fs: &dyn FileSystem, dir_path: &Path) -> Result<u64> {
+    let names = match fs.list(dir_path) {
+        Ok(names) => names,
+        Err(e) => {
+            warn!("failed to read database directory: {}", e);
+            return Err(Errors::FailedToReadDatabaseDir);
+        }
+    };
+
+    let mut size = 0;
+    for name in names {
+        let path = dir_path.join(name);
+        if fs.is_file(&path)
+            && let Ok(len) = fs.file_size(&path)
+        {
+            size += len;
+        }
+    }
+    Ok(size)
+}
+
+// 读取关闭数据库时保存的序列号, 文件不存在时返回 NON_BATCH_SEQ_NO
+// 将序列号写入到数据目录的序列号文件中, 覆盖之前保存的值
+pub(crate) fn save_seq_no(fs: &dyn FileSystem, dir_path: &Path, seq_no: u64) -> Result<()> {
+    let seq_no_path = dir_path.join(SEQ_NO_FILE_NAME);
+    if fs.is_file(&seq_no_path)
+        && let Err(e) = fs.remove_file(&seq_no_path)
+    {
+        error!("failed to remove seq no file: {}", e);
+        return Err(Errors::FailedToWriteToDataFile);
+    }
+
+    let seq_no_file = DataFile::new_seq_no_file(fs, dir_path.to_path_buf())?;
+    let mut value = BytesMut::with_capacity(8);
+    value.put_u64(seq_no);
+    let mut record = LogRecord::new(SEQ_NO_KEY.to_vec(), value.to_vec());
+    seq_no_file.write(&record.encode())?;
+    seq_no_file.sync()
+}
+
+fn load_seq_no(fs: &dyn FileSystem, dir_path: &Path) -> Result<u64> {
+    if !fs.is_file(&dir_path.join(SEQ_NO_FILE_NAME)) {
+        return Ok(NON_BATCH_SEQ_NO);
+    }
+
+    let seq_no_file = DataFile::open_seq_no_file(fs, dir_path.to_path_buf())?;
+    let record = seq_no_file.read_log_record(DATA_FILE_HEADER_SIZE)?.record;
+    if record.key != SEQ_NO_KEY || record.value.len() != 8 {
+        return Err(Errors::DataDirectoryCorrupted);
+    }
+
+    Ok(record.value.as_slice().get_u64())
 }
 
 // 从数据目录中加载数据文件
-fn load_data_files(dir_path: PathBuf) -> Result<Vec<DataFile>> {
-    match fs::read_dir(dir_path.clone()) {
-        Ok(dir) => {
+// 最后一个文件会作为活跃文件继续写入, 总是使用标准文件 IO 打开
+// 最后一个文件只写入了部分头部时按照写入中断处理, 除了 Fail 策略都会重新写入头部并记录到 discarded 中
+fn load_data_files(
+    fs: &dyn FileSystem,
+    dir_path: PathBuf,
+    options: &Options,
+    cipher: Option<Arc<Cipher>>,
+    discarded: &mut Vec<DiscardedData>,
+) -> Result<Vec<DataFile>> {
+    match fs.list(&dir_path) {
+        Ok(file_names) => {
             let mut file_ids = Vec::new();
             let mut data_files = Vec::new();
-            for entry in dir.into_iter().flatten() {
-                // 拿到文件名
-                if let Some(file_name) = entry.file_name().to_str() {
-                    // 判断文件名后缀是否为 .data
-                    if file_name.ends_with(DATA_FILE_SUFFIX) {
-                        // 00001.data
-                        let split_file_name: Vec<&str> = file_name.split(".").collect();
-                        let file_id = match split_file_name[0].parse::<u32>() {
-                            Ok(fid) => fid,
-                            Err(_) => {
-                                return Err(Errors::DataDirectoryCorrupted);
-                            }
-                        };
-                        file_ids.push(file_id);
-                    }
+            for file_name in file_names {
+                // 判断文件名后缀是否为 .data
+                if file_name.ends_with(DATA_FILE_SUFFIX) {
+                    // 00001.data
+                    let split_file_name: Vec<&str> = file_name.split(".").collect();
+                    let file_id = match split_file_name[0].parse::<u32>() {
+                        Ok(fid) => fid,
+                        Err(_) => {
+                            return Err(Errors::DataDirectoryCorrupted);
+                        }
+                    };
+                    file_ids.push(file_id);
                 }
             }
 
@@ -297,8 +830,31 @@ fn load_data_files(dir_path: PathBuf) -> Result<Vec<DataFile>> {
             // 对文件 id 进行排序
             file_ids.sort_unstable();
             // 遍历文件id, 依次打开对应的数据文件
+            let active_file_id = file_ids[file_ids.len() - 1];
             for file_id in file_ids {
-                data_files.push(DataFile::new(dir_path.clone(), file_id)?);
+                let data_file = match options.mmap_older_files && file_id != active_file_id {
+                    true => DataFile::open_mmap(fs, dir_path.clone(), file_id)?,
+                    false => match DataFile::open(fs, dir_path.clone(), file_id) {
+                        Err(Errors::InvalidDataFileHeader)
+                            if file_id == active_file_id
+                                && options.recovery_policy != RecoveryPolicy::Fail =>
+                        {
+                            let (data_file, size) =
+                                DataFile::open_torn_header(fs, dir_path.clone(), file_id)?;
+                            warn!("rewrite incomplete header of data file {}", file_id);
+                            discarded.push(DiscardedData {
+                                file_id,
+                                offset: 0,
+                                size,
+                                reason: Errors::InvalidDataFileHeader,
+                                truncated: true,
+                            });
+                            data_file
+                        }
+                        res => res?,
+                    },
+                };
+                data_files.push(data_file.with_cipher(cipher.clone()));
             }
             Ok(data_files)
         }
@@ -309,7 +865,7 @@ fn load_data_files(dir_path: PathBuf) -> Result<Vec<DataFile>> {
     }
 }
 
-fn check_options(opts: &Options) -> Option<Errors> {
+pub(crate) fn check_options(opts: &Options) -> Option<Errors> {
     let dir_path = opts.dir_path.to_str();
     if let Some(size) = dir_path {
         if size.is_empty() {
@@ -323,5 +879,1120 @@ fn check_options(opts: &Options) -> Option<Errors> {
         return Some(Errors::DataFileSizeTooSmall);
     }
 
+    match opts.sync_policy {
+        SyncPolicy::EveryNBytes(0) => return Some(Errors::InvalidSyncPolicy),
+        SyncPolicy::Interval(interval) if interval.is_zero() => {
+            return Some(Errors::InvalidSyncPolicy);
+        }
+        _ => {}
+    }
+
+    let compaction = &opts.compaction;
+    if compaction.check_interval.is_zero()
+        || compaction.dead_ratio_threshold <= 0.0
+        || compaction.dead_ratio_threshold > 1.0
+    {
+        return Some(Errors::InvalidCompactionOptions);
+    }
+
+    // 旧密钥只用于解密, 必须同时配置当前密钥
+    if opts.encryption_key.is_none() && !opts.old_encryption_keys.is_empty() {
+        return Some(Errors::InvalidEncryptionKey);
+    }
+
     None
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{IndexType, IoManger, IoType, MemoryFileSystem, data::get_data_file_name};
+    use std::fs;
+
+    #[test]
+    fn engine_reopen_should_keep_data() {
+        let opts = Options {
+            dir_path: PathBuf::from("/tmp/bitcask-engine-reopen"),
+            ..Default::default()
+        };
+        let _ = fs::remove_dir_all(&opts.dir_path);
+
+        let engine = Engine::open(opts.clone()).expect("failed to open engine");
+        engine
+            .put(Bytes::from("name"), Bytes::from("bitcask"))
+            .unwrap();
+        engine
+            .put(Bytes::from("lang"), Bytes::from("rust"))
+            .unwrap();
+        engine.delete(Bytes::from("lang")).unwrap();
+        drop(engine);
+
+        // 重启之后之前写入的数据仍然可以读取
+        let engine = Engine::open(opts.clone()).expect("failed to reopen engine");
+        assert_eq!(
+            engine.get(Bytes::from("name")).unwrap(),
+            Bytes::from("bitcask")
+        );
+        assert_eq!(
+            engine.get(Bytes::from("lang")).err().unwrap(),
+            Errors::KeyNotFound
+        );
+
+        // 重启之后继续写入, 不会覆盖已有的数据
+        engine.put(Bytes::from("db"), Bytes::from("kv")).unwrap();
+        drop(engine);
+
+        let engine = Engine::open(opts.clone()).expect("failed to reopen engine");
+        assert_eq!(
+            engine.get(Bytes::from("name")).unwrap(),
+            Bytes::from("bitcask")
+        );
+        assert_eq!(engine.get(Bytes::from("db")).unwrap(), Bytes::from("kv"));
+
+        fs::remove_dir_all(opts.dir_path).expect("failed to remove path");
+    }
+
+    #[test]
+    fn engine_reopen_with_multiple_files_should_work() {
+        let opts = Options {
+            dir_path: PathBuf::from("/tmp/bitcask-engine-reopen-files"),
+            data_file_size: 64,
+            ..Default::default()
+        };
+        let _ = fs::remove_dir_all(&opts.dir_path);
+
+        let engine = Engine::open(opts.clone()).expect("failed to open engine");
+        for i in 0..100 {
+            let key = Bytes::from(format!("key-{:03}", i));
+            let value = Bytes::from(format!("value-{:03}", i));
+            engine.put(key, value).unwrap();
+        }
+        assert!(engine.older_files.read().len() > 1);
+        drop(engine);
+
+        let engine = Engine::open(opts.clone()).expect("failed to reopen engine");
+        for i in 0..100 {
+            let key = Bytes::from(format!("key-{:03}", i));
+            let value = Bytes::from(format!("value-{:03}", i));
+            assert_eq!(engine.get(key).unwrap(), value);
+        }
+
+        // 活跃文件是 id 最大的文件, 写入从其末尾继续
+        let active_fid = engine.active_file.read().get_file_id();
+        assert_eq!(Some(&active_fid), engine.file_ids.iter().max());
+        engine
+            .put(Bytes::from("key-new"), Bytes::from("value-new"))
+            .unwrap();
+        drop(engine);
+
+        let engine = Engine::open(opts.clone()).expect("failed to reopen engine");
+        assert_eq!(
+            engine.get(Bytes::from("key-new")).unwrap(),
+            Bytes::from("value-new")
+        );
+        assert_eq!(
+            engine.get(Bytes::from("key-000")).unwrap(),
+            Bytes::from("value-000")
+        );
+
+        fs::remove_dir_all(opts.dir_path).expect("failed to remove path");
+    }
+
+    #[test]
+    fn engine_with_skiplist_index_should_work() {
+        let opts = Options {
+            dir_path: PathBuf::from("/tmp/bitcask-engine-skiplist"),
+            index_type: IndexType::SkipList,
+            ..Default::default()
+        };
+        let _ = fs::remove_dir_all(&opts.dir_path);
+
+        let engine = Engine::open(opts.clone()).expect("failed to open engine");
+        engine
+            .put(Bytes::from("name"), Bytes::from("bitcask"))
+            .unwrap();
+        engine
+            .put(Bytes::from("lang"), Bytes::from("rust"))
+            .unwrap();
+        engine.delete(Bytes::from("lang")).unwrap();
+        drop(engine);
+
+        let engine = Engine::open(opts.clone()).expect("failed to reopen engine");
+        assert_eq!(
+            engine.get(Bytes::from("name")).unwrap(),
+            Bytes::from("bitcask")
+        );
+        assert_eq!(
+            engine.get(Bytes::from("lang")).err().unwrap(),
+            Errors::KeyNotFound
+        );
+
+        fs::remove_dir_all(opts.dir_path).expect("failed to remove path");
+    }
+
+    #[test]
+    fn engine_put_overwrite_should_track_dead_bytes() {
+        let opts = Options {
+            dir_path: PathBuf::from("/tmp/bitcask-engine-overwrite"),
+            ..Default::default()
+        };
+        let _ = fs::remove_dir_all(&opts.dir_path);
+
+        let engine = Engine::open(opts.clone()).expect("failed to open engine");
+        engine.put(Bytes::from("name"), Bytes::from("v1")).unwrap();
+        assert!(engine.dead_bytes.read().clone().is_empty());
+
+        // 重复写入同一个 key 会覆盖旧值
+        engine.put(Bytes::from("name"), Bytes::from("v2")).unwrap();
+        assert_eq!(engine.get(Bytes::from("name")).unwrap(), Bytes::from("v2"));
+
+        // 组提交写入的记录带有提交时间
+        let record_size = LogRecord {
+            commit_at: now_millis(),
+            ..LogRecord::new(b"name".to_vec(), b"v1".to_vec())
+        }
+        .encode()
+        .len() as u64;
+        assert_eq!(engine.dead_bytes.read().clone().get(&0), Some(&record_size));
+
+        // 删除之后, 旧值以及删除标记都是失效数据
+        engine.delete(Bytes::from("name")).unwrap();
+        let tombstone_size = LogRecord {
+            key: b"name".to_vec(),
+            value: Default::default(),
+            rec_type: LogRecordType::DELETED,
+            seq_no: NON_BATCH_SEQ_NO,
+            expire_at: NEVER_EXPIRE,
+            commit_at: now_millis(),
+        }
+        .encode()
+        .len() as u64;
+        let dead_bytes = engine.dead_bytes.read().clone();
+        assert_eq!(
+            dead_bytes.get(&0),
+            Some(&(record_size * 2 + tombstone_size))
+        );
+        drop(engine);
+
+        // 重启之后重放数据文件, 得到相同的结果
+        let engine = Engine::open(opts.clone()).expect("failed to reopen engine");
+        assert_eq!(
+            engine.get(Bytes::from("name")).err().unwrap(),
+            Errors::KeyNotFound
+        );
+        assert_eq!(engine.dead_bytes.read().clone(), dead_bytes);
+
+        engine.put(Bytes::from("name"), Bytes::from("v3")).unwrap();
+        assert_eq!(engine.get(Bytes::from("name")).unwrap(), Bytes::from("v3"));
+
+        fs::remove_dir_all(opts.dir_path).expect("failed to remove path");
+    }
+
+    #[test]
+    fn engine_open_locked_dir_should_fail() {
+        let opts = Options {
+            dir_path: PathBuf::from("/tmp/bitcask-engine-flock"),
+            ..Default::default()
+        };
+        let _ = fs::remove_dir_all(&opts.dir_path);
+
+        let engine = Engine::open(opts.clone()).expect("failed to open engine");
+
+        // 目录已经被使用, 再次打开失败
+        let res = Engine::open(opts.clone());
+        assert_eq!(res.err().unwrap(), Errors::DatabaseIsUsing);
+
+        // 释放之后可以重新打开
+        drop(engine);
+        let engine = Engine::open(opts.clone());
+        assert!(engine.is_ok());
+
+        fs::remove_dir_all(opts.dir_path).expect("failed to remove path");
+    }
+
+    #[test]
+    fn engine_close_should_sync_and_release_lock() {
+        let opts = Options {
+            dir_path: PathBuf::from("/tmp/bitcask-engine-close"),
+            sync_policy: SyncPolicy::Never,
+            ..Default::default()
+        };
+        let _ = fs::remove_dir_all(&opts.dir_path);
+
+        let engine = Engine::open(opts.clone()).expect("failed to open engine");
+        let wb = engine
+            .new_write_batch(crate::WriteBatchOptions::default())
+            .unwrap();
+        wb.put(Bytes::from("a"), Bytes::from("a-value")).unwrap();
+        wb.commit().unwrap();
+        drop(wb);
+        engine
+            .put(Bytes::from("b"), Bytes::from("b-value"))
+            .unwrap();
+
+        // merge 之后数据文件中不再有序列号
+        engine.merge().expect("failed to merge");
+        engine.close().expect("failed to close engine");
+        // 重复关闭直接返回
+        assert!(engine.close().is_ok());
+
+        // 关闭之后不能再读写
+        assert_eq!(
+            engine.put(Bytes::from("c"), Bytes::from("c-value")).err(),
+            Some(Errors::EngineClosed)
+        );
+        assert_eq!(
+            engine.delete(Bytes::from("b")).err(),
+            Some(Errors::EngineClosed)
+        );
+        assert_eq!(
+            engine.get(Bytes::from("a")).err(),
+            Some(Errors::EngineClosed)
+        );
+        assert_eq!(
+            engine
+                .new_write_batch(crate::WriteBatchOptions::default())
+                .err(),
+            Some(Errors::EngineClosed)
+        );
+        assert_eq!(engine.merge().err(), Some(Errors::EngineClosed));
+
+        // 关闭之后文件锁已经释放, 可以再次打开
+        let engine2 = Engine::open(opts.clone()).expect("failed to reopen engine");
+        assert_eq!(
+            engine2.get(Bytes::from("a")).unwrap(),
+            Bytes::from("a-value")
+        );
+        assert_eq!(
+            engine2.get(Bytes::from("b")).unwrap(),
+            Bytes::from("b-value")
+        );
+        assert_eq!(engine2.seq_no.load(Ordering::SeqCst), 1);
+
+        drop(engine2);
+        drop(engine);
+        fs::remove_dir_all(opts.dir_path).expect("failed to remove path");
+    }
+
+    #[test]
+    fn engine_failed_close_should_release_lock() {
+        let fs = MemoryFileSystem::new();
+        let opts = memory_options(&fs, "/tmp/bitcask-engine-close-failed");
+
+        let engine = Engine::open(opts.clone()).expect("failed to open engine");
+        engine.put(Bytes::from("a"), Bytes::from("value")).unwrap();
+
+        // 序列号文件的位置被目录占用, 保存序列号失败
+        let seq_no_path = opts.dir_path.join(SEQ_NO_FILE_NAME);
+        fs.create_dir_all(&seq_no_path).unwrap();
+        assert_eq!(engine.close().err(), Some(Errors::FailedToOpenDataFile));
+
+        // 关闭失败时文件锁同样被释放, 可以重新打开
+        fs.remove_dir_all(&seq_no_path).unwrap();
+        let engine2 = Engine::open(opts.clone()).expect("failed to reopen engine");
+        assert_eq!(engine2.get(Bytes::from("a")).unwrap(), Bytes::from("value"));
+        drop(engine2);
+        drop(engine);
+    }
+
+    #[test]
+    fn engine_stat_should_work() {
+        let opts = Options {
+            dir_path: PathBuf::from("/tmp/bitcask-engine-stat"),
+            data_file_size: 64,
+            ..Default::default()
+        };
+        let _ = fs::remove_dir_all(&opts.dir_path);
+
+        let engine = Engine::open(opts.clone()).expect("failed to open engine");
+        let stat = engine.stat().unwrap();
+        assert_eq!(stat.key_num, 0);
+        assert_eq!(stat.data_file_num, 1);
+        assert_eq!(stat.reclaimable_size, 0);
+
+        for i in 0..10 {
+            engine
+                .put(Bytes::from(format!("key-{}", i)), Bytes::from("value"))
+                .unwrap();
+        }
+        engine
+            .put(Bytes::from("key-0"), Bytes::from("new"))
+            .unwrap();
+        engine.delete(Bytes::from("key-1")).unwrap();
+
+        let stat = engine.stat().unwrap();
+        assert_eq!(stat.key_num, 9);
+        assert!(stat.data_file_num > 1);
+        assert!(stat.reclaimable_size > 0);
+        assert_eq!(
+            stat.reclaimable_size,
+            stat.reclaimable_size_per_file.values().sum::<u64>()
+        );
+        assert!(stat.disk_size > stat.reclaimable_size);
+
+        fs::remove_dir_all(opts.dir_path).expect("failed to remove path");
+    }
+
+    #[test]
+    fn engine_sync_policy_should_work() {
+        let opts = Options {
+            dir_path: PathBuf::from("/tmp/bitcask-engine-sync-policy"),
+            sync_policy: SyncPolicy::EveryNBytes(64),
+            ..Default::default()
+        };
+        let _ = fs::remove_dir_all(&opts.dir_path);
+
+        // 无效的持久化策略
+        for sync_policy in [
+            SyncPolicy::EveryNBytes(0),
+            SyncPolicy::Interval(std::time::Duration::ZERO),
+        ] {
+            let res = Engine::open(Options {
+                sync_policy,
+                ..opts.clone()
+            });
+            assert_eq!(res.err().unwrap(), Errors::InvalidSyncPolicy);
+        }
+
+        // 累计写入达到阈值之后持久化
+        let engine = Engine::open(opts.clone()).expect("failed to open engine");
+        engine.put(Bytes::from("a"), Bytes::from("value")).unwrap();
+        let bytes = engine.bytes_since_sync.load(Ordering::SeqCst);
+        assert!(bytes > 0 && bytes < 64);
+        engine
+            .put(Bytes::from("b"), Bytes::from("x".repeat(64)))
+            .unwrap();
+        assert_eq!(engine.bytes_since_sync.load(Ordering::SeqCst), 0);
+
+        // 手动持久化
+        engine.put(Bytes::from("c"), Bytes::from("value")).unwrap();
+        engine.sync().unwrap();
+        assert_eq!(engine.bytes_since_sync.load(Ordering::SeqCst), 0);
+        drop(engine);
+
+        // 后台定时持久化
+        let engine = Engine::open(Options {
+            sync_policy: SyncPolicy::Interval(std::time::Duration::from_millis(10)),
+            ..opts.clone()
+        })
+        .expect("failed to open engine");
+        assert!(engine.flusher.is_some());
+        engine.put(Bytes::from("d"), Bytes::from("value")).unwrap();
+        std::thread::sleep(std::time::Duration::from_millis(50));
+        engine.close().unwrap();
+        drop(engine);
+
+        let engine = Engine::open(opts.clone()).expect("failed to reopen engine");
+        for key in ["a", "b", "c", "d"] {
+            assert!(engine.get(Bytes::from(key)).is_ok());
+        }
+
+        fs::remove_dir_all(opts.dir_path).expect("failed to remove path");
+    }
+
+    #[test]
+    fn engine_with_mmap_older_files_should_work() {
+        let opts = Options {
+            dir_path: PathBuf::from("/tmp/bitcask-engine-mmap"),
+            data_file_size: 64,
+            mmap_older_files: true,
+            ..Default::default()
+        };
+        let _ = fs::remove_dir_all(&opts.dir_path);
+
+        let engine = Engine::open(opts.clone()).expect("failed to open engine");
+        for i in 0..20 {
+            engine
+                .put(Bytes::from(format!("key-{}", i)), Bytes::from("value"))
+                .unwrap();
+        }
+        engine.delete(Bytes::from("key-0")).unwrap();
+        drop(engine);
+
+        // 重启之后旧的数据文件使用 mmap 加载, 活跃文件仍然可以写入
+        let engine = Engine::open(opts.clone()).expect("failed to reopen engine");
+        assert!(engine.older_files.read().len() > 1);
+        for i in 1..20 {
+            assert_eq!(
+                engine.get(Bytes::from(format!("key-{}", i))).unwrap(),
+                Bytes::from("value")
+            );
+        }
+        engine
+            .put(Bytes::from("key-0"), Bytes::from("new"))
+            .unwrap();
+        assert_eq!(
+            engine.get(Bytes::from("key-0")).unwrap(),
+            Bytes::from("new")
+        );
+
+        // merge 之后的数据文件同样使用 mmap 打开
+        engine.merge().expect("failed to merge");
+        assert_eq!(
+            engine.get(Bytes::from("key-0")).unwrap(),
+            Bytes::from("new")
+        );
+        assert_eq!(engine.list_keys().unwrap().len(), 20);
+
+        drop(engine);
+        fs::remove_dir_all(opts.dir_path).expect("failed to remove path");
+    }
+
+    #[test]
+    fn engine_expired_keys_should_count_as_dead_bytes() {
+        let fs = MemoryFileSystem::new();
+        let opts = memory_options(&fs, "/tmp/bitcask-engine-ttl-dead-bytes");
+
+        let engine = Engine::open(opts.clone()).expect("failed to open engine");
+        engine
+            .put_with_ttl(
+                Bytes::from("a"),
+                Bytes::from("value"),
+                Duration::from_millis(50),
+            )
+            .unwrap();
+        engine.put(Bytes::from("b"), Bytes::from("value")).unwrap();
+        let pos = engine.index.get(b"a".to_vec()).unwrap();
+        std::thread::sleep(Duration::from_millis(100));
+
+        // 过期的 key 不计入 key 的数量, 过期的数据成为失效数据
+        let stat = engine.stat().unwrap();
+        assert_eq!(stat.key_num, 1);
+        assert_eq!(stat.reclaimable_size, pos.get_size() as u64);
+
+        // 删除过期的 key 不写入删除标记, 重复读取不会重复统计
+        let write_off = engine.active_file.read().get_write_off();
+        engine.delete(Bytes::from("a")).unwrap();
+        let wb = engine
+            .new_write_batch(crate::WriteBatchOptions::default())
+            .unwrap();
+        wb.delete(Bytes::from("a")).unwrap();
+        wb.commit().unwrap();
+        assert_eq!(engine.active_file.read().get_write_off(), write_off);
+        assert_eq!(
+            engine.get(Bytes::from("a")).err(),
+            Some(Errors::KeyNotFound)
+        );
+        assert_eq!(
+            engine.stat().unwrap().reclaimable_size,
+            pos.get_size() as u64
+        );
+        drop(engine);
+
+        // 重启之后加载时同样作为失效数据统计
+        let engine = Engine::open(opts.clone()).expect("failed to reopen engine");
+        let stat = engine.stat().unwrap();
+        assert_eq!(stat.key_num, 1);
+        assert_eq!(stat.reclaimable_size, pos.get_size() as u64);
+    }
+
+    #[test]
+    fn engine_put_with_huge_ttl_should_not_expire() {
+        let opts = Options {
+            dir_path: PathBuf::from("/tmp/bitcask-engine-huge-ttl"),
+            ..Default::default()
+        };
+        let _ = fs::remove_dir_all(&opts.dir_path);
+
+        let engine = Engine::open(opts.clone()).expect("failed to open engine");
+        engine
+            .put_with_ttl(Bytes::from("max"), Bytes::from("value"), Duration::MAX)
+            .unwrap();
+
+        assert_eq!(
+            engine.get(Bytes::from("max")).unwrap(),
+            Bytes::from("value")
+        );
+        let ttl = engine.ttl(Bytes::from("max")).unwrap().unwrap();
+        assert!(ttl > Duration::from_secs(100 * 365 * 24 * 3600));
+
+        // 重新打开之后仍然没有过期
+        drop(engine);
+        let engine = Engine::open(opts.clone()).expect("failed to reopen engine");
+        assert_eq!(
+            engine.get(Bytes::from("max")).unwrap(),
+            Bytes::from("value")
+        );
+
+        drop(engine);
+        fs::remove_dir_all(opts.dir_path).expect("failed to remove path");
+    }
+
+    #[test]
+    fn engine_put_with_ttl_should_expire() {
+        let opts = Options {
+            dir_path: PathBuf::from("/tmp/bitcask-engine-ttl"),
+            ..Default::default()
+        };
+        let _ = fs::remove_dir_all(&opts.dir_path);
+
+        let engine = Engine::open(opts.clone()).expect("failed to open engine");
+        engine
+            .put(Bytes::from("forever"), Bytes::from("value"))
+            .unwrap();
+        engine
+            .put_with_ttl(
+                Bytes::from("short"),
+                Bytes::from("value"),
+                Duration::from_millis(100),
+            )
+            .unwrap();
+        engine
+            .put_with_ttl(
+                Bytes::from("long"),
+                Bytes::from("value"),
+                Duration::from_secs(3600),
+            )
+            .unwrap();
+
+        assert_eq!(engine.ttl(Bytes::from("forever")).unwrap(), None);
+        let ttl = engine.ttl(Bytes::from("long")).unwrap().unwrap();
+        assert!(ttl > Duration::from_secs(3500) && ttl <= Duration::from_secs(3600));
+        assert!(engine.get(Bytes::from("short")).is_ok());
+        assert_eq!(
+            engine.ttl(Bytes::from("missing")).err(),
+            Some(Errors::KeyNotFound)
+        );
+
+        std::thread::sleep(Duration::from_millis(150));
+
+        // 过期之后和被删除的数据一样处理
+        assert_eq!(
+            engine.get(Bytes::from("short")).err(),
+            Some(Errors::KeyNotFound)
+        );
+        assert_eq!(
+            engine.ttl(Bytes::from("short")).err(),
+            Some(Errors::KeyNotFound)
+        );
+        assert_eq!(
+            engine.list_keys().unwrap(),
+            vec![Bytes::from("forever"), Bytes::from("long")]
+        );
+        assert_eq!(engine.iter(IteratorOptions::default()).count(), 2);
+        drop(engine);
+
+        // 重启之后过期时间仍然有效
+        let engine = Engine::open(opts.clone()).expect("failed to reopen engine");
+        assert_eq!(
+            engine.get(Bytes::from("short")).err(),
+            Some(Errors::KeyNotFound)
+        );
+        assert!(engine.ttl(Bytes::from("long")).unwrap().is_some());
+
+        // merge 之后过期的数据被清理, 过期时间保留在 hint 文件中
+        engine.merge().expect("failed to merge");
+        assert_eq!(engine.stat().unwrap().key_num, 2);
+        drop(engine);
+        let engine = Engine::open(opts.clone()).expect("failed to reopen engine");
+        assert_eq!(engine.stat().unwrap().key_num, 2);
+        assert!(engine.ttl(Bytes::from("long")).unwrap().is_some());
+        assert_eq!(engine.ttl(Bytes::from("forever")).unwrap(), None);
+
+        fs::remove_dir_all(opts.dir_path).expect("failed to remove path");
+    }
+
+    #[test]
+    fn engine_with_mixed_compression_should_work() {
+        let opts = Options {
+            dir_path: PathBuf::from("/tmp/bitcask-engine-compression"),
+            ..Default::default()
+        };
+        let _ = fs::remove_dir_all(&opts.dir_path);
+
+        let value = Bytes::from("{\"name\": \"bitcask\"}".repeat(32));
+        let compressions = [
+            crate::CompressionType::None,
+            crate::CompressionType::Lz4,
+            crate::CompressionType::Zstd,
+        ];
+
+        // 同一个数据目录中写入不同压缩类型的数据
+        let mut sizes = Vec::new();
+        for (i, compression) in compressions.iter().enumerate() {
+            let engine = Engine::open(Options {
+                compression: *compression,
+                ..opts.clone()
+            })
+            .expect("failed to open engine");
+            let before = engine.active_file.read().get_write_off();
+            engine
+                .put(Bytes::from(format!("key-{}", i)), value.clone())
+                .unwrap();
+            sizes.push(engine.active_file.read().get_write_off() - before);
+        }
+        assert!(sizes[1] < sizes[0] && sizes[2] < sizes[0]);
+
+        // 不论当前配置如何, 所有的数据都可以读取
+        let engine = Engine::open(opts.clone()).expect("failed to reopen engine");
+        for i in 0..compressions.len() {
+            assert_eq!(
+                engine.get(Bytes::from(format!("key-{}", i))).unwrap(),
+                value
+            );
+        }
+        engine.merge().expect("failed to merge");
+        for i in 0..compressions.len() {
+            assert_eq!(
+                engine.get(Bytes::from(format!("key-{}", i))).unwrap(),
+                value
+            );
+        }
+
+        drop(engine);
+        fs::remove_dir_all(opts.dir_path).expect("failed to remove path");
+    }
+
+    #[test]
+    fn engine_with_encryption_key_should_work() {
+        let opts = Options {
+            dir_path: PathBuf::from("/tmp/bitcask-engine-encryption"),
+            encryption_key: Some([1; crate::ENCRYPTION_KEY_SIZE]),
+            ..Default::default()
+        };
+        let _ = fs::remove_dir_all(&opts.dir_path);
+
+        let engine = Engine::open(opts.clone()).expect("failed to open engine");
+        engine
+            .put(Bytes::from("name"), Bytes::from("plaintext-value"))
+            .unwrap();
+        assert_eq!(
+            engine.get(Bytes::from("name")).unwrap(),
+            Bytes::from("plaintext-value")
+        );
+        drop(engine);
+
+        // 数据文件中没有明文的 value
+        let data = fs::read(crate::data::get_data_file_name(
+            &opts.dir_path,
+            INITIAL_FILE_ID,
+        ))
+        .unwrap();
+        assert!(!data.windows(15).any(|w| w == b"plaintext-value"));
+
+        // 没有密钥或者密钥错误时无法打开
+        let res = Engine::open(Options {
+            encryption_key: None,
+            ..opts.clone()
+        });
+        assert_eq!(res.err(), Some(Errors::InvalidEncryptionKey));
+        let res = Engine::open(Options {
+            encryption_key: Some([2; crate::ENCRYPTION_KEY_SIZE]),
+            ..opts.clone()
+        });
+        assert_eq!(res.err(), Some(Errors::InvalidEncryptionKey));
+
+        // 只配置旧密钥
+        let res = Engine::open(Options {
+            encryption_key: None,
+            old_encryption_keys: vec![[1; crate::ENCRYPTION_KEY_SIZE]],
+            ..opts.clone()
+        });
+        assert_eq!(res.err(), Some(Errors::InvalidEncryptionKey));
+
+        let engine = Engine::open(opts.clone()).expect("failed to reopen engine");
+        assert_eq!(
+            engine.get(Bytes::from("name")).unwrap(),
+            Bytes::from("plaintext-value")
+        );
+
+        drop(engine);
+        fs::remove_dir_all(opts.dir_path).expect("failed to remove path");
+    }
+
+    // 翻转数据文件中 offset 处字节的最低位
+    fn flip_bit(fs: &MemoryFileSystem, dir_path: &Path, file_id: u32, offset: u64) {
+        let file_name = get_data_file_name(dir_path, file_id);
+        fs.file(file_name).unwrap().flip_bit(offset);
+    }
+
+    #[test]
+    fn engine_open_with_torn_tail_should_truncate() {
+        let fs = MemoryFileSystem::new();
+        let opts = memory_options(&fs, "/tmp/bitcask-engine-torn-tail");
+
+        let engine = Engine::open(opts.clone()).expect("failed to open engine");
+        engine.put(Bytes::from("a"), Bytes::from("1")).unwrap();
+        engine.put(Bytes::from("b"), Bytes::from("2")).unwrap();
+        drop(engine);
+
+        // 模拟掉电时最后一次写入只写入了一部分
+        let file_name = get_data_file_name(&opts.dir_path, INITIAL_FILE_ID);
+        let valid_size = fs.file(&file_name).unwrap().size().unwrap();
+        let record = LogRecord::new(b"c".to_vec(), b"3".to_vec()).encode();
+        let file = fs.file(&file_name).unwrap();
+        file.write(&record[..record.len() - 2]).unwrap();
+        file.sync().unwrap();
+
+        let res = Engine::open(Options {
+            recovery_policy: RecoveryPolicy::Fail,
+            ..opts.clone()
+        });
+        assert_eq!(res.err(), Some(Errors::IncompleteLogRecord));
+
+        let engine = Engine::open(opts.clone()).expect("failed to reopen engine");
+        assert_eq!(engine.get(Bytes::from("a")).unwrap(), Bytes::from("1"));
+        assert_eq!(engine.get(Bytes::from("b")).unwrap(), Bytes::from("2"));
+        assert_eq!(
+            engine.discarded_data(),
+            &[DiscardedData {
+                file_id: INITIAL_FILE_ID,
+                offset: valid_size,
+                size: record.len() as u64 - 2,
+                reason: Errors::IncompleteLogRecord,
+                truncated: true,
+            }]
+        );
+        assert_eq!(fs.file(&file_name).unwrap().size().unwrap(), valid_size);
+
+        // 截断之后可以继续写入
+        engine.put(Bytes::from("c"), Bytes::from("3")).unwrap();
+        drop(engine);
+
+        let engine = Engine::open(opts.clone()).expect("failed to reopen engine");
+        assert!(engine.discarded_data().is_empty());
+        assert_eq!(engine.get(Bytes::from("c")).unwrap(), Bytes::from("3"));
+    }
+
+    #[test]
+    fn engine_open_with_corrupted_record_should_follow_policy() {
+        let fs = MemoryFileSystem::new();
+        let opts = memory_options(&fs, "/tmp/bitcask-engine-corrupted");
+
+        let engine = Engine::open(opts.clone()).expect("failed to open engine");
+        for key in ["a", "b", "c"] {
+            engine.put(Bytes::from(key), Bytes::from("value")).unwrap();
+        }
+        let pos = engine.index.get(b"b".to_vec()).unwrap();
+        drop(engine);
+
+        // 损坏中间的一条记录
+        let file_name = get_data_file_name(&opts.dir_path, INITIAL_FILE_ID);
+        let file_size = fs.file(&file_name).unwrap().size().unwrap();
+        flip_bit(
+            &fs,
+            &opts.dir_path,
+            INITIAL_FILE_ID,
+            pos.get_offset() + pos.get_size() as u64 - 1,
+        );
+
+        let res = Engine::open(Options {
+            recovery_policy: RecoveryPolicy::Fail,
+            ..opts.clone()
+        });
+        assert_eq!(res.err(), Some(Errors::InvalidLogRecordCrc));
+
+        // 跳过损坏的记录, 之后的记录仍然有效
+        let skip_opts = Options {
+            recovery_policy: RecoveryPolicy::Skip,
+            ..opts.clone()
+        };
+        let engine = Engine::open(skip_opts.clone()).expect("failed to reopen engine");
+        assert_eq!(engine.get(Bytes::from("a")).unwrap(), Bytes::from("value"));
+        assert_eq!(
+            engine.get(Bytes::from("b")).err(),
+            Some(Errors::KeyNotFound)
+        );
+        assert_eq!(engine.get(Bytes::from("c")).unwrap(), Bytes::from("value"));
+        assert_eq!(
+            engine.discarded_data(),
+            &[DiscardedData {
+                file_id: INITIAL_FILE_ID,
+                offset: pos.get_offset(),
+                size: pos.get_size() as u64,
+                reason: Errors::InvalidLogRecordCrc,
+                truncated: false,
+            }]
+        );
+        assert_eq!(fs.file(&file_name).unwrap().size().unwrap(), file_size);
+        drop(engine);
+
+        // 损坏的记录之后还有有效的记录, 截断会丢失已经提交的数据, 打开失败并且不修改数据文件
+        assert_eq!(
+            Engine::open(opts.clone()).err(),
+            Some(Errors::InvalidLogRecordCrc)
+        );
+        assert_eq!(fs.file(&file_name).unwrap().size().unwrap(), file_size);
+
+        // 之后的记录也损坏时截断活跃文件, 损坏的记录之后没有有效的记录
+        let engine = Engine::open(skip_opts.clone()).expect("failed to reopen engine");
+        let last_pos = engine.index.get(b"c".to_vec()).unwrap();
+        drop(engine);
+        flip_bit(
+            &fs,
+            &opts.dir_path,
+            INITIAL_FILE_ID,
+            last_pos.get_offset() + last_pos.get_size() as u64 - 1,
+        );
+        let engine = Engine::open(opts.clone()).expect("failed to reopen engine");
+        assert_eq!(engine.get(Bytes::from("a")).unwrap(), Bytes::from("value"));
+        assert_eq!(
+            engine.get(Bytes::from("c")).err(),
+            Some(Errors::KeyNotFound)
+        );
+        assert_eq!(
+            engine.discarded_data(),
+            &[DiscardedData {
+                file_id: INITIAL_FILE_ID,
+                offset: pos.get_offset(),
+                size: file_size - pos.get_offset(),
+                reason: Errors::InvalidLogRecordCrc,
+                truncated: true,
+            }]
+        );
+        assert_eq!(
+            fs.file(&file_name).unwrap().size().unwrap(),
+            pos.get_offset()
+        );
+    }
+
+    #[test]
+    fn engine_skip_corrupted_older_file_should_merge() {
+        let fs = MemoryFileSystem::new();
+        let opts = Options {
+            data_file_size: 4 * 1024,
+            recovery_policy: RecoveryPolicy::Skip,
+            ..memory_options(&fs, "/tmp/bitcask-engine-corrupted-older")
+        };
+
+        let engine = Engine::open(opts.clone()).expect("failed to open engine");
+        for i in 0..500 {
+            engine
+                .put(Bytes::from(format!("key-{}", i)), Bytes::from("value"))
+                .unwrap();
+        }
+        let pos = engine.index.get(b"key-0".to_vec()).unwrap();
+        drop(engine);
+        flip_bit(
+            &fs,
+            &opts.dir_path,
+            pos.get_file_id(),
+            pos.get_offset() + pos.get_size() as u64 - 1,
+        );
+
+        // 只有活跃文件可以截断, 旧的数据文件损坏时打开失败
+        let res = Engine::open(Options {
+            recovery_policy: RecoveryPolicy::Truncate,
+            ..opts.clone()
+        });
+        assert_eq!(res.err(), Some(Errors::InvalidLogRecordCrc));
+
+        let engine = Engine::open(opts.clone()).expect("failed to reopen engine");
+        assert_eq!(engine.discarded_data().len(), 1);
+        assert_eq!(
+            engine.get(Bytes::from("key-0")).err(),
+            Some(Errors::KeyNotFound)
+        );
+        assert_eq!(engine.stat().unwrap().key_num, 499);
+
+        // merge 时同样跳过损坏的数据
+        engine.merge().expect("failed to merge");
+        drop(engine);
+
+        let engine = Engine::open(opts.clone()).expect("failed to reopen engine");
+        assert!(engine.discarded_data().is_empty());
+        assert_eq!(engine.stat().unwrap().key_num, 499);
+        assert_eq!(
+            engine.get(Bytes::from("key-1")).unwrap(),
+            Bytes::from("value")
+        );
+    }
+
+    fn memory_options(fs: &MemoryFileSystem, dir_path: &str) -> Options {
+        Options {
+            dir_path: PathBuf::from(dir_path),
+            io_type: IoType::Memory(fs.clone()),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn engine_failed_write_should_not_be_visible_after_reopen() {
+        let fs = MemoryFileSystem::new();
+        let opts = memory_options(&fs, "/tmp/bitcask-engine-fault-write");
+
+        let engine = Engine::open(opts.clone()).expect("failed to open engine");
+        for i in 0..10 {
+            engine
+                .put(Bytes::from(format!("key-{}", i)), Bytes::from("value"))
+                .unwrap();
+        }
+
+        // 第 1 次 write 是数据文件头部, 之后每次 put 一次 write
+        let file = fs
+            .file(get_data_file_name(&opts.dir_path, INITIAL_FILE_ID))
+            .unwrap();
+        file.fail_nth_write(12);
+        file.short_nth_write(13, 5);
+        assert_eq!(
+            engine
+                .put(Bytes::from("key-10"), Bytes::from("value"))
+                .err(),
+            Some(Errors::FailedToWriteToDataFile)
+        );
+        assert_eq!(
+            engine
+                .put(Bytes::from("key-11"), Bytes::from("value"))
+                .err(),
+            Some(Errors::FailedToWriteToDataFile)
+        );
+
+        // 写入不完整的数据已经被撤销, 之后的写入可以正常读取
+        engine
+            .put(Bytes::from("key-12"), Bytes::from("value"))
+            .unwrap();
+        drop(engine);
+
+        let engine = Engine::open(opts.clone()).expect("failed to reopen engine");
+        assert!(engine.discarded_data().is_empty());
+        for i in [10, 11] {
+            assert_eq!(
+                engine.get(Bytes::from(format!("key-{}", i))).err(),
+                Some(Errors::KeyNotFound)
+            );
+        }
+        for i in (0..10).chain([12]) {
+            assert_eq!(
+                engine.get(Bytes::from(format!("key-{}", i))).unwrap(),
+                Bytes::from("value")
+            );
+        }
+    }
+
+    #[test]
+    fn engine_open_with_torn_header_should_rewrite_header() {
+        let fs = MemoryFileSystem::new();
+        let opts = memory_options(&fs, "/tmp/bitcask-engine-torn-header");
+
+        let engine = Engine::open(opts.clone()).expect("failed to open engine");
+        engine.put(Bytes::from("a"), Bytes::from("1")).unwrap();
+        drop(engine);
+
+        // 模拟切换活跃文件时, 新文件的头部只写入了一部分
+        let file_name = get_data_file_name(&opts.dir_path, INITIAL_FILE_ID + 1);
+        let file = fs.create(&file_name).unwrap();
+        file.write(b"BCSK\0").unwrap();
+        file.sync().unwrap();
+
+        let res = Engine::open(Options {
+            recovery_policy: RecoveryPolicy::Fail,
+            ..opts.clone()
+        });
+        assert_eq!(res.err(), Some(Errors::InvalidDataFileHeader));
+
+        let engine = Engine::open(opts.clone()).expect("failed to reopen engine");
+        assert_eq!(
+            engine.discarded_data(),
+            &[DiscardedData {
+                file_id: INITIAL_FILE_ID + 1,
+                offset: 0,
+                size: 5,
+                reason: Errors::InvalidDataFileHeader,
+                truncated: true,
+            }]
+        );
+        assert_eq!(file.size().unwrap(), DATA_FILE_HEADER_SIZE);
+        assert_eq!(engine.get(Bytes::from("a")).unwrap(), Bytes::from("1"));
+
+        // 重新写入头部之后可以继续写入
+        engine.put(Bytes::from("b"), Bytes::from("2")).unwrap();
+        drop(engine);
+
+        let engine = Engine::open(opts.clone()).expect("failed to reopen engine");
+        assert!(engine.discarded_data().is_empty());
+        assert_eq!(engine.get(Bytes::from("b")).unwrap(), Bytes::from("2"));
+        drop(engine);
+
+        // 不是头部的一部分时不当作写入中断处理
+        let file_name = get_data_file_name(&opts.dir_path, INITIAL_FILE_ID + 2);
+        fs.create(&file_name).unwrap().write(b"XY").unwrap();
+        assert_eq!(
+            Engine::open(opts.clone()).err(),
+            Some(Errors::InvalidDataFileHeader)
+        );
+    }
+
+    #[test]
+    fn engine_power_loss_should_drop_unsynced_data() {
+        let fs = MemoryFileSystem::new();
+        let opts = memory_options(&fs, "/tmp/bitcask-engine-power-loss");
+
+        let engine = Engine::open(opts.clone()).expect("failed to open engine");
+        for i in 0..10 {
+            engine
+                .put(Bytes::from(format!("key-{}", i)), Bytes::from("value"))
+                .unwrap();
+        }
+        engine.sync().unwrap();
+        for i in 10..20 {
+            engine
+                .put(Bytes::from(format!("key-{}", i)), Bytes::from("value"))
+                .unwrap();
+        }
+
+        // 不关闭数据库直接掉电, 目录锁随之释放
+        std::mem::forget(engine);
+        fs.power_loss();
+
+        let engine = Engine::open(opts.clone()).expect("failed to reopen engine");
+        assert!(engine.discarded_data().is_empty());
+        assert_eq!(engine.stat().unwrap().key_num, 10);
+        for i in 0..10 {
+            assert_eq!(
+                engine.get(Bytes::from(format!("key-{}", i))).unwrap(),
+                Bytes::from("value")
+            );
+        }
+        assert_eq!(
+            engine.get(Bytes::from("key-10")).err(),
+            Some(Errors::KeyNotFound)
+        );
+        assert_eq!(
+            Engine::open(opts.clone()).err(),
+            Some(Errors::DatabaseIsUsing)
+        );
+    }
+
+    #[test]
+    fn engine_torn_write_before_power_loss_should_be_truncated() {
+        let fs = MemoryFileSystem::new();
+        let opts = Options {
+            sync_policy: SyncPolicy::Always,
+            ..memory_options(&fs, "/tmp/bitcask-engine-torn-write")
+        };
+
+        let engine = Engine::open(opts.clone()).expect("failed to open engine");
+        for i in 0..10 {
+            engine
+                .put(Bytes::from(format!("key-{}", i)), Bytes::from("value"))
+                .unwrap();
+        }
+
+        // 只有部分记录在掉电之前写入了磁盘
+        let file_name = get_data_file_name(&opts.dir_path, INITIAL_FILE_ID);
+        let file = fs.file(&file_name).unwrap();
+        let valid_size = file.size().unwrap();
+        let mut record = LogRecord::new(b"key-10".to_vec(), b"value".to_vec());
+        file.write(&record.encode()[..8]).unwrap();
+        file.sync().unwrap();
+        std::mem::forget(engine);
+        fs.power_loss();
+
+        let engine = Engine::open(opts.clone()).expect("failed to reopen engine");
+        assert_eq!(engine.discarded_data().len(), 1);
+        assert!(engine.discarded_data()[0].truncated);
+        assert_eq!(file.size().unwrap(), valid_size);
+        assert_eq!(engine.stat().unwrap().key_num, 10);
+
+        // 截断之后可以继续写入
+        engine
+            .put(Bytes::from("key-10"), Bytes::from("value"))
+            .unwrap();
+        drop(engine);
+        let engine = Engine::open(opts.clone()).expect("failed to reopen engine");
+        assert_eq!(
+            engine.get(Bytes::from("key-10")).unwrap(),
+            Bytes::from("value")
+        );
+    }
+}
@@ -1,7 +1,7 @@
 use std::result;
 use thiserror::Error;
 
-#[derive(Debug, Error, PartialEq, Eq)]
+#[derive(Debug, Clone, Error, PartialEq, Eq)]
 pub enum Errors {
     #[error("failed to read from data file")]
     FailedToReadFromDataFile,
@@ -21,6 +21,9 @@ pub enum Errors {
     #[error("index update failed")]
     IndexUpdateFailed,
 
+    #[error("the record at the index position belongs to another key")]
+    IndexKeyMismatch,
+
     #[error("key not found")]
     KeyNotFound,
 
@@ -44,6 +47,89 @@ pub enum Errors {
 
     #[error("read data file eof")]
     ReadDataFileEOF,
+
+    #[error("invalid crc value, log record maybe corrupted")]
+    InvalidLogRecordCrc,
+
+    #[error("incomplete log record, data file maybe torn")]
+    IncompleteLogRecord,
+
+    #[error("invalid data file header")]
+    InvalidDataFileHeader,
+
+    #[error("unsupported data file format version: {0}")]
+    UnsupportedDataFileVersion(u32),
+
+    #[error("invalid sync policy")]
+    InvalidSyncPolicy,
+
+    #[error("unsupported compression type: {0}")]
+    UnsupportedCompressionType(u8),
+
+    #[error("failed to decompress value")]
+    FailedToDecompressValue,
+
+    #[error("the encryption key is missing or wrong")]
+    InvalidEncryptionKey,
+
+    #[error("merge is in progress, try again later")]
+    MergeInProgress,
+
+    #[error("merge is not finished")]
+    MergeNotFinished,
+
+    #[error("failed to swap merge data files")]
+    FailedToSwapMergeFiles,
+
+    #[error("merge is cancelled")]
+    MergeCancelled,
+
+    #[error("invalid compaction options")]
+    InvalidCompactionOptions,
+
+    #[error("background compaction is not enabled in the options")]
+    CompactionNotEnabled,
+
+    #[error("the database directory is used by another process")]
+    DatabaseIsUsing,
+
+    #[error("failed to lock database directory")]
+    FailedToLockDatabaseDir,
+
+    #[error("failed to unlock database directory")]
+    FailedToUnlockDatabaseDir,
+
+    #[error("the database is closed")]
+    EngineClosed,
+
+    #[error(
+        "data files are in an unknown state after a failed merge or write, reopen the database"
+    )]
+    EnginePoisoned,
+
+    #[error("exceed the max batch num")]
+    ExceedMaxBatchNum,
+
+    #[error("the backup directory is not empty")]
+    BackupDirNotEmpty,
+
+    #[error("failed to copy data file to backup directory")]
+    FailedToCopyDataFile,
+
+    #[error("the backup manifest is missing or corrupted")]
+    BackupManifestCorrupted,
+
+    #[error("the restore directory is not empty")]
+    RestoreDirNotEmpty,
+
+    #[error("no backup is available for the restore target")]
+    NoBackupToRestore,
+
+    #[error("the backups do not form a valid incremental chain")]
+    InvalidBackupChain,
+
+    #[error("transaction conflict, the keys are changed by another writer")]
+    TransactionConflict,
 }
 
 pub type Result<T> = result::Result<T, Errors>;
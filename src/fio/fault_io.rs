@@ -0,0 +1,235 @@
+use super::IoManger;
+use crate::{Errors, Result};
+use parking_lot::Mutex;
+use std::{collections::HashSet, sync::Arc};
+
+/// 注入故障的 IO, 包装其他的 IoManger, 用于确定性地测试崩溃恢复
+///
+/// 写入的数据在 sync 之前只暂存在内存中, 模拟掉电时直接丢弃
+/// 克隆之后共享同一份状态, 可以在交给 DataFile 之后继续注入故障
+#[derive(Clone)]
+pub struct FaultIo {
+    inner: Arc<dyn IoManger>,
+    state: Arc<Mutex<FaultState>>,
+}
+
+#[derive(Default)]
+struct FaultState {
+    // 已经执行的 write 次数
+    write_count: u64,
+    // 第 N 次 write 失败, 不写入任何数据
+    fail_write_at: Option<u64>,
+    // 第 N 次 write 只写入部分数据之后失败
+    short_write_at: Option<(u64, usize)>,
+    // 读取时需要翻转最低位的字节偏移
+    flipped_offsets: HashSet<u64>,
+    // 还没有持久化的数据
+    unsynced: Vec<u8>,
+}
+
+impl FaultIo {
+    pub fn new(inner: impl IoManger + 'static) -> Self {
+        Self {
+            inner: Arc::new(inner),
+            state: Arc::new(Mutex::new(FaultState::default())),
+        }
+    }
+
+    /// 第 n 次 write 返回错误, 从 1 开始计数
+    pub fn fail_nth_write(&self, n: u64) {
+        self.state.lock().fail_write_at = Some(n);
+    }
+
+    /// 第 n 次 write 只写入前 len 个字节, 然后返回错误, 模拟写入被截断
+    pub fn short_nth_write(&self, n: u64, len: usize) {
+        self.state.lock().short_write_at = Some((n, len));
+    }
+
+    /// 读取时翻转 offset 处字节的最低位, 模拟磁盘上的数据损坏
+    pub fn flip_bit(&self, offset: u64) {
+        self.state.lock().flipped_offsets.insert(offset);
+    }
+
+    /// 模拟掉电, 丢弃所有还没有持久化的数据
+    pub fn power_loss(&self) {
+        self.state.lock().unsynced.clear();
+    }
+}
+
+impl IoManger for FaultIo {
+    fn read(&self, buf: &mut [u8], offset: u64) -> Result<usize> {
+        let state = self.state.lock();
+        let synced_size = self.inner.size()?;
+
+        // 先从已经持久化的部分读取, 剩余的部分从未持久化的数据中读取
+        let mut n = 0;
+        if offset < synced_size {
+            n = self.inner.read(buf, offset)?;
+        }
+        if n < buf.len() {
+            let start = (offset + n as u64).saturating_sub(synced_size) as usize;
+            if start < state.unsynced.len() {
+                let len = (buf.len() - n).min(state.unsynced.len() - start);
+                buf[n..n + len].copy_from_slice(&state.unsynced[start..start + len]);
+                n += len;
+            }
+        }
+
+        for (i, byte) in buf[..n].iter_mut().enumerate() {
+            if state.flipped_offsets.contains(&(offset + i as u64)) {
+                *byte ^= 1;
+            }
+        }
+
+        Ok(n)
+    }
+
+    fn write(&self, buf: &[u8]) -> Result<usize> {
+        let mut state = self.state.lock();
+        state.write_count += 1;
+
+        if state.fail_write_at == Some(state.write_count) {
+            return Err(Errors::FailedToWriteToDataFile);
+        }
+        if let Some((n, len)) = state.short_write_at
+            && n == state.write_count
+        {
+            state.unsynced.extend_from_slice(&buf[..len.min(buf.len())]);
+            return Err(Errors::FailedToWriteToDataFile);
+        }
+
+        state.unsynced.extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn sync(&self) -> Result<()> {
+        let mut state = self.state.lock();
+        if !state.unsynced.is_empty() {
+            self.inner.write(&state.unsynced)?;
+            state.unsynced.clear();
+        }
+        self.inner.sync()
+    }
+
+    fn size(&self) -> Result<u64> {
+        let state = self.state.lock();
+        Ok(self.inner.size()? + state.unsynced.len() as u64)
+    }
+
+    fn truncate(&self, size: u64) -> Result<()> {
+        let mut state = self.state.lock();
+        let synced_size = self.inner.size()?;
+        if size >= synced_size {
+            state.unsynced.truncate((size - synced_size) as usize);
+            return Ok(());
+        }
+
+        state.unsynced.clear();
+        self.inner.truncate(size)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{DataFile, LogRecord, MemoryIo, data::DATA_FILE_HEADER_SIZE};
+
+    fn new_data_file() -> (DataFile, FaultIo) {
+        let fault_io = FaultIo::new(MemoryIo::new());
+        let data_file = DataFile::with_io_manager(0, Box::new(fault_io.clone())).unwrap();
+        (data_file, fault_io)
+    }
+
+    fn encode(key: &str) -> Vec<u8> {
+        LogRecord::new(key.as_bytes().to_vec(), b"value".to_vec()).encode()
+    }
+
+    #[test]
+    fn fault_io_fail_write_should_work() {
+        let (data_file, fault_io) = new_data_file();
+        // 第 1 次 write 是数据文件的头部
+        fault_io.fail_nth_write(3);
+
+        assert!(data_file.write(&encode("a")).is_ok());
+        assert_eq!(
+            data_file.write(&encode("b")).err(),
+            Some(Errors::FailedToWriteToDataFile)
+        );
+        assert!(data_file.write(&encode("c")).is_ok());
+
+        let record = data_file.read_log_record(DATA_FILE_HEADER_SIZE).unwrap();
+        let next = data_file.read_log_record(DATA_FILE_HEADER_SIZE + record.size);
+        assert_eq!(next.unwrap().record.key, b"c".to_vec());
+    }
+
+    #[test]
+    fn fault_io_short_write_should_be_discarded() {
+        let (data_file, fault_io) = new_data_file();
+        fault_io.short_nth_write(3, 6);
+
+        assert!(data_file.write(&encode("a")).is_ok());
+        let write_off = data_file.get_write_off();
+        assert!(data_file.write(&encode("b")).is_err());
+
+        // 写入的部分数据被截断, 写偏移和文件大小保持一致
+        assert_eq!(data_file.get_write_off(), write_off);
+        assert_eq!(fault_io.size().unwrap(), write_off);
+
+        // 再次写入的记录紧接在前一条完整的记录之后
+        assert!(data_file.write(&encode("c")).is_ok());
+        let record = data_file.read_log_record(DATA_FILE_HEADER_SIZE).unwrap();
+        assert_eq!(record.record.key, b"a".to_vec());
+        let next = data_file.read_log_record(DATA_FILE_HEADER_SIZE + record.size);
+        assert_eq!(next.unwrap().record.key, b"c".to_vec());
+    }
+
+    #[test]
+    fn fault_io_truncate_should_cut_synced_and_unsynced_data() {
+        let fault_io = FaultIo::new(MemoryIo::new());
+        fault_io.write(b"key-a").unwrap();
+        fault_io.sync().unwrap();
+        fault_io.write(b"key-b").unwrap();
+
+        fault_io.truncate(7).unwrap();
+        assert_eq!(fault_io.size().unwrap(), 7);
+        fault_io.truncate(3).unwrap();
+        assert_eq!(fault_io.size().unwrap(), 3);
+
+        let mut buf = vec![0u8; 5];
+        assert_eq!(fault_io.read(&mut buf, 0).unwrap(), 3);
+        assert_eq!(&buf[..3], b"key");
+    }
+
+    #[test]
+    fn fault_io_power_loss_should_drop_unsynced_data() {
+        let (data_file, fault_io) = new_data_file();
+        data_file.write(&encode("a")).unwrap();
+        data_file.sync().unwrap();
+        data_file.write(&encode("b")).unwrap();
+        assert_eq!(fault_io.size().unwrap(), data_file.get_write_off());
+
+        fault_io.power_loss();
+        let record = data_file.read_log_record(DATA_FILE_HEADER_SIZE).unwrap();
+        assert_eq!(record.record.key, b"a".to_vec());
+        assert_eq!(
+            data_file
+                .read_log_record(DATA_FILE_HEADER_SIZE + record.size)
+                .err(),
+            Some(Errors::ReadDataFileEOF)
+        );
+    }
+
+    #[test]
+    fn fault_io_flip_bit_should_fail_crc() {
+        let (data_file, fault_io) = new_data_file();
+        data_file.write(&encode("a")).unwrap();
+        data_file.sync().unwrap();
+
+        // 翻转 value 中的一位
+        fault_io.flip_bit(data_file.get_write_off() - 1);
+        assert_eq!(
+            data_file.read_log_record(DATA_FILE_HEADER_SIZE).err(),
+            Some(Errors::InvalidLogRecordCrc)
+        );
+    }
+}
@@ -15,14 +15,21 @@ pub struct FileIo {
 }
 
 impl FileIo {
-    pub fn try_new(path: PathBuf) -> Result<Self> {
-        match OpenOptions::new()
-            .create(true)
-            .truncate(true)
-            .read(true)
-            .write(true)
-            .open(path)
-        {
+    /// 创建一个新的数据文件, 文件已存在时返回错误, 避免覆盖已有数据
+    pub fn create(path: PathBuf) -> Result<Self> {
+        Self::open_with(
+            path,
+            OpenOptions::new().create_new(true).read(true).append(true),
+        )
+    }
+
+    /// 打开一个已存在的数据文件, 写入从文件末尾继续追加
+    pub fn open(path: PathBuf) -> Result<Self> {
+        Self::open_with(path, OpenOptions::new().read(true).append(true))
+    }
+
+    fn open_with(path: PathBuf, opts: &OpenOptions) -> Result<Self> {
+        match opts.open(path) {
             Ok(file) => Ok(Self::new(file)),
             Err(e) => {
                 error!("failed to open data file: {}", e);
@@ -53,11 +60,11 @@ impl IoManger for FileIo {
     fn write(&self, buf: &[u8]) -> Result<usize> {
         let mut w = self.fd.write();
 
-        match w.write(buf) {
-            Ok(n) => Ok(n),
+        match w.write_all(buf) {
+            Ok(_) => Ok(buf.len()),
             Err(e) => {
                 error!("write to data file err: {}", e);
-                Err(Errors::FailedToReadFromDataFile)
+                Err(Errors::FailedToWriteToDataFile)
             }
         }
     }
@@ -71,6 +78,28 @@ impl IoManger for FileIo {
 
         Ok(())
     }
+
+    fn size(&self) -> Result<u64> {
+        let r = self.fd.read();
+        match r.metadata() {
+            Ok(metadata) => Ok(metadata.len()),
+            Err(e) => {
+                error!("failed to get data file metadata: {}", e);
+                Err(Errors::FailedToReadFromDataFile)
+            }
+        }
+    }
+
+    fn truncate(&self, size: u64) -> Result<()> {
+        // 文件以追加模式打开, 截断之后的写入从新的末尾开始
+        let w = self.fd.write();
+        if let Err(e) = w.set_len(size) {
+            error!("failed to truncate data file: {}", e);
+            return Err(Errors::FailedToWriteToDataFile);
+        }
+
+        Ok(())
+    }
 }
 
 #[cfg(test)]
@@ -83,7 +112,7 @@ mod tests {
     fn file_io_write_should_work() {
         let path = PathBuf::from("/tmp/a.data");
 
-        let f = FileIo::try_new(path.clone());
+        let f = FileIo::create(path.clone());
         assert!(f.is_ok());
 
         let f = f.ok().unwrap();
@@ -99,7 +128,7 @@ mod tests {
     fn file_io_read_should_work() {
         let path = PathBuf::from("/tmp/b.data");
 
-        let f = FileIo::try_new(path.clone());
+        let f = FileIo::create(path.clone());
         assert!(f.is_ok());
 
         let f = f.ok().unwrap();
@@ -120,7 +149,7 @@ mod tests {
     fn file_io_fio_should_work() {
         let path = PathBuf::from("/tmp/c.data");
 
-        let f = FileIo::try_new(path.clone());
+        let f = FileIo::create(path.clone());
         assert!(f.is_ok());
 
         let f = f.ok().unwrap();
@@ -140,4 +169,67 @@ mod tests {
         let res = fs::remove_file(path);
         assert!(res.is_ok());
     }
+
+    #[test]
+    fn file_io_create_existing_file_should_fail() {
+        let path = PathBuf::from("/tmp/bitcask-file-io-create-existing.data");
+        let _ = fs::remove_file(&path);
+
+        let f = FileIo::create(path.clone());
+        assert!(f.is_ok());
+
+        let res = FileIo::create(path.clone());
+        assert_eq!(res.err().unwrap(), Errors::FailedToOpenDataFile);
+
+        let res = fs::remove_file(path);
+        assert!(res.is_ok());
+    }
+
+    #[test]
+    fn file_io_open_should_append() {
+        let path = PathBuf::from("/tmp/bitcask-file-io-open-append.data");
+        let _ = fs::remove_file(&path);
+
+        // 打开不存在的文件
+        assert!(FileIo::open(path.clone()).is_err());
+
+        let f = FileIo::create(path.clone()).unwrap();
+        f.write("key-a".as_bytes()).expect("write should work");
+        drop(f);
+
+        // 重新打开后不会清空数据, 且从文件末尾继续写入
+        let f = FileIo::open(path.clone()).unwrap();
+        assert_eq!(f.size().unwrap(), 5);
+
+        f.write("key-b".as_bytes()).expect("write should work");
+        assert_eq!(f.size().unwrap(), 10);
+
+        let mut buf = vec![0u8; 10];
+        let res = f.read(&mut buf, 0);
+        assert_eq!(res.ok().unwrap(), 10);
+        assert_eq!(buf, "key-akey-b".as_bytes().to_vec());
+
+        let res = fs::remove_file(path);
+        assert!(res.is_ok());
+    }
+
+    #[test]
+    fn file_io_truncate_should_append_at_new_end() {
+        let path = PathBuf::from("/tmp/bitcask-file-io-truncate.data");
+        let _ = fs::remove_file(&path);
+
+        let f = FileIo::create(path.clone()).unwrap();
+        f.write("key-a".as_bytes()).expect("write should work");
+        f.truncate(3).expect("truncate should work");
+        assert_eq!(f.size().unwrap(), 3);
+
+        // 截断之后从新的末尾继续写入
+        f.write("-b".as_bytes()).expect("write should work");
+        let mut buf = vec![0u8; 5];
+        assert_eq!(f.read(&mut buf, 0).unwrap(), 5);
+        assert_eq!(buf, "key-b".as_bytes().to_vec());
+
+        let res = fs::remove_file(path);
+        assert!(res.is_ok());
+    }
 }
@@ -0,0 +1,195 @@
+use super::{FileIo, IoManger, MMapIo};
+use crate::{Errors, Result};
+use log::warn;
+use std::{
+    fs::{self, File, OpenOptions, TryLockError},
+    io,
+    path::Path,
+};
+
+/// 数据目录的文件系统接口, 数据文件的创建和打开, 列出文件, 重命名, 删除以及目录锁都通过该接口
+///
+/// 目录操作返回 io::Error, 由调用方根据场景转换为对应的错误
+pub trait FileSystem: Sync + Send {
+    /// 创建一个新文件, 文件已存在时返回错误, 避免覆盖已有数据
+    fn create(&self, path: &Path) -> Result<Box<dyn IoManger>>;
+
+    /// 打开一个已存在的文件, 写入从文件末尾继续追加
+    fn open(&self, path: &Path) -> Result<Box<dyn IoManger>>;
+
+    /// 使用 mmap 只读打开一个已存在的文件, 不支持 mmap 时和 open 相同
+    fn open_mmap(&self, path: &Path) -> Result<Box<dyn IoManger>> {
+        self.open(path)
+    }
+
+    /// path 是否是一个文件
+    fn is_file(&self, path: &Path) -> bool;
+
+    /// path 是否是一个目录
+    fn is_dir(&self, path: &Path) -> bool;
+
+    /// 列出目录中所有文件和子目录的名称
+    fn list(&self, dir_path: &Path) -> io::Result<Vec<String>>;
+
+    /// 获取文件的大小
+    fn file_size(&self, path: &Path) -> io::Result<u64>;
+
+    /// 重命名文件, 目标文件已存在时原子地替换
+    fn rename(&self, from: &Path, to: &Path) -> io::Result<()>;
+
+    /// 删除文件, 已经打开的文件仍然可以读写
+    fn remove_file(&self, path: &Path) -> io::Result<()>;
+
+    /// 为 src 创建硬链接 dest, 不支持时返回错误, 调用方改为拷贝
+    fn hard_link(&self, src: &Path, dest: &Path) -> io::Result<()>;
+
+    /// 创建目录以及不存在的上级目录
+    fn create_dir_all(&self, dir_path: &Path) -> io::Result<()>;
+
+    /// 删除目录以及其中的所有内容
+    fn remove_dir_all(&self, dir_path: &Path) -> io::Result<()>;
+
+    /// 获取目录的排他锁, 已经被其他实例持有时返回 DatabaseIsUsing
+    fn lock_dir(&self, dir_path: &Path) -> Result<Box<dyn DirLock>>;
+}
+
+/// 数据目录的排他锁, 调用 unlock 或者 drop 之后释放
+pub trait DirLock: Sync + Send {
+    fn unlock(&self) -> Result<()>;
+}
+
+/// 数据目录中的文件锁名称
+const FILE_LOCK_NAME: &str = "flock";
+
+/// 标准文件系统, 数据文件使用标准文件 IO, 旧的数据文件可以使用 mmap
+pub struct StdFileSystem;
+
+impl FileSystem for StdFileSystem {
+    fn create(&self, path: &Path) -> Result<Box<dyn IoManger>> {
+        Ok(Box::new(FileIo::create(path.to_path_buf())?))
+    }
+
+    fn open(&self, path: &Path) -> Result<Box<dyn IoManger>> {
+        Ok(Box::new(FileIo::open(path.to_path_buf())?))
+    }
+
+    fn open_mmap(&self, path: &Path) -> Result<Box<dyn IoManger>> {
+        Ok(Box::new(MMapIo::open(path.to_path_buf())?))
+    }
+
+    fn is_file(&self, path: &Path) -> bool {
+        path.is_file()
+    }
+
+    fn is_dir(&self, path: &Path) -> bool {
+        path.is_dir()
+    }
+
+    fn list(&self, dir_path: &Path) -> io::Result<Vec<String>> {
+        let mut names = Vec::new();
+        for entry in fs::read_dir(dir_path)? {
+            if let Some(name) = entry?.file_name().to_str() {
+                names.push(name.to_string());
+            }
+        }
+        Ok(names)
+    }
+
+    fn file_size(&self, path: &Path) -> io::Result<u64> {
+        Ok(fs::metadata(path)?.len())
+    }
+
+    fn rename(&self, from: &Path, to: &Path) -> io::Result<()> {
+        fs::rename(from, to)
+    }
+
+    fn remove_file(&self, path: &Path) -> io::Result<()> {
+        fs::remove_file(path)
+    }
+
+    fn hard_link(&self, src: &Path, dest: &Path) -> io::Result<()> {
+        fs::hard_link(src, dest)
+    }
+
+    fn create_dir_all(&self, dir_path: &Path) -> io::Result<()> {
+        fs::create_dir_all(dir_path)
+    }
+
+    fn remove_dir_all(&self, dir_path: &Path) -> io::Result<()> {
+        fs::remove_dir_all(dir_path)
+    }
+
+    fn lock_dir(&self, dir_path: &Path) -> Result<Box<dyn DirLock>> {
+        let lock_file = match OpenOptions::new()
+            .create(true)
+            .truncate(false)
+            .read(true)
+            .write(true)
+            .open(dir_path.join(FILE_LOCK_NAME))
+        {
+            Ok(file) => file,
+            Err(e) => {
+                warn!("failed to open lock file: {}", e);
+                return Err(Errors::FailedToOpenDataFile);
+            }
+        };
+
+        match lock_file.try_lock() {
+            Ok(()) => Ok(Box::new(FileLock(lock_file))),
+            Err(TryLockError::WouldBlock) => Err(Errors::DatabaseIsUsing),
+            Err(TryLockError::Error(e)) => {
+                warn!("failed to lock database directory: {}", e);
+                Err(Errors::FailedToLockDatabaseDir)
+            }
+        }
+    }
+}
+
+// 基于文件锁的目录锁, 文件关闭时释放
+struct FileLock(File);
+
+impl DirLock for FileLock {
+    fn unlock(&self) -> Result<()> {
+        if let Err(e) = self.0.unlock() {
+            warn!("failed to unlock database directory: {}", e);
+            return Err(Errors::FailedToUnlockDatabaseDir);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    #[test]
+    fn std_file_system_should_work() {
+        let fs = StdFileSystem;
+        let dir_path = PathBuf::from("/tmp/bitcask-std-fs");
+        let _ = fs::remove_dir_all(&dir_path);
+        fs.create_dir_all(&dir_path).unwrap();
+        assert!(fs.is_dir(&dir_path));
+
+        let path = dir_path.join("a.data");
+        let file = fs.create(&path).unwrap();
+        file.write(b"key-a").unwrap();
+        assert!(fs.create(&path).is_err());
+        assert!(fs.is_file(&path));
+        assert_eq!(fs.file_size(&path).unwrap(), 5);
+
+        let new_path = dir_path.join("b.data");
+        fs.rename(&path, &new_path).unwrap();
+        assert_eq!(fs.list(&dir_path).unwrap(), vec!["b.data".to_string()]);
+
+        // 目录锁只能被持有一次, 释放之后可以再次获取
+        let lock = fs.lock_dir(&dir_path).unwrap();
+        assert_eq!(fs.lock_dir(&dir_path).err(), Some(Errors::DatabaseIsUsing));
+        lock.unlock().unwrap();
+        drop(lock);
+        assert!(fs.lock_dir(&dir_path).is_ok());
+
+        fs.remove_dir_all(&dir_path).unwrap();
+        assert!(!fs.is_dir(&dir_path));
+    }
+}
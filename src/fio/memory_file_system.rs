@@ -0,0 +1,287 @@
+use super::{DirLock, FaultIo, FileSystem, IoManger, MemoryIo};
+use crate::{Errors, Result};
+use parking_lot::Mutex;
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap},
+    io,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+/// 内存中的文件系统, 克隆之后共享同一份数据, 可以在多次打开数据库之间保留数据
+///
+/// 每个文件都是包装了 MemoryIo 的 FaultIo, 可以通过 file 获取之后注入故障, 用于确定性地测试崩溃恢复
+/// 创建, 重命名和删除等目录操作立即生效, 文件中的数据在 sync 之前会被 power_loss 丢弃
+#[derive(Clone, Default)]
+pub struct MemoryFileSystem {
+    state: Arc<Mutex<MemoryFsState>>,
+}
+
+#[derive(Default)]
+struct MemoryFsState {
+    files: BTreeMap<PathBuf, FaultIo>,
+    dirs: BTreeSet<PathBuf>,
+    // 被锁住的目录, 以及持有锁的编号
+    locks: HashMap<PathBuf, u64>,
+    next_lock_id: u64,
+}
+
+impl MemoryFileSystem {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// 获取 path 对应的文件, 用于注入故障, 文件不存在时返回 None
+    pub fn file(&self, path: impl AsRef<Path>) -> Option<FaultIo> {
+        self.state.lock().files.get(path.as_ref()).cloned()
+    }
+
+    /// 模拟掉电, 所有文件丢弃还没有持久化的数据, 并释放所有的目录锁
+    pub fn power_loss(&self) {
+        let mut state = self.state.lock();
+        for file in state.files.values() {
+            file.power_loss();
+        }
+        state.locks.clear();
+    }
+}
+
+impl MemoryFsState {
+    fn check_parent(&self, path: &Path) -> io::Result<()> {
+        match path.parent() {
+            Some(parent) if !parent.as_os_str().is_empty() && !self.dirs.contains(parent) => {
+                Err(not_found(parent))
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+impl FileSystem for MemoryFileSystem {
+    fn create(&self, path: &Path) -> Result<Box<dyn IoManger>> {
+        let mut state = self.state.lock();
+        if state.check_parent(path).is_err()
+            || state.files.contains_key(path)
+            || state.dirs.contains(path)
+        {
+            return Err(Errors::FailedToOpenDataFile);
+        }
+
+        let file = FaultIo::new(MemoryIo::new());
+        state.files.insert(path.to_path_buf(), file.clone());
+        Ok(Box::new(file))
+    }
+
+    fn open(&self, path: &Path) -> Result<Box<dyn IoManger>> {
+        match self.state.lock().files.get(path) {
+            Some(file) => Ok(Box::new(file.clone())),
+            None => Err(Errors::FailedToOpenDataFile),
+        }
+    }
+
+    fn is_file(&self, path: &Path) -> bool {
+        self.state.lock().files.contains_key(path)
+    }
+
+    fn is_dir(&self, path: &Path) -> bool {
+        self.state.lock().dirs.contains(path)
+    }
+
+    fn list(&self, dir_path: &Path) -> io::Result<Vec<String>> {
+        let state = self.state.lock();
+        if !state.dirs.contains(dir_path) {
+            return Err(not_found(dir_path));
+        }
+
+        let paths = state.files.keys().chain(state.dirs.iter());
+        Ok(paths
+            .filter(|path| path.parent() == Some(dir_path))
+            .filter_map(|path| path.file_name()?.to_str().map(String::from))
+            .collect())
+    }
+
+    fn file_size(&self, path: &Path) -> io::Result<u64> {
+        match self.state.lock().files.get(path) {
+            Some(file) => file.size().map_err(io::Error::other),
+            None => Err(not_found(path)),
+        }
+    }
+
+    fn rename(&self, from: &Path, to: &Path) -> io::Result<()> {
+        let mut state = self.state.lock();
+        state.check_parent(to)?;
+        if state.dirs.contains(to) {
+            return Err(io::Error::new(
+                io::ErrorKind::IsADirectory,
+                format!("{} is a directory", to.display()),
+            ));
+        }
+
+        // 已经打开的文件仍然指向同一份数据
+        let file = state.files.remove(from).ok_or_else(|| not_found(from))?;
+        state.files.insert(to.to_path_buf(), file);
+        Ok(())
+    }
+
+    fn remove_file(&self, path: &Path) -> io::Result<()> {
+        match self.state.lock().files.remove(path) {
+            Some(_) => Ok(()),
+            None => Err(not_found(path)),
+        }
+    }
+
+    fn hard_link(&self, src: &Path, dest: &Path) -> io::Result<()> {
+        let mut state = self.state.lock();
+        state.check_parent(dest)?;
+        if state.files.contains_key(dest) || state.dirs.contains(dest) {
+            return Err(io::Error::new(
+                io::ErrorKind::AlreadyExists,
+                format!("{} already exists", dest.display()),
+            ));
+        }
+
+        let file = state
+            .files
+            .get(src)
+            .cloned()
+            .ok_or_else(|| not_found(src))?;
+        state.files.insert(dest.to_path_buf(), file);
+        Ok(())
+    }
+
+    fn create_dir_all(&self, dir_path: &Path) -> io::Result<()> {
+        let mut state = self.state.lock();
+        for dir in dir_path.ancestors() {
+            if state.files.contains_key(dir) {
+                return Err(io::Error::new(
+                    io::ErrorKind::AlreadyExists,
+                    format!("{} is a file", dir.display()),
+                ));
+            }
+        }
+        for dir in dir_path.ancestors() {
+            if !dir.as_os_str().is_empty() {
+                state.dirs.insert(dir.to_path_buf());
+            }
+        }
+        Ok(())
+    }
+
+    fn remove_dir_all(&self, dir_path: &Path) -> io::Result<()> {
+        let mut state = self.state.lock();
+        if !state.dirs.contains(dir_path) {
+            return Err(not_found(dir_path));
+        }
+
+        state.files.retain(|path, _| !path.starts_with(dir_path));
+        state.dirs.retain(|path| !path.starts_with(dir_path));
+        Ok(())
+    }
+
+    fn lock_dir(&self, dir_path: &Path) -> Result<Box<dyn DirLock>> {
+        let mut state = self.state.lock();
+        if !state.dirs.contains(dir_path) {
+            return Err(Errors::FailedToOpenDataFile);
+        }
+        if state.locks.contains_key(dir_path) {
+            return Err(Errors::DatabaseIsUsing);
+        }
+
+        state.next_lock_id += 1;
+        let id = state.next_lock_id;
+        state.locks.insert(dir_path.to_path_buf(), id);
+        Ok(Box::new(MemoryDirLock {
+            state: self.state.clone(),
+            dir_path: dir_path.to_path_buf(),
+            id,
+        }))
+    }
+}
+
+// 内存文件系统中的目录锁, 掉电之后被其他实例重新获取的锁不会被释放
+struct MemoryDirLock {
+    state: Arc<Mutex<MemoryFsState>>,
+    dir_path: PathBuf,
+    id: u64,
+}
+
+impl DirLock for MemoryDirLock {
+    fn unlock(&self) -> Result<()> {
+        let mut state = self.state.lock();
+        if state.locks.get(&self.dir_path) == Some(&self.id) {
+            state.locks.remove(&self.dir_path);
+        }
+        Ok(())
+    }
+}
+
+impl Drop for MemoryDirLock {
+    fn drop(&mut self) {
+        let _ = self.unlock();
+    }
+}
+
+fn not_found(path: &Path) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::NotFound,
+        format!("{} not found", path.display()),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn memory_file_system_should_work() {
+        let fs = MemoryFileSystem::new();
+        let dir_path = PathBuf::from("/data/db");
+        let path = dir_path.join("a.data");
+
+        // 上级目录不存在时无法创建文件
+        assert!(fs.create(&path).is_err());
+        fs.create_dir_all(&dir_path).unwrap();
+        assert!(fs.is_dir(Path::new("/data")));
+
+        let file = fs.create(&path).unwrap();
+        file.write(b"key-a").unwrap();
+        assert!(fs.create(&path).is_err());
+        assert_eq!(fs.file_size(&path).unwrap(), 5);
+
+        // 重命名之后已经打开的文件仍然指向同一份数据, 克隆之后共享同一份数据
+        let new_path = dir_path.join("b.data");
+        fs.rename(&path, &new_path).unwrap();
+        file.write(b"key-b").unwrap();
+        let fs2 = fs.clone();
+        assert_eq!(fs2.list(&dir_path).unwrap(), vec!["b.data".to_string()]);
+        assert_eq!(fs2.file_size(&new_path).unwrap(), 10);
+
+        // 掉电之后没有持久化的数据被丢弃
+        fs.file(&new_path).unwrap().sync().unwrap();
+        file.write(b"key-c").unwrap();
+        fs.power_loss();
+        assert_eq!(fs.open(&new_path).unwrap().size().unwrap(), 10);
+
+        fs.remove_dir_all(Path::new("/data")).unwrap();
+        assert!(!fs.is_file(&new_path));
+        assert!(!fs.is_dir(&dir_path));
+    }
+
+    #[test]
+    fn memory_file_system_lock_should_work() {
+        let fs = MemoryFileSystem::new();
+        let dir_path = PathBuf::from("/data/db");
+        fs.create_dir_all(&dir_path).unwrap();
+
+        let lock = fs.lock_dir(&dir_path).unwrap();
+        assert_eq!(fs.lock_dir(&dir_path).err(), Some(Errors::DatabaseIsUsing));
+        drop(lock);
+
+        // 掉电之后锁被释放, 之前的锁不会释放新获取的锁
+        let lock = fs.lock_dir(&dir_path).unwrap();
+        fs.power_loss();
+        let _new_lock = fs.lock_dir(&dir_path).unwrap();
+        lock.unlock().unwrap();
+        assert_eq!(fs.lock_dir(&dir_path).err(), Some(Errors::DatabaseIsUsing));
+    }
+}
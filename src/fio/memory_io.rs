@@ -0,0 +1,71 @@
+use super::IoManger;
+use crate::Result;
+use parking_lot::RwLock;
+use std::sync::Arc;
+
+/// 基于内存的 IO, 数据只保存在内存中, 克隆之后共享同一份数据
+#[derive(Clone, Default)]
+pub struct MemoryIo {
+    data: Arc<RwLock<Vec<u8>>>,
+}
+
+impl MemoryIo {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl IoManger for MemoryIo {
+    fn read(&self, buf: &mut [u8], offset: u64) -> Result<usize> {
+        let data = self.data.read();
+        if offset >= data.len() as u64 {
+            return Ok(0);
+        }
+
+        let start = offset as usize;
+        let end = (start + buf.len()).min(data.len());
+        buf[..end - start].copy_from_slice(&data[start..end]);
+        Ok(end - start)
+    }
+
+    fn write(&self, buf: &[u8]) -> Result<usize> {
+        self.data.write().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn sync(&self) -> Result<()> {
+        Ok(())
+    }
+
+    fn size(&self) -> Result<u64> {
+        Ok(self.data.read().len() as u64)
+    }
+
+    fn truncate(&self, size: u64) -> Result<()> {
+        self.data.write().truncate(size as usize);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn memory_io_should_work() {
+        let m = MemoryIo::new();
+        assert_eq!(m.size().unwrap(), 0);
+        assert_eq!(m.write("key-a".as_bytes()).unwrap(), 5);
+        assert_eq!(m.write("key-b".as_bytes()).unwrap(), 5);
+        assert!(m.sync().is_ok());
+
+        // 克隆之后共享同一份数据
+        let m2 = m.clone();
+        assert_eq!(m2.size().unwrap(), 10);
+
+        let mut buf = vec![0u8; 8];
+        assert_eq!(m2.read(&mut buf, 5).unwrap(), 5);
+        assert_eq!(&buf[..5], "key-b".as_bytes());
+        assert_eq!(m2.read(&mut buf, 10).unwrap(), 0);
+    }
+}
@@ -0,0 +1,99 @@
+use super::IoManger;
+use crate::{Errors, Result};
+use log::error;
+use memmap2::Mmap;
+use std::{fs::OpenOptions, path::PathBuf};
+
+/// 基于 mmap 的只读 IO, 用于不会再写入的旧数据文件, 读取时不需要系统调用
+pub struct MMapIo {
+    map: Mmap,
+}
+
+impl MMapIo {
+    /// 只读映射一个已存在的文件
+    pub fn open(path: PathBuf) -> Result<Self> {
+        let file = match OpenOptions::new().read(true).open(path) {
+            Ok(file) => file,
+            Err(e) => {
+                error!("failed to open data file: {}", e);
+                return Err(Errors::FailedToOpenDataFile);
+            }
+        };
+
+        // 映射期间文件不会被截断或修改, 旧的数据文件只会被 merge 整个替换掉
+        match unsafe { Mmap::map(&file) } {
+            Ok(map) => Ok(Self { map }),
+            Err(e) => {
+                error!("failed to map data file: {}", e);
+                Err(Errors::FailedToOpenDataFile)
+            }
+        }
+    }
+}
+
+impl IoManger for MMapIo {
+    fn read(&self, buf: &mut [u8], offset: u64) -> Result<usize> {
+        let len = self.map.len() as u64;
+        if offset >= len {
+            return Ok(0);
+        }
+
+        let start = offset as usize;
+        let end = (start + buf.len()).min(self.map.len());
+        buf[..end - start].copy_from_slice(&self.map[start..end]);
+        Ok(end - start)
+    }
+
+    fn write(&self, _buf: &[u8]) -> Result<usize> {
+        error!("mmap io is read only");
+        Err(Errors::FailedToWriteToDataFile)
+    }
+
+    fn sync(&self) -> Result<()> {
+        Ok(())
+    }
+
+    fn size(&self) -> Result<u64> {
+        Ok(self.map.len() as u64)
+    }
+
+    fn truncate(&self, _size: u64) -> Result<()> {
+        error!("mmap io is read only");
+        Err(Errors::FailedToWriteToDataFile)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::FileIo;
+    use std::fs;
+
+    #[test]
+    fn mmap_io_read_should_work() {
+        let path = PathBuf::from("/tmp/bitcask-mmap-read.data");
+        let _ = fs::remove_file(&path);
+
+        let f = FileIo::create(path.clone()).unwrap();
+        f.write("key-a".as_bytes()).unwrap();
+        f.write("key-b".as_bytes()).unwrap();
+
+        let m = MMapIo::open(path.clone()).unwrap();
+        assert_eq!(m.size().unwrap(), 10);
+
+        let mut buf = vec![0u8; 5];
+        assert_eq!(m.read(&mut buf, 5).unwrap(), 5);
+        assert_eq!(buf, "key-b".as_bytes().to_vec());
+
+        // 超出文件末尾只读取剩余的部分
+        let mut buf = vec![0u8; 8];
+        assert_eq!(m.read(&mut buf, 7).unwrap(), 3);
+        assert_eq!(m.read(&mut buf, 10).unwrap(), 0);
+
+        // 只读, 不能写入
+        assert!(m.write("key-c".as_bytes()).is_err());
+        assert!(m.truncate(5).is_err());
+
+        fs::remove_file(path).unwrap();
+    }
+}
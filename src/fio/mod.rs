@@ -1,10 +1,21 @@
+mod fault_io;
 mod file_io;
+mod file_system;
+mod memory_file_system;
+mod memory_io;
+mod mmap;
 
-use crate::Result;
+use crate::{Result, options::IoType};
+use std::sync::Arc;
 
+pub use fault_io::FaultIo;
 pub use file_io::FileIo;
+pub use file_system::{DirLock, FileSystem, StdFileSystem};
+pub use memory_file_system::MemoryFileSystem;
+pub use memory_io::MemoryIo;
+pub use mmap::MMapIo;
 
-/// Io 管理接口, 可以插入不同的 IO 类型, 目前支持标准文件 IO
+/// Io 管理接口, 可以插入不同的 IO 类型, 目前支持标准文件 IO, 只读的 mmap, 以及用于测试的内存 IO 和故障注入 IO
 pub trait IoManger: Sync + Send {
     /// 从文件的给定位置读取对应的数据
     fn read(&self, buf: &mut [u8], offset: u64) -> Result<usize>;
@@ -14,4 +25,18 @@ pub trait IoManger: Sync + Send {
 
     /// 持久化数据
     fn sync(&self) -> Result<()>;
+
+    /// 获取文件大小, 无法获取时返回错误, 不能当作空文件处理
+    fn size(&self) -> Result<u64>;
+
+    /// 将文件截断到给定的大小, 用于丢弃写入失败时留下的不完整数据
+    fn truncate(&self, size: u64) -> Result<()>;
+}
+
+/// 根据 IO 类型打开数据目录使用的文件系统
+pub fn new_file_system(io_type: &IoType) -> Arc<dyn FileSystem> {
+    match io_type {
+        IoType::StandardFile => Arc::new(StdFileSystem),
+        IoType::Memory(fs) => Arc::new(fs.clone()),
+    }
 }
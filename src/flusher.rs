@@ -0,0 +1,60 @@
+use crate::DataFile;
+use log::error;
+use parking_lot::{Condvar, Mutex, RwLock};
+use std::{
+    sync::Arc,
+    thread::{self, JoinHandle},
+    time::Duration,
+};
+
+/// 后台定时持久化活跃文件, 用于 SyncPolicy::Interval
+pub(crate) struct Flusher {
+    // 是否停止后台线程, 通过条件变量唤醒等待中的线程
+    stopped: Arc<(Mutex<bool>, Condvar)>,
+    // 后台线程的句柄
+    handle: Mutex<Option<JoinHandle<()>>>,
+}
+
+impl Flusher {
+    /// 启动后台线程, 每隔 interval 持久化一次活跃文件
+    pub(crate) fn start(active_file: Arc<RwLock<DataFile>>, interval: Duration) -> Self {
+        let stopped = Arc::new((Mutex::new(false), Condvar::new()));
+        let thread_stopped = stopped.clone();
+        let handle = thread::spawn(move || {
+            let (lock, cond) = &*thread_stopped;
+            loop {
+                {
+                    let mut stopped = lock.lock();
+                    if !*stopped {
+                        cond.wait_for(&mut stopped, interval);
+                    }
+                    if *stopped {
+                        return;
+                    }
+                }
+
+                if let Err(e) = active_file.read().sync() {
+                    error!("failed to sync active file: {}", e);
+                }
+            }
+        });
+
+        Self {
+            stopped,
+            handle: Mutex::new(Some(handle)),
+        }
+    }
+
+    /// 停止后台线程并等待其退出
+    pub(crate) fn stop(&self) {
+        let (lock, cond) = &*self.stopped;
+        *lock.lock() = true;
+        cond.notify_all();
+
+        if let Some(handle) = self.handle.lock().take()
+            && handle.join().is_err()
+        {
+            error!("background flusher thread panicked");
+        }
+    }
+}
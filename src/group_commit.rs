@@ -0,0 +1,329 @@
+use crate::{
+    Engine, Errors, LogRecord, LogRecordPos, LogRecordType, Result, data::now_millis,
+    transaction::ConflictCheck,
+};
+use log::error;
+use parking_lot::Mutex;
+use std::{
+    collections::HashMap,
+    mem,
+    sync::{Arc, atomic::Ordering},
+};
+
+/// 一次 put, delete 或批量提交产生的写入请求, 由组提交统一写入
+pub(crate) struct WriteRequest {
+    // 需要写入的记录, 提交时被取出
+    records: Mutex<Vec<LogRecord>>,
+    // 只有 key 存在时才写入, 用于 delete
+    require_exists: bool,
+    // 是否为批量写入, 写入之前为记录分配序列号
+    batch: bool,
+    // 是否需要持久化, 和持久化策略无关
+    sync: bool,
+    // 事务提交时的冲突检查, 有冲突时不写入
+    conflict_check: Option<ConflictCheck>,
+    // 提交的结果, 每条记录在数据文件中的位置
+    result: Mutex<Option<Result<Vec<LogRecordPos>>>>,
+}
+
+impl Engine {
+    /// 组提交, 将并发写入的记录合并为一次写入和一次持久化, 返回这一组记录各自的位置
+    ///
+    /// 写入请求先进入等待队列, 然后竞争活跃文件的写锁, 拿到锁的线程将队列中所有的请求一起写入,
+    /// 在此期间到达的请求继续在队列中累积, 等待下一个拿到锁的线程处理
+    /// require_exists 为 true 时只有第一条记录的 key 存在才写入, 不写入时返回空的位置
+    pub(crate) fn group_commit(
+        &self,
+        records: Vec<LogRecord>,
+        require_exists: bool,
+        sync: bool,
+    ) -> Result<Vec<LogRecordPos>> {
+        self.submit_write_request(WriteRequest {
+            records: Mutex::new(records),
+            require_exists,
+            batch: false,
+            sync,
+            conflict_check: None,
+            result: Mutex::new(None),
+        })
+    }
+
+    /// 组提交一个批次, 序列号在持有活跃文件的写锁时分配, 数据文件中批次的顺序和序列号的顺序一致
+    /// conflict_check 不为空时写入之前先进行冲突检查, 事务开始之后检查的 key 被修改过时返回 TransactionConflict
+    pub(crate) fn group_commit_batch(
+        &self,
+        records: Vec<LogRecord>,
+        sync: bool,
+        conflict_check: Option<ConflictCheck>,
+    ) -> Result<Vec<LogRecordPos>> {
+        self.submit_write_request(WriteRequest {
+            records: Mutex::new(records),
+            require_exists: false,
+            batch: true,
+            sync,
+            conflict_check,
+            result: Mutex::new(None),
+        })
+    }
+
+    // 将写入请求放入等待队列, 由拿到活跃文件写锁的线程统一写入
+    fn submit_write_request(&self, request: WriteRequest) -> Result<Vec<LogRecordPos>> {
+        let request = Arc::new(request);
+        self.write_queue.lock().push(request.clone());
+
+        let mut active_file = self.active_file.write();
+
+        // 已经被其他线程一起提交了
+        if let Some(result) = request.result.lock().take() {
+            return result;
+        }
+
+        let requests = mem::take(&mut *self.write_queue.lock());
+
+        // 数据库已经关闭, 释放文件锁之后不能再写入数据文件
+        if let Err(e) = self.check_open() {
+            for req in requests.iter() {
+                *req.result.lock() = Some(Err(e.clone()));
+            }
+            return Err(e);
+        }
+
+        // 记录这一组中的写入对 key 是否存在的影响, 用于判断后面的 delete 是否需要写入
+        let mut key_exists: HashMap<Vec<u8>, bool> = HashMap::new();
+        let mut records = Vec::new();
+        let mut ranges = Vec::with_capacity(requests.len());
+        let mut need_sync = false;
+        for req in requests.iter() {
+            let mut req_records = mem::take(&mut *req.records.lock());
+
+            // 被这一组中前面的请求或者之前的提交修改过的 key 都是冲突
+            if let Some(check) = &req.conflict_check
+                && check.keys.iter().any(|key| {
+                    key_exists.contains_key(key) || self.commits.changed_since(key, check.start)
+                })
+            {
+                ranges.push(None);
+                continue;
+            }
+
+            if req.require_exists
+                && let Some(record) = req_records.first()
+                && !key_exists.get(&record.key).copied().unwrap_or_else(|| {
+                    self.index
+                        .get(record.key.clone())
+                        .is_some_and(|pos| !pos.is_expired())
+                })
+            {
+                ranges.push(Some(records.len()..records.len()));
+                continue;
+            }
+
+            // 在写入之前分配序列号, 之后分配到序列号的批次一定写在后面
+            if req.batch {
+                let seq_no = self.seq_no.fetch_add(1, Ordering::SeqCst) + 1;
+                for record in req_records.iter_mut() {
+                    record.seq_no = seq_no;
+                }
+            }
+
+            for record in req_records.iter() {
+                match record.rec_type {
+                    LogRecordType::NORMAL => key_exists.insert(record.key.clone(), true),
+                    LogRecordType::DELETED => key_exists.insert(record.key.clone(), false),
+                    LogRecordType::BATCHFINISHED | LogRecordType::SNAPSHOT => None,
+                };
+            }
+
+            ranges.push(Some(records.len()..records.len() + req_records.len()));
+            records.extend(req_records);
+            need_sync |= req.sync;
+        }
+
+        // 同一组提交的记录使用相同的提交时间, 按时间恢复时不会拆开一个批次
+        let commit_at = now_millis();
+        for record in records.iter_mut() {
+            record.commit_at = commit_at;
+        }
+
+        // 所有的记录一起写入, 然后根据持久化策略持久化一次
+        let start_file_id = active_file.get_file_id();
+        let start_offset = active_file.get_write_off();
+        let res = self
+            .write_log_records(&mut active_file, &mut records)
+            .and_then(|positions| {
+                let written = positions.iter().map(|pos| pos.get_size() as u64).sum();
+                self.sync_by_policy(&active_file, written, need_sync)?;
+                Ok(positions)
+            });
+
+        let positions = match res {
+            Ok(positions) => positions,
+            Err(e) => {
+                // 写入失败之前可能已经写入了一部分记录, 撤销这一组的写入, 避免报告失败的写入在重启之后出现
+                // 无法撤销时数据文件的状态未知, 不能再继续使用
+                if let Err(rollback_err) =
+                    self.rollback_log_records(&active_file, start_file_id, start_offset)
+                {
+                    error!("failed to rollback log records: {}", rollback_err);
+                    self.poisoned.store(true, Ordering::SeqCst);
+                }
+                for req in requests.iter() {
+                    *req.result.lock() = Some(Err(e.clone()));
+                }
+                return Err(e);
+            }
+        };
+
+        // 持有活跃文件的锁更新内存索引, 保证索引和数据文件中的写入顺序一致
+        // 更新之前先记录这次提交修改的 key, 以及快照可能需要的旧版本
+        self.commits.commit(&records, self.index.as_ref());
+        for (record, pos) in records.into_iter().zip(positions.iter()) {
+            self.update_index(record.key, record.rec_type, *pos);
+        }
+
+        for (req, range) in requests.iter().zip(ranges) {
+            *req.result.lock() = Some(match range {
+                Some(range) => Ok(positions[range].to_vec()),
+                None => Err(Errors::TransactionConflict),
+            });
+        }
+
+        request.result.lock().take().unwrap()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        FileSystem, IoType, MemoryFileSystem, Options, SyncPolicy, data::get_data_file_name,
+    };
+    use bytes::Bytes;
+    use std::{fs, path::PathBuf, thread, time::Duration};
+
+    #[test]
+    fn group_commit_should_merge_concurrent_writes() {
+        let opts = Options {
+            dir_path: PathBuf::from("/tmp/bitcask-group-commit"),
+            sync_policy: SyncPolicy::Always,
+            ..Default::default()
+        };
+        let _ = fs::remove_dir_all(&opts.dir_path);
+
+        let engine = Arc::new(Engine::open(opts.clone()).expect("failed to open engine"));
+        engine
+            .put(Bytes::from("deleted"), Bytes::from("value"))
+            .unwrap();
+
+        // 持有活跃文件的锁, 让并发的写入在队列中累积
+        let active_file = engine.active_file.write();
+        let mut handles = Vec::new();
+        for i in 0..8 {
+            let engine = engine.clone();
+            handles.push(thread::spawn(move || {
+                let key = Bytes::from(format!("key-{}", i));
+                engine.put(key.clone(), Bytes::from("value")).unwrap();
+                engine.delete(key).unwrap();
+            }));
+        }
+        let engine_clone = engine.clone();
+        handles.push(thread::spawn(move || {
+            engine_clone.delete(Bytes::from("deleted")).unwrap();
+        }));
+        while engine.write_queue.lock().len() < 9 {
+            thread::sleep(Duration::from_millis(1));
+        }
+        drop(active_file);
+
+        for handle in handles {
+            handle.join().unwrap();
+        }
+        assert!(engine.write_queue.lock().is_empty());
+        assert!(engine.list_keys().unwrap().is_empty());
+
+        // 一个请求中的多条记录连续写入, 并分别返回各自的位置
+        let positions = engine
+            .group_commit(
+                vec![
+                    LogRecord::new(b"a".to_vec(), b"1".to_vec()),
+                    LogRecord::new(b"b".to_vec(), b"2".to_vec()),
+                ],
+                false,
+                true,
+            )
+            .unwrap();
+        assert_eq!(positions.len(), 2);
+        assert_eq!(
+            positions[0].get_offset() + positions[0].get_size() as u64,
+            positions[1].get_offset()
+        );
+        assert_eq!(engine.get(Bytes::from("b")).unwrap(), Bytes::from("2"));
+
+        // 不存在的 key 删除时不会写入
+        let record = LogRecord {
+            key: b"missing".to_vec(),
+            value: Default::default(),
+            rec_type: LogRecordType::DELETED,
+            seq_no: crate::data::NON_BATCH_SEQ_NO,
+            expire_at: crate::data::NEVER_EXPIRE,
+            commit_at: crate::data::NO_COMMIT_TIME,
+        };
+        assert!(
+            engine
+                .group_commit(vec![record], true, false)
+                .unwrap()
+                .is_empty()
+        );
+
+        drop(engine);
+        fs::remove_dir_all(opts.dir_path).expect("failed to remove path");
+    }
+
+    #[test]
+    fn group_commit_failure_should_rollback_written_records() {
+        let fs = MemoryFileSystem::new();
+        let opts = Options {
+            dir_path: PathBuf::from("/tmp/bitcask-group-commit-rollback"),
+            data_file_size: 200,
+            io_type: IoType::Memory(fs.clone()),
+            ..Default::default()
+        };
+
+        let engine = Engine::open(opts.clone()).expect("failed to open engine");
+        engine.put(Bytes::from("a"), Bytes::from("1")).unwrap();
+        let write_off = engine.active_file.read().get_write_off();
+
+        // 下一个数据文件的位置被目录占用, 活跃文件写满之后无法切换
+        let next_file = get_data_file_name(&opts.dir_path, 1);
+        fs.create_dir_all(&next_file).unwrap();
+
+        // 前两条记录写入活跃文件之后, 第三条记录需要切换文件时失败
+        let records = ["b", "c", "d"]
+            .iter()
+            .map(|key| LogRecord::new(key.as_bytes().to_vec(), vec![b'v'; 60]))
+            .collect();
+        assert!(engine.group_commit(records, false, false).is_err());
+
+        // 已经写入的记录被撤销
+        let active_file = engine.active_file.read();
+        assert_eq!(active_file.get_write_off(), write_off);
+        assert_eq!(active_file.file_size().unwrap(), write_off);
+        drop(active_file);
+
+        // 之后的写入仍然可以继续
+        fs.remove_dir_all(&next_file).unwrap();
+        engine.put(Bytes::from("e"), Bytes::from("5")).unwrap();
+        drop(engine);
+
+        // 报告失败的写入在重启之后不会出现
+        let engine = Engine::open(opts.clone()).expect("failed to reopen engine");
+        assert_eq!(engine.get(Bytes::from("a")).unwrap(), Bytes::from("1"));
+        assert_eq!(engine.get(Bytes::from("e")).unwrap(), Bytes::from("5"));
+        for key in ["b", "c", "d"] {
+            assert_eq!(
+                engine.get(Bytes::from(key)).err(),
+                Some(Errors::KeyNotFound)
+            );
+        }
+    }
+}
@@ -1,5 +1,5 @@
-use super::Indexer;
-use crate::LogRecordPos;
+use super::{IndexIterator, Indexer, SnapshotIterator, key_range};
+use crate::{LogRecordPos, options::IteratorOptions};
 use parking_lot::RwLock;
 use std::{collections::BTreeMap, sync::Arc};
 
@@ -14,20 +14,33 @@ impl Indexer for BTree {
         self.tree.read().get(&key).copied()
     }
 
-    fn put(&self, key: Vec<u8>, pos: LogRecordPos) -> bool {
-        if self.get(key.clone()).is_some() {
-            return false;
-        }
-
+    fn put(&self, key: Vec<u8>, pos: LogRecordPos) -> Option<LogRecordPos> {
         let mut w = self.tree.write();
-        w.insert(key, pos);
-        true
+        w.insert(key, pos)
     }
 
-    fn delete(&self, key: Vec<u8>) -> bool {
+    fn delete(&self, key: Vec<u8>) -> Option<LogRecordPos> {
         let mut w = self.tree.write();
-        let ret = w.remove(&key);
-        ret.is_some()
+        w.remove(&key)
+    }
+
+    fn len(&self) -> usize {
+        self.tree.read().len()
+    }
+
+    fn iterator(&self, options: IteratorOptions) -> Box<dyn IndexIterator> {
+        let items = match key_range(&options) {
+            Some(range) => {
+                let r = self.tree.read();
+                r.range(range)
+                    .take_while(|(key, _)| key.starts_with(&options.prefix))
+                    .map(|(key, pos)| (key.clone(), *pos))
+                    .collect()
+            }
+            None => Vec::new(),
+        };
+
+        Box::new(SnapshotIterator::new(items, options.reverse))
     }
 }
 
@@ -42,10 +55,15 @@ mod tests {
         let key = "".as_bytes().to_vec();
 
         // success put
-        assert!(tree.put(key.clone(), LogRecordPos::new(1, 10)));
+        assert!(
+            tree.put(key.clone(), LogRecordPos::new(1, 10, 100))
+                .is_none()
+        );
 
-        // failed put
-        assert!(!tree.put(key.clone(), LogRecordPos::new(1, 10)));
+        // overwrite put, return the old pos
+        let old = tree.put(key.clone(), LogRecordPos::new(1, 20, 100));
+        assert!(old.is_some());
+        assert_eq!(old.unwrap().get_offset(), 10);
 
         let pos = tree.get(key.clone());
 
@@ -53,13 +71,16 @@ mod tests {
         assert!(pos.is_some());
 
         assert_eq!(pos.unwrap().get_file_id(), 1);
-        assert_eq!(pos.unwrap().get_offset(), 10);
+        assert_eq!(pos.unwrap().get_offset(), 20);
+        assert_eq!(pos.unwrap().get_size(), 100);
 
-        // success delete
-        assert!(tree.delete(key.clone()));
+        // success delete, return the removed pos
+        let removed = tree.delete(key.clone());
+        assert!(removed.is_some());
+        assert_eq!(removed.unwrap().get_offset(), 20);
 
         // fail delete
-        assert!(!tree.delete("None".as_bytes().to_vec()));
+        assert!(tree.delete("None".as_bytes().to_vec()).is_none());
 
         // fail get
         assert!(tree.get("None".as_bytes().to_vec()).is_none());
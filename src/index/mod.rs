@@ -1,25 +1,229 @@
 mod btree;
+mod skiplist;
 
-use crate::{LogRecordPos, options::IndexType};
+use crate::{
+    LogRecordPos,
+    options::{IndexType, IteratorOptions},
+};
+use std::ops::Bound;
 
 pub use btree::BTree;
+pub use skiplist::SkipList;
 
 /// Indexer 抽象索引接口
 pub trait Indexer: Sync + Send {
-    /// 向索引中存储 key 对应数据位置信息
-    fn put(&self, key: Vec<u8>, pos: LogRecordPos) -> bool;
+    /// 向索引中存储 key 对应数据位置信息, key 已存在时覆盖, 并返回旧的位置信息
+    fn put(&self, key: Vec<u8>, pos: LogRecordPos) -> Option<LogRecordPos>;
 
     /// 根据 key 取出对应的索引位置信息
     fn get(&self, key: Vec<u8>) -> Option<LogRecordPos>;
 
-    /// 根据 key 删除对应的索引位置信息
-    fn delete(&self, key: Vec<u8>) -> bool;
+    /// 根据 key 删除对应的索引位置信息, 返回被删除的位置信息
+    fn delete(&self, key: Vec<u8>) -> Option<LogRecordPos>;
+
+    /// 索引中 key 的数量
+    fn len(&self) -> usize;
+
+    /// 索引是否为空
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// 返回索引迭代器, 按照 key 的顺序遍历满足条件的数据
+    fn iterator(&self, options: IteratorOptions) -> Box<dyn IndexIterator>;
+}
+
+/// IndexIterator 抽象索引迭代器
+pub trait IndexIterator: Sync + Send {
+    /// 重新回到迭代器的起点, 即第一个数据
+    fn rewind(&mut self);
+
+    /// 根据传入的 key 查找到第一个大于 (反向遍历时为小于) 等于的目标 key, 从这个 key 开始遍历
+    fn seek(&mut self, key: Vec<u8>);
+
+    /// 跳转到下一个 key, 返回 None 则说明迭代完毕
+    fn next(&mut self) -> Option<(&Vec<u8>, &LogRecordPos)>;
 }
 
 /// 根据索引类型打开内存索引
-pub fn new_indexer(index_type: IndexType) -> impl Indexer {
+pub fn new_indexer(index_type: IndexType) -> Box<dyn Indexer> {
     match index_type {
-        IndexType::BTree => btree::BTree::default(),
-        IndexType::SkipList => todo!(),
+        IndexType::BTree => Box::new(btree::BTree::default()),
+        IndexType::SkipList => Box::new(skiplist::SkipList::default()),
+    }
+}
+
+/// 创建迭代器时拷贝出满足条件的索引数据, 迭代过程中不需要持有索引的锁
+pub(crate) struct SnapshotIterator {
+    // 按照遍历方向排好序的 key 及位置信息
+    items: Vec<(Vec<u8>, LogRecordPos)>,
+    // 当前遍历的下标
+    curr_index: usize,
+    // 是否反向遍历
+    reverse: bool,
+}
+
+impl SnapshotIterator {
+    /// items 需要按照 key 升序排列
+    pub(crate) fn new(mut items: Vec<(Vec<u8>, LogRecordPos)>, reverse: bool) -> Self {
+        if reverse {
+            items.reverse();
+        }
+        Self {
+            items,
+            curr_index: 0,
+            reverse,
+        }
+    }
+}
+
+impl IndexIterator for SnapshotIterator {
+    fn rewind(&mut self) {
+        self.curr_index = 0;
+    }
+
+    fn seek(&mut self, key: Vec<u8>) {
+        self.curr_index = match self.reverse {
+            true => self.items.partition_point(|(k, _)| *k > key),
+            false => self.items.partition_point(|(k, _)| *k < key),
+        };
+    }
+
+    fn next(&mut self) -> Option<(&Vec<u8>, &LogRecordPos)> {
+        let item = self.items.get(self.curr_index)?;
+        self.curr_index += 1;
+        Some((&item.0, &item.1))
+    }
+}
+
+/// key 的遍历范围, 分别为下界和上界
+pub(crate) type KeyRange = (Bound<Vec<u8>>, Bound<Vec<u8>>);
+
+/// 根据迭代器配置计算 key 的遍历范围, 范围为空时返回 None
+/// 前缀相同的 key 是连续的, 所以前缀也可以作为范围的下界
+pub(crate) fn key_range(options: &IteratorOptions) -> Option<KeyRange> {
+    let lower = match &options.start {
+        Some(start) => Some(start.clone().max(options.prefix.clone())),
+        None if !options.prefix.is_empty() => Some(options.prefix.clone()),
+        None => None,
+    };
+
+    if let (Some(lower), Some(end)) = (&lower, &options.end)
+        && lower >= end
+    {
+        return None;
+    }
+
+    let lower = match lower {
+        Some(lower) => Bound::Included(lower),
+        None => Bound::Unbounded,
+    };
+    let upper = match &options.end {
+        Some(end) => Bound::Excluded(end.clone()),
+        None => Bound::Unbounded,
+    };
+
+    Some((lower, upper))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_iterator(index_type: IndexType) {
+        let indexer = new_indexer(index_type);
+        for (i, key) in ["aa", "ab", "b", "ca", "cb", "cc", "d"].iter().enumerate() {
+            indexer.put(key.as_bytes().to_vec(), LogRecordPos::new(0, i as u64, 1));
+        }
+
+        let collect = |options: IteratorOptions| {
+            let mut iter = indexer.iterator(options);
+            let mut keys = Vec::new();
+            while let Some((key, _)) = iter.next() {
+                keys.push(String::from_utf8(key.clone()).unwrap());
+            }
+            keys
+        };
+
+        // 全部遍历
+        assert_eq!(
+            collect(IteratorOptions::default()),
+            vec!["aa", "ab", "b", "ca", "cb", "cc", "d"]
+        );
+
+        // 反向遍历
+        assert_eq!(
+            collect(IteratorOptions {
+                reverse: true,
+                ..Default::default()
+            }),
+            vec!["d", "cc", "cb", "ca", "b", "ab", "aa"]
+        );
+
+        // 前缀遍历
+        assert_eq!(
+            collect(IteratorOptions {
+                prefix: b"c".to_vec(),
+                ..Default::default()
+            }),
+            vec!["ca", "cb", "cc"]
+        );
+
+        // 范围遍历, 包含 start 不包含 end
+        assert_eq!(
+            collect(IteratorOptions {
+                start: Some(b"ab".to_vec()),
+                end: Some(b"cb".to_vec()),
+                ..Default::default()
+            }),
+            vec!["ab", "b", "ca"]
+        );
+
+        // 前缀和范围同时指定
+        assert_eq!(
+            collect(IteratorOptions {
+                prefix: b"c".to_vec(),
+                start: Some(b"cb".to_vec()),
+                reverse: true,
+                ..Default::default()
+            }),
+            vec!["cc", "cb"]
+        );
+
+        // 空的范围
+        assert!(
+            collect(IteratorOptions {
+                start: Some(b"d".to_vec()),
+                end: Some(b"a".to_vec()),
+                ..Default::default()
+            })
+            .is_empty()
+        );
+
+        // seek 和 rewind
+        let mut iter = indexer.iterator(IteratorOptions::default());
+        iter.seek(b"bb".to_vec());
+        assert_eq!(iter.next().unwrap().0, &b"ca".to_vec());
+        iter.rewind();
+        assert_eq!(iter.next().unwrap().0, &b"aa".to_vec());
+
+        let mut iter = indexer.iterator(IteratorOptions {
+            reverse: true,
+            ..Default::default()
+        });
+        iter.seek(b"bb".to_vec());
+        assert_eq!(iter.next().unwrap().0, &b"b".to_vec());
+        iter.seek(b"zz".to_vec());
+        assert_eq!(iter.next().unwrap().0, &b"d".to_vec());
+    }
+
+    #[test]
+    fn btree_iterator_should_work() {
+        test_iterator(IndexType::BTree);
+    }
+
+    #[test]
+    fn skiplist_iterator_should_work() {
+        test_iterator(IndexType::SkipList);
     }
 }
@@ -0,0 +1,140 @@
+use super::{IndexIterator, Indexer, SnapshotIterator, key_range};
+use crate::{LogRecordPos, options::IteratorOptions};
+use crossbeam_skiplist::SkipMap;
+use parking_lot::Mutex;
+use std::sync::Arc;
+
+// SkipList 索引, 封装 crossbeam 中无锁并发的 SkipMap 结构, 写入之间不需要互斥
+//
+// 每个 key 的位置信息保存在单独的单元中, 覆盖和删除时在单元内交换出旧的位置
+// 保证并发写入同一个 key 时每个旧的位置只会被返回一次, 失效数据的统计不会重复或遗漏
+// 单元为空说明写入还没有完成, 读取时视为不存在
+#[derive(Default)]
+pub struct SkipList {
+    skl: Arc<SkipMap<Vec<u8>, Mutex<Option<LogRecordPos>>>>,
+}
+
+impl Indexer for SkipList {
+    fn get(&self, key: Vec<u8>) -> Option<LogRecordPos> {
+        self.skl.get(&key).and_then(|entry| *entry.value().lock())
+    }
+
+    fn put(&self, key: Vec<u8>, pos: LogRecordPos) -> Option<LogRecordPos> {
+        loop {
+            let entry = self
+                .skl
+                .get_or_insert_with(key.clone(), || Mutex::new(None));
+            let mut cell = entry.value().lock();
+            // 单元已经被并发的删除移出跳表, 重新插入
+            if entry.is_removed() {
+                continue;
+            }
+            return cell.replace(pos);
+        }
+    }
+
+    fn delete(&self, key: Vec<u8>) -> Option<LogRecordPos> {
+        let entry = self.skl.remove(&key)?;
+        entry.value().lock().take()
+    }
+
+    fn len(&self) -> usize {
+        self.skl.len()
+    }
+
+    fn iterator(&self, options: IteratorOptions) -> Box<dyn IndexIterator> {
+        let items = match key_range(&options) {
+            Some(range) => self
+                .skl
+                .range(range)
+                .take_while(|entry| entry.key().starts_with(&options.prefix))
+                .filter_map(|entry| {
+                    let pos = (*entry.value().lock())?;
+                    Some((entry.key().clone(), pos))
+                })
+                .collect(),
+            None => Vec::new(),
+        };
+
+        Box::new(SnapshotIterator::new(items, options.reverse))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn skiplist_get_put_delete_should_work() {
+        let skl = SkipList::default();
+
+        let key = "".as_bytes().to_vec();
+
+        // success put
+        assert!(
+            skl.put(key.clone(), LogRecordPos::new(1, 10, 100))
+                .is_none()
+        );
+
+        // overwrite put, return the old pos
+        let old = skl.put(key.clone(), LogRecordPos::new(1, 20, 100));
+        assert!(old.is_some());
+        assert_eq!(old.unwrap().get_offset(), 10);
+
+        let pos = skl.get(key.clone());
+
+        // success get
+        assert!(pos.is_some());
+
+        assert_eq!(pos.unwrap().get_file_id(), 1);
+        assert_eq!(pos.unwrap().get_offset(), 20);
+        assert_eq!(pos.unwrap().get_size(), 100);
+
+        // success delete, return the removed pos
+        let removed = skl.delete(key.clone());
+        assert!(removed.is_some());
+        assert_eq!(removed.unwrap().get_offset(), 20);
+
+        // fail delete
+        assert!(skl.delete("None".as_bytes().to_vec()).is_none());
+
+        // fail get
+        assert!(skl.get("None".as_bytes().to_vec()).is_none());
+    }
+
+    #[test]
+    fn skiplist_concurrent_put_should_return_each_old_pos_once() {
+        let skl = Arc::new(SkipList::default());
+        let key = "key".as_bytes().to_vec();
+
+        let handles: Vec<_> = (0..4)
+            .map(|t| {
+                let skl = skl.clone();
+                let key = key.clone();
+                std::thread::spawn(move || {
+                    let mut replaced = Vec::new();
+                    for i in 0..1000 {
+                        let pos = LogRecordPos::new(t, i, 1);
+                        replaced.extend(skl.put(key.clone(), pos));
+                        if i % 10 == 0 {
+                            replaced.extend(skl.delete(key.clone()));
+                        }
+                    }
+                    replaced
+                })
+            })
+            .collect();
+
+        // 每个写入的位置要么被覆盖或删除返回一次, 要么是最后保留在索引中的位置
+        let mut seen: Vec<_> = handles
+            .into_iter()
+            .flat_map(|handle| handle.join().unwrap())
+            .collect();
+        seen.extend(skl.get(key.clone()));
+        seen.sort_by_key(|pos| (pos.get_file_id(), pos.get_offset()));
+        let total = seen.len();
+        seen.dedup();
+        assert_eq!(seen.len(), total);
+        assert_eq!(total, 4000);
+    }
+}
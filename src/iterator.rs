@@ -0,0 +1,248 @@
+use crate::{Engine, Errors, Result, index::IndexIterator, options::IteratorOptions};
+use bytes::Bytes;
+
+/// 按照 key 的顺序遍历数据的迭代器, value 在遍历到对应的 key 时才从数据文件中读取
+pub struct EngineIterator<'a> {
+    // 索引迭代器
+    index_iter: Box<dyn IndexIterator>,
+    engine: &'a Engine,
+    // 快照的提交序号, 为 None 时读取最新的数据
+    snapshot: Option<u64>,
+}
+
+impl Engine {
+    /// 获取迭代器
+    pub fn iter(&self, options: IteratorOptions) -> EngineIterator<'_> {
+        EngineIterator::new(self, self.index.iterator(options), None)
+    }
+
+    /// 获取数据库中所有的 key
+    pub fn list_keys(&self) -> Result<Vec<Bytes>> {
+        // 持有活跃文件的锁拷贝索引, 不会看到只更新了一部分索引的批量写入
+        let mut index_iter = {
+            let _active_file = self.active_file.read();
+            self.check_open()?;
+            self.index.iterator(IteratorOptions::default())
+        };
+        let mut keys = Vec::new();
+        while let Some((key, pos)) = index_iter.next() {
+            // 跳过已经过期的数据
+            if !pos.is_expired() {
+                keys.push(Bytes::copy_from_slice(key));
+            }
+        }
+        Ok(keys)
+    }
+
+    /// 按照 key 的顺序遍历所有数据, 函数返回 false 时终止遍历
+    /// 遍历基于开始时的快照进行, 遍历期间的写入和删除不可见, 不会长时间持有数据文件的锁
+    pub fn fold<F>(&self, mut f: F) -> Result<()>
+    where
+        F: FnMut(Bytes, Bytes) -> bool,
+    {
+        let snapshot = self.snapshot();
+        for item in snapshot.iter(IteratorOptions::default()) {
+            let (key, value) = item?;
+            if !f(key, value) {
+                break;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl<'a> EngineIterator<'a> {
+    pub(crate) fn new(
+        engine: &'a Engine,
+        index_iter: Box<dyn IndexIterator>,
+        snapshot: Option<u64>,
+    ) -> Self {
+        Self {
+            index_iter,
+            engine,
+            snapshot,
+        }
+    }
+
+    /// 重新回到迭代器的起点, 即第一个数据
+    pub fn rewind(&mut self) {
+        self.index_iter.rewind();
+    }
+
+    /// 根据传入的 key 查找到第一个大于 (反向遍历时为小于) 等于的目标 key, 从这个 key 开始遍历
+    pub fn seek(&mut self, key: Vec<u8>) {
+        self.index_iter.seek(key);
+    }
+}
+
+impl Iterator for EngineIterator<'_> {
+    type Item = Result<(Bytes, Bytes)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let key = self.index_iter.next()?.0.clone();
+            match self.engine.get_value_at(&key, self.snapshot) {
+                Ok(value) => return Some(Ok((Bytes::from(key), value))),
+                // 创建迭代器之后被删除的 key 直接跳过
+                Err(Errors::KeyNotFound) => continue,
+                Err(e) => return Some(Err(e)),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Options;
+    use std::{fs, path::PathBuf};
+
+    #[test]
+    fn engine_iterator_should_work() {
+        let opts = Options {
+            dir_path: PathBuf::from("/tmp/bitcask-iterator"),
+            ..Default::default()
+        };
+        let _ = fs::remove_dir_all(&opts.dir_path);
+
+        let engine = Engine::open(opts.clone()).expect("failed to open engine");
+
+        // 没有数据的情况
+        assert!(engine.iter(IteratorOptions::default()).next().is_none());
+
+        for key in ["aa", "ab", "b", "ca", "cb"] {
+            engine
+                .put(Bytes::from(key), Bytes::from(format!("{}-value", key)))
+                .unwrap();
+        }
+
+        let collect = |iter: EngineIterator| {
+            iter.map(|item| {
+                let (key, value) = item.unwrap();
+                assert_eq!(
+                    value,
+                    Bytes::from(format!("{}-value", String::from_utf8_lossy(&key)))
+                );
+                String::from_utf8(key.to_vec()).unwrap()
+            })
+            .collect::<Vec<_>>()
+        };
+
+        assert_eq!(
+            collect(engine.iter(IteratorOptions::default())),
+            vec!["aa", "ab", "b", "ca", "cb"]
+        );
+        assert_eq!(
+            collect(engine.iter(IteratorOptions {
+                prefix: b"a".to_vec(),
+                reverse: true,
+                ..Default::default()
+            })),
+            vec!["ab", "aa"]
+        );
+        assert_eq!(
+            collect(engine.iter(IteratorOptions {
+                start: Some(b"ab".to_vec()),
+                end: Some(b"cb".to_vec()),
+                ..Default::default()
+            })),
+            vec!["ab", "b", "ca"]
+        );
+
+        // 迭代器创建之后删除的 key 会被跳过
+        let mut iter = engine.iter(IteratorOptions::default());
+        engine.delete(Bytes::from("ab")).unwrap();
+        iter.seek(b"ab".to_vec());
+        assert_eq!(iter.next().unwrap().unwrap().0, Bytes::from("b"));
+        iter.rewind();
+        assert_eq!(collect(iter), vec!["aa", "b", "ca", "cb"]);
+
+        fs::remove_dir_all(opts.dir_path).expect("failed to remove path");
+    }
+
+    #[test]
+    fn engine_list_keys_and_fold_should_work() {
+        let opts = Options {
+            dir_path: PathBuf::from("/tmp/bitcask-list-keys-fold"),
+            ..Default::default()
+        };
+        let _ = fs::remove_dir_all(&opts.dir_path);
+
+        let engine = Engine::open(opts.clone()).expect("failed to open engine");
+        assert!(engine.list_keys().unwrap().is_empty());
+
+        for key in ["c", "a", "d", "b"] {
+            engine.put(Bytes::from(key), Bytes::from(key)).unwrap();
+        }
+        engine.delete(Bytes::from("d")).unwrap();
+
+        assert_eq!(
+            engine.list_keys().unwrap(),
+            vec![Bytes::from("a"), Bytes::from("b"), Bytes::from("c")]
+        );
+
+        // 遍历全部数据
+        let mut pairs = Vec::new();
+        engine
+            .fold(|key, value| {
+                pairs.push((key, value));
+                true
+            })
+            .unwrap();
+        assert_eq!(pairs.len(), 3);
+        assert!(pairs.iter().all(|(key, value)| key == value));
+
+        // 返回 false 时提前终止
+        let mut count = 0;
+        engine
+            .fold(|key, _| {
+                count += 1;
+                key != "b"
+            })
+            .unwrap();
+        assert_eq!(count, 2);
+
+        fs::remove_dir_all(opts.dir_path).expect("failed to remove path");
+    }
+
+    #[test]
+    fn engine_fold_should_not_see_concurrent_writes() {
+        let opts = Options {
+            dir_path: PathBuf::from("/tmp/bitcask-fold-snapshot"),
+            ..Default::default()
+        };
+        let _ = fs::remove_dir_all(&opts.dir_path);
+
+        let engine = Engine::open(opts.clone()).expect("failed to open engine");
+        for key in ["a", "b", "c"] {
+            engine.put(Bytes::from(key), Bytes::from("old")).unwrap();
+        }
+
+        // 遍历期间修改, 删除以及新增还没有遍历到的 key
+        let mut pairs = Vec::new();
+        engine
+            .fold(|key, value| {
+                if key == "a" {
+                    engine.put(Bytes::from("b"), Bytes::from("new")).unwrap();
+                    engine.delete(Bytes::from("c")).unwrap();
+                    engine.put(Bytes::from("d"), Bytes::from("new")).unwrap();
+                }
+                pairs.push((key, value));
+                true
+            })
+            .unwrap();
+        assert_eq!(
+            pairs,
+            ["a", "b", "c"]
+                .map(|key| (Bytes::from(key), Bytes::from("old")))
+                .to_vec()
+        );
+        assert_eq!(
+            engine.list_keys().unwrap(),
+            vec![Bytes::from("a"), Bytes::from("b"), Bytes::from("d")]
+        );
+
+        drop(engine);
+        fs::remove_dir_all(opts.dir_path).expect("failed to remove path");
+    }
+}
@@ -1,14 +1,36 @@
+mod backup;
+mod batch;
+mod compaction;
 mod data;
 mod db;
 mod error;
 mod fio;
+mod flusher;
+mod group_commit;
 mod index;
+mod iterator;
+mod merge;
 mod options;
+mod snapshot;
+mod transaction;
 
-pub use data::{DATA_FILE_SUFFIX, DataFile, LogRecord, LogRecordPos, LogRecordType, ReadLogRecord};
-pub use db::Engine;
+pub use batch::WriteBatch;
+pub use data::{
+    DATA_FILE_SUFFIX, DataFile, ENCRYPTION_KEY_SIZE, LogRecord, LogRecordPos, LogRecordType,
+    ReadLogRecord,
+};
+pub use db::{DiscardedData, Engine, Stat};
 pub use error::Errors;
 pub use error::Result;
-pub use fio::{FileIo, IoManger};
-pub use index::{BTree, Indexer};
-pub use options::Options;
+pub use fio::{
+    DirLock, FaultIo, FileIo, FileSystem, IoManger, MMapIo, MemoryFileSystem, MemoryIo,
+    StdFileSystem,
+};
+pub use index::{BTree, IndexIterator, Indexer, SkipList};
+pub use iterator::EngineIterator;
+pub use options::{
+    CompactionOptions, CompressionType, IndexType, IoType, IteratorOptions, Options,
+    RecoveryPolicy, RestoreTarget, SyncPolicy, TransactionOptions, WriteBatchOptions,
+};
+pub use snapshot::Snapshot;
+pub use transaction::Transaction;
@@ -0,0 +1,990 @@
+use crate::{
+    DataFile, Engine, Errors, FileSystem, LogRecord, LogRecordPos, LogRecordType, Result,
+    data::{
+        Cipher, DATA_FILE_HEADER_SIZE, MERGE_FINISHED_FILE_NAME, NON_BATCH_SEQ_NO,
+        get_data_file_name, get_hint_file_name,
+    },
+    db::{INITIAL_FILE_ID, is_corrupted},
+    options::{CompressionType, RecoveryPolicy},
+};
+use bytes::{Buf, BufMut, BytesMut};
+use log::{error, warn};
+use std::{
+    path::{Path, PathBuf},
+    sync::{Arc, atomic::Ordering},
+};
+
+const MERGE_DIR_SUFFIX: &str = "merge";
+const MERGE_FIN_KEY: &[u8] = "merge.finished".as_bytes();
+
+/// merge 过程中被重写的一条记录, 包含其原来的位置和在 merge 文件中的新位置
+/// 已经过期的记录不会被重写, 新位置为 None
+struct MergedRecord {
+    key: Vec<u8>,
+    old_pos: LogRecordPos,
+    new_pos: Option<LogRecordPos>,
+}
+
+impl Engine {
+    /// merge 数据目录, 将旧数据文件中的有效数据重写到新的数据文件中, 清理无效数据
+    /// merge 期间仍然可以正常读写, 完成后会用新的数据文件替换掉旧的数据文件
+    pub fn merge(&self) -> Result<()> {
+        self.merge_with(false)
+    }
+
+    /// 执行 merge, 后台 compaction 触发的 merge 会受到限速以及暂停的控制
+    pub(crate) fn merge_with(&self, background: bool) -> Result<()> {
+        // 如果 merge 正在进行当中, 则直接返回
+        let lock = self.merging_lock.try_lock();
+        if lock.is_none() {
+            return Err(Errors::MergeInProgress);
+        }
+        self.check_open()?;
+
+        let merge_path = get_merge_path(&self.options.dir_path);
+        // 如果目录已经存在, 说明上一次 merge 没有完成, 直接删除
+        if self.fs.is_dir(&merge_path) {
+            remove_merge_dir(self.fs.as_ref(), &merge_path)?;
+        }
+
+        // 创建 merge 数据目录
+        if let Err(e) = self.fs.create_dir_all(&merge_path) {
+            warn!("failed to create merge path: {}", e);
+            return Err(Errors::FailedToCreateDatabaseDir);
+        }
+
+        // 获取需要 merge 的数据文件, 以及没有参与 merge 的最小文件 id
+        let (merge_file_ids, non_merge_file_id) = self.rotate_merge_files()?;
+        if merge_file_ids.is_empty() {
+            return remove_merge_dir(self.fs.as_ref(), &merge_path);
+        }
+
+        let merged = match self.write_merge_files(
+            &merge_path,
+            &merge_file_ids,
+            non_merge_file_id,
+            background,
+        ) {
+            Ok(merged) => merged,
+            Err(e) => {
+                // 被取消的 merge 不会替换数据文件, 直接删除 merge 目录
+                if e == Errors::MergeCancelled {
+                    remove_merge_dir(self.fs.as_ref(), &merge_path)?;
+                }
+                return Err(e);
+            }
+        };
+
+        // 用 merge 之后的数据文件替换掉旧的数据文件
+        self.swap_merge_files(&merge_path, non_merge_file_id, merged)
+    }
+
+    /// 将当前活跃文件转为旧的数据文件, 返回所有需要 merge 的文件 id
+    fn rotate_merge_files(&self) -> Result<(Vec<u32>, u32)> {
+        let mut active_file = self.active_file.write();
+        self.rotate_active_file(&mut active_file)?;
+
+        let non_merge_file_id = active_file.get_file_id();
+        let older_files = self.older_files.read();
+        let mut merge_file_ids: Vec<u32> = older_files
+            .keys()
+            .copied()
+            .filter(|fid| *fid < non_merge_file_id)
+            .collect();
+        merge_file_ids.sort_unstable();
+
+        Ok((merge_file_ids, non_merge_file_id))
+    }
+
+    /// 将需要 merge 的数据文件中的有效数据重写到 merge 目录中, 最后写入 merge 完成标识
+    fn write_merge_files(
+        &self,
+        merge_path: &Path,
+        merge_file_ids: &[u32],
+        non_merge_file_id: u32,
+        background: bool,
+    ) -> Result<Vec<MergedRecord>> {
+        if background {
+            self.compaction.reset_rate_limiter();
+        }
+        let mut writer = MergeWriter::new(
+            self.fs.clone(),
+            merge_path.to_path_buf(),
+            non_merge_file_id,
+            self.options.data_file_size,
+            self.options.compression,
+            self.cipher.clone(),
+        )?;
+        let mut merged = Vec::new();
+
+        // 依次处理每个数据文件, 重写有效的数据
+        for file_id in merge_file_ids {
+            let mut offset = DATA_FILE_HEADER_SIZE;
+            loop {
+                // 每次读取时获取锁, 避免阻塞前台的写入
+                let res = {
+                    let older_files = self.older_files.read();
+                    match older_files.get(file_id) {
+                        Some(data_file) => data_file.read_log_record(offset),
+                        None => return Err(Errors::DataFileNotFound),
+                    }
+                };
+
+                let (mut log_record, size) = match res {
+                    Ok(result) => (result.record, result.size),
+                    Err(Errors::ReadDataFileEOF) => break,
+                    // 打开数据库时跳过的损坏数据, merge 时同样跳过, 不会被重写
+                    // 打开时记录的范围之后是完整的记录或者文件末尾时直接使用, 否则重新查找下一条有效的记录
+                    Err(e)
+                        if is_corrupted(&e)
+                            && self.options.recovery_policy == RecoveryPolicy::Skip =>
+                    {
+                        let older_files = self.older_files.read();
+                        let data_file = older_files.get(file_id).ok_or(Errors::DataFileNotFound)?;
+                        let skipped = self.discarded.iter().find(|data| {
+                            data.file_id == *file_id && data.offset == offset && !data.truncated
+                        });
+                        offset = match skipped {
+                            Some(data)
+                                if offset + data.size == data_file.file_size()?
+                                    || data_file.read_log_record(offset + data.size).is_ok() =>
+                            {
+                                offset + data.size
+                            }
+                            _ => data_file.next_valid_record(offset + 1)?,
+                        };
+                        continue;
+                    }
+                    Err(e) => return Err(e),
+                };
+
+                // 内存索引中的位置和当前记录一致, 说明是有效的数据, 重写到 merge 文件中
+                // 快照还能看到的旧版本也需要重写, 写入为 SNAPSHOT 类型并且不写入 hint 文件, 重启之后不会被加载到索引中
+                // 提交时先在修改历史中记录旧的位置再更新索引, 每条记录先检查索引再检查修改历史
+                // merge 期间被覆盖的记录和新创建的快照引用的记录都不会被遗漏
+                let old_pos = LogRecordPos::new(*file_id, offset, size as u32)
+                    .with_expire_at(log_record.expire_at);
+                let live = self
+                    .index
+                    .get(log_record.key.clone())
+                    .is_some_and(|pos| pos.get_file_id() == *file_id && pos.get_offset() == offset);
+                if matches!(
+                    log_record.rec_type,
+                    LogRecordType::NORMAL | LogRecordType::SNAPSHOT
+                ) && (live || self.commits.is_referenced(&log_record.key, &old_pos))
+                {
+                    // 已经过期的数据直接丢弃, 替换文件时从内存索引中删除
+                    if old_pos.is_expired() {
+                        merged.push(MergedRecord {
+                            key: log_record.key,
+                            old_pos,
+                            new_pos: None,
+                        });
+                    } else {
+                        // 重写之后的数据都已经生效, 不再需要批量写入的序列号
+                        log_record.seq_no = NON_BATCH_SEQ_NO;
+                        log_record.rec_type = match live {
+                            true => LogRecordType::NORMAL,
+                            false => LogRecordType::SNAPSHOT,
+                        };
+                        let new_pos = writer.append(&mut log_record)?;
+                        if live {
+                            writer.write_hint(log_record.key.clone(), new_pos)?;
+                        }
+                        merged.push(MergedRecord {
+                            key: log_record.key,
+                            old_pos,
+                            new_pos: Some(new_pos),
+                        });
+                    }
+                }
+
+                // 后台 merge 需要限制读写速度, 暂停时等待恢复
+                if background && !self.compaction.throttle(size) {
+                    return Err(Errors::MergeCancelled);
+                }
+
+                offset += size;
+            }
+        }
+
+        // 持久化 merge 的数据文件和 hint 文件, 然后写入 merge 完成的标识
+        writer.sync()?;
+        let merge_fin_file =
+            DataFile::new_merge_fin_file(self.fs.as_ref(), merge_path.to_path_buf())?;
+        let mut merge_fin_record = LogRecord::new(
+            MERGE_FIN_KEY.to_vec(),
+            encode_merge_fin_value(non_merge_file_id, writer.file_count()),
+        );
+        merge_fin_file.write(&merge_fin_record.encode())?;
+        merge_fin_file.sync()?;
+
+        Ok(merged)
+    }
+
+    /// 用 merge 目录中的数据文件替换旧的数据文件, 并更新内存索引
+    fn swap_merge_files(
+        &self,
+        merge_path: &Path,
+        non_merge_file_id: u32,
+        merged: Vec<MergedRecord>,
+    ) -> Result<()> {
+        // 替换期间阻塞前台的读写, 索引更新和文件替换对外是原子的
+        let _active_file = self.active_file.write();
+        let mut older_files = self.older_files.write();
+
+        // 替换之前先打开 merge 目录中的数据文件, 打开失败时数据目录还没有被修改
+        // rename 之后已经打开的文件仍然指向同一个文件
+        let (_, merge_file_count) = read_merge_fin_file(self.fs.as_ref(), merge_path)?;
+        let mut merged_files = Vec::with_capacity(merge_file_count as usize);
+        for file_id in INITIAL_FILE_ID..INITIAL_FILE_ID + merge_file_count {
+            merged_files.push(self.open_older_file(merge_path.to_path_buf(), file_id)?);
+        }
+
+        // 部分文件已经被替换时, 内存中的数据文件和索引都无法和磁盘保持一致
+        // 之后的读写都返回错误, 重新打开时根据 merge 完成标识继续替换
+        if let Err(e) = move_merge_files(self.fs.as_ref(), &self.options.dir_path, merge_path) {
+            error!(
+                "failed to swap merge files, the database must be reopened: {}",
+                e
+            );
+            self.poisoned.store(true, Ordering::SeqCst);
+            return Err(e);
+        }
+
+        older_files.retain(|fid, _| *fid >= non_merge_file_id);
+        for data_file in merged_files {
+            older_files.insert(data_file.get_file_id(), data_file);
+        }
+
+        let mut dead_bytes = self.dead_bytes.write();
+        dead_bytes.retain(|fid, _| *fid >= non_merge_file_id);
+
+        // 快照的修改历史中引用的旧位置同样需要更新
+        let relocated = merged
+            .iter()
+            .map(|record| {
+                let old_pos = record.old_pos;
+                (
+                    (old_pos.get_file_id(), old_pos.get_offset()),
+                    record.new_pos,
+                )
+            })
+            .collect();
+        self.commits.relocate(&relocated);
+
+        // 更新内存索引, merge 期间已经被覆盖或删除的 key 在新文件中是失效数据
+        for record in merged {
+            match (self.index.get(record.key.clone()), record.new_pos) {
+                (Some(pos), Some(new_pos)) if pos == record.old_pos => {
+                    self.index.put(record.key, new_pos);
+                }
+                (Some(pos), None) if pos == record.old_pos => {
+                    self.index.delete(record.key);
+                }
+                (_, Some(new_pos)) => {
+                    *dead_bytes.entry(new_pos.get_file_id()).or_default() +=
+                        new_pos.get_size() as u64;
+                }
+                (_, None) => {}
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl Engine {
+    /// 从 hint 文件中加载数据文件的索引, 没有对应的 hint 文件时返回 false
+    pub(crate) fn load_index_from_hint_file(&self, file_id: u32) -> Result<bool> {
+        let dir_path = self.options.dir_path.clone();
+        if !self.fs.is_file(&get_hint_file_name(&dir_path, file_id)) {
+            return Ok(false);
+        }
+
+        let hint_file = DataFile::open_hint_file(self.fs.as_ref(), dir_path, file_id)?
+            .with_cipher(self.cipher.clone());
+        let mut offset = DATA_FILE_HEADER_SIZE;
+        loop {
+            let (log_record, size) = match hint_file.read_log_record(offset) {
+                Ok(result) => (result.record, result.size),
+                Err(e) => {
+                    if e == Errors::ReadDataFileEOF {
+                        break;
+                    }
+                    return Err(e);
+                }
+            };
+
+            // hint 记录的 value 是数据的位置信息
+            let log_record_pos = LogRecordPos::decode(&log_record.value)?;
+            self.update_index(log_record.key, LogRecordType::NORMAL, log_record_pos);
+
+            offset += size;
+        }
+
+        Ok(true)
+    }
+}
+
+/// merge 数据文件的写入, 文件 id 从 INITIAL_FILE_ID 开始递增, 每个数据文件对应一个 hint 文件
+struct MergeWriter {
+    fs: Arc<dyn FileSystem>,
+    dir_path: PathBuf,
+    // 没有参与 merge 的最小文件 id, merge 的文件 id 不能达到该值, 否则替换时会覆盖之后的数据文件
+    non_merge_file_id: u32,
+    data_file_size: u64,
+    // 重写的数据使用当前配置的压缩类型
+    compression: CompressionType,
+    // 重写的数据使用当前的密钥加密, 完成密钥的轮换
+    cipher: Option<Arc<Cipher>>,
+    active_file: DataFile,
+    hint_file: DataFile,
+}
+
+impl MergeWriter {
+    fn new(
+        fs: Arc<dyn FileSystem>,
+        dir_path: PathBuf,
+        non_merge_file_id: u32,
+        data_file_size: u64,
+        compression: CompressionType,
+        cipher: Option<Arc<Cipher>>,
+    ) -> Result<Self> {
+        let active_file = DataFile::new(fs.as_ref(), dir_path.clone(), INITIAL_FILE_ID)?
+            .with_cipher(cipher.clone());
+        let hint_file = DataFile::new_hint_file(fs.as_ref(), dir_path.clone(), INITIAL_FILE_ID)?
+            .with_cipher(cipher.clone());
+        Ok(Self {
+            fs,
+            dir_path,
+            non_merge_file_id,
+            data_file_size,
+            compression,
+            cipher,
+            active_file,
+            hint_file,
+        })
+    }
+
+    fn append(&mut self, log_record: &mut LogRecord) -> Result<LogRecordPos> {
+        let enc_record = self
+            .active_file
+            .encode_log_record(log_record, self.compression)?;
+        let record_len = enc_record.len() as u64;
+
+        // 当前文件达到阀值, 持久化之后打开一个新的文件
+        // 调小 data_file_size, 开启加密或者关闭压缩之后 merge 的数据可能比原来更多
+        // 文件 id 用完时继续写入最后一个文件, 该文件会超过阀值
+        let file_id = self.active_file.get_file_id() + 1;
+        if self.active_file.get_write_off() + record_len > self.data_file_size
+            && file_id < self.non_merge_file_id
+        {
+            self.sync()?;
+            self.active_file = DataFile::new(self.fs.as_ref(), self.dir_path.clone(), file_id)?
+                .with_cipher(self.cipher.clone());
+            self.hint_file =
+                DataFile::new_hint_file(self.fs.as_ref(), self.dir_path.clone(), file_id)?
+                    .with_cipher(self.cipher.clone());
+        }
+
+        let write_off = self.active_file.get_write_off();
+        self.active_file.write(&enc_record)?;
+
+        Ok(
+            LogRecordPos::new(self.active_file.get_file_id(), write_off, record_len as u32)
+                .with_expire_at(log_record.expire_at),
+        )
+    }
+
+    /// 写入 hint 记录到当前数据文件对应的 hint 文件中
+    fn write_hint(&self, key: Vec<u8>, pos: LogRecordPos) -> Result<()> {
+        self.hint_file.write_hint_record(key, pos)
+    }
+
+    fn sync(&self) -> Result<()> {
+        self.active_file.sync()?;
+        self.hint_file.sync()
+    }
+
+    fn file_count(&self) -> u32 {
+        self.active_file.get_file_id() - INITIAL_FILE_ID + 1
+    }
+}
+
+/// 获取 merge 数据目录, 和数据目录在同一级
+fn get_merge_path(dir_path: &Path) -> PathBuf {
+    let file_name = dir_path.file_name().unwrap_or_default();
+    let merge_name = format!("{}-{}", file_name.to_string_lossy(), MERGE_DIR_SUFFIX);
+    let parent = dir_path.parent().unwrap_or(dir_path);
+    parent.join(merge_name)
+}
+
+/// 启动时加载 merge 数据目录, 完成的 merge 替换到数据目录中, 未完成的直接删除
+pub(crate) fn load_merge_files(fs: &dyn FileSystem, dir_path: &Path) -> Result<()> {
+    let merge_path = get_merge_path(dir_path);
+    if !fs.is_dir(&merge_path) {
+        return Ok(());
+    }
+
+    // 没有完整的 merge 完成标识, 说明 merge 过程中发生了崩溃, 丢弃 merge 的数据
+    if read_merge_fin_file(fs, &merge_path).is_err() {
+        return remove_merge_dir(fs, &merge_path);
+    }
+
+    move_merge_files(fs, dir_path, &merge_path)?;
+    Ok(())
+}
+
+/// 将 merge 目录中的数据文件移动到数据目录中, 并删除被 merge 的旧数据文件
+/// 每一步都可以重复执行, 中途崩溃之后下次启动时重新执行即可, 返回 merge 后的文件数量
+fn move_merge_files(fs: &dyn FileSystem, dir_path: &Path, merge_path: &Path) -> Result<u32> {
+    let (non_merge_file_id, merge_file_count) = read_merge_fin_file(fs, merge_path)?;
+
+    // rename 会原子地替换掉数据目录中相同 id 的旧文件
+    for file_id in INITIAL_FILE_ID..INITIAL_FILE_ID + merge_file_count {
+        for get_file_name in [get_data_file_name, get_hint_file_name] {
+            let src = get_file_name(merge_path, file_id);
+            if !fs.is_file(&src) {
+                continue;
+            }
+            if let Err(e) = fs.rename(&src, &get_file_name(dir_path, file_id)) {
+                error!("failed to move merge file: {}", e);
+                return Err(Errors::FailedToSwapMergeFiles);
+            }
+        }
+    }
+
+    // 删除没有被替换掉的旧数据文件, 以及之前 merge 留下的 hint 文件
+    for file_id in INITIAL_FILE_ID + merge_file_count..non_merge_file_id {
+        for path in [
+            get_data_file_name(dir_path, file_id),
+            get_hint_file_name(dir_path, file_id),
+        ] {
+            if fs.is_file(&path)
+                && let Err(e) = fs.remove_file(&path)
+            {
+                error!("failed to remove merged file: {}", e);
+                return Err(Errors::FailedToSwapMergeFiles);
+            }
+        }
+    }
+
+    remove_merge_dir(fs, merge_path)?;
+    Ok(merge_file_count)
+}
+
+fn remove_merge_dir(fs: &dyn FileSystem, merge_path: &Path) -> Result<()> {
+    if let Err(e) = fs.remove_dir_all(merge_path) {
+        error!("failed to remove merge dir: {}", e);
+        return Err(Errors::FailedToSwapMergeFiles);
+    }
+    Ok(())
+}
+
+/// 读取 merge 完成标识, 返回没有参与 merge 的最小文件 id 和 merge 后的文件数量
+fn read_merge_fin_file(fs: &dyn FileSystem, merge_path: &Path) -> Result<(u32, u32)> {
+    if !fs.is_file(&merge_path.join(MERGE_FINISHED_FILE_NAME)) {
+        return Err(Errors::MergeNotFinished);
+    }
+
+    let merge_fin_file = DataFile::open_merge_fin_file(fs, merge_path.to_path_buf())?;
+    let record = merge_fin_file
+        .read_log_record(DATA_FILE_HEADER_SIZE)?
+        .record;
+    if record.key != MERGE_FIN_KEY || record.value.len() != 8 {
+        return Err(Errors::MergeNotFinished);
+    }
+
+    // merge 的文件数量超过被 merge 的文件 id 范围时, 替换会覆盖之后的数据文件
+    let mut value = record.value.as_slice();
+    let (non_merge_file_id, merge_file_count) = (value.get_u32(), value.get_u32());
+    if INITIAL_FILE_ID + merge_file_count > non_merge_file_id {
+        return Err(Errors::MergeNotFinished);
+    }
+    Ok((non_merge_file_id, merge_file_count))
+}
+
+fn encode_merge_fin_value(non_merge_file_id: u32, merge_file_count: u32) -> Vec<u8> {
+    let mut buf = BytesMut::with_capacity(8);
+    buf.put_u32(non_merge_file_id);
+    buf.put_u32(merge_file_count);
+    buf.to_vec()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{IoType, MemoryFileSystem, Options, StdFileSystem};
+    use bytes::Bytes;
+    use std::{fs, thread};
+
+    fn get_test_key(i: usize) -> Bytes {
+        Bytes::from(format!("bitcask-key-{:09}", i))
+    }
+
+    fn get_test_value(i: usize) -> Bytes {
+        Bytes::from(format!("bitcask-value-{:09}-{}", i, "x".repeat(64)))
+    }
+
+    fn dir_size(dir_path: &Path) -> u64 {
+        fs::read_dir(dir_path)
+            .unwrap()
+            .flatten()
+            .map(|entry| entry.metadata().unwrap().len())
+            .sum()
+    }
+
+    #[test]
+    fn merge_empty_engine_should_work() {
+        let opts = Options {
+            dir_path: PathBuf::from("/tmp/bitcask-merge-empty"),
+            data_file_size: 32 * 1024,
+            ..Default::default()
+        };
+        let _ = fs::remove_dir_all(&opts.dir_path);
+
+        let engine = Engine::open(opts.clone()).expect("failed to open engine");
+        assert!(engine.merge().is_ok());
+        assert!(!get_merge_path(&opts.dir_path).exists());
+
+        fs::remove_dir_all(opts.dir_path).expect("failed to remove path");
+    }
+
+    #[test]
+    fn merge_should_reclaim_dead_data() {
+        let opts = Options {
+            dir_path: PathBuf::from("/tmp/bitcask-merge-reclaim"),
+            data_file_size: 32 * 1024,
+            ..Default::default()
+        };
+        let _ = fs::remove_dir_all(&opts.dir_path);
+
+        let engine = Engine::open(opts.clone()).expect("failed to open engine");
+        for i in 0..5000 {
+            engine.put(get_test_key(i), get_test_value(i)).unwrap();
+        }
+        // 覆盖一部分, 删除一部分
+        for i in 0..2000 {
+            engine
+                .put(get_test_key(i), Bytes::from("new-value"))
+                .unwrap();
+        }
+        for i in 2000..4000 {
+            engine.delete(get_test_key(i)).unwrap();
+        }
+
+        let size_before = dir_size(&opts.dir_path);
+        assert!(engine.merge().is_ok());
+        let size_after = dir_size(&opts.dir_path);
+        assert!(size_after < size_before);
+
+        // 失效数据已经全部被清理
+        assert!(engine.dead_bytes.read().values().all(|size| *size == 0));
+
+        let check = |engine: &Engine| {
+            for i in 0..2000 {
+                assert_eq!(
+                    engine.get(get_test_key(i)).unwrap(),
+                    Bytes::from("new-value")
+                );
+            }
+            for i in 2000..4000 {
+                assert_eq!(engine.get(get_test_key(i)).err(), Some(Errors::KeyNotFound));
+            }
+            for i in 4000..5000 {
+                assert_eq!(engine.get(get_test_key(i)).unwrap(), get_test_value(i));
+            }
+        };
+        check(&engine);
+
+        // merge 之后继续写入
+        engine
+            .put(get_test_key(0), Bytes::from("after-merge"))
+            .unwrap();
+        drop(engine);
+
+        // 重启之后数据仍然正确
+        let engine = Engine::open(opts.clone()).expect("failed to reopen engine");
+        assert_eq!(
+            engine.get(get_test_key(0)).unwrap(),
+            Bytes::from("after-merge")
+        );
+        for i in 1..2000 {
+            assert_eq!(
+                engine.get(get_test_key(i)).unwrap(),
+                Bytes::from("new-value")
+            );
+        }
+        for i in 2000..4000 {
+            assert_eq!(engine.get(get_test_key(i)).err(), Some(Errors::KeyNotFound));
+        }
+        for i in 4000..5000 {
+            assert_eq!(engine.get(get_test_key(i)).unwrap(), get_test_value(i));
+        }
+
+        // 再次 merge 也可以正常工作
+        assert!(engine.merge().is_ok());
+        assert_eq!(
+            engine.get(get_test_key(0)).unwrap(),
+            Bytes::from("after-merge")
+        );
+        for i in 4000..5000 {
+            assert_eq!(engine.get(get_test_key(i)).unwrap(), get_test_value(i));
+        }
+
+        fs::remove_dir_all(opts.dir_path).expect("failed to remove path");
+    }
+
+    #[test]
+    fn merge_should_write_hint_files() {
+        let opts = Options {
+            dir_path: PathBuf::from("/tmp/bitcask-merge-hint"),
+            data_file_size: 32 * 1024,
+            ..Default::default()
+        };
+        let _ = fs::remove_dir_all(&opts.dir_path);
+
+        let engine = Engine::open(opts.clone()).expect("failed to open engine");
+        for i in 0..3000 {
+            engine.put(get_test_key(i), get_test_value(i)).unwrap();
+        }
+        for i in 0..1000 {
+            engine.delete(get_test_key(i)).unwrap();
+        }
+        assert!(engine.merge().is_ok());
+
+        // 每个 merge 之后的数据文件都有对应的 hint 文件
+        let merged_file_ids: Vec<u32> = engine
+            .older_files
+            .read()
+            .keys()
+            .copied()
+            .filter(|fid| get_hint_file_name(&opts.dir_path, *fid).is_file())
+            .collect();
+        assert!(!merged_file_ids.is_empty());
+
+        // hint 文件中记录的位置和内存索引一致
+        let hint_file =
+            DataFile::open_hint_file(&StdFileSystem, opts.dir_path.clone(), INITIAL_FILE_ID)
+                .unwrap();
+        let hint_record = hint_file.read_log_record(DATA_FILE_HEADER_SIZE).unwrap();
+        let pos = LogRecordPos::decode(&hint_record.record.value).unwrap();
+        assert_eq!(engine.index.get(hint_record.record.key), Some(pos));
+
+        engine
+            .put(get_test_key(2000), Bytes::from("new-value"))
+            .unwrap();
+        drop(engine);
+
+        // 重启之后从 hint 文件加载索引
+        let engine = Engine::open(opts.clone()).expect("failed to reopen engine");
+        for i in 0..1000 {
+            assert_eq!(engine.get(get_test_key(i)).err(), Some(Errors::KeyNotFound));
+        }
+        assert_eq!(
+            engine.get(get_test_key(2000)).unwrap(),
+            Bytes::from("new-value")
+        );
+        for i in (1000..3000).filter(|i| *i != 2000) {
+            assert_eq!(engine.get(get_test_key(i)).unwrap(), get_test_value(i));
+        }
+
+        fs::remove_dir_all(opts.dir_path).expect("failed to remove path");
+    }
+
+    #[test]
+    fn merge_with_concurrent_writes_should_work() {
+        let opts = Options {
+            dir_path: PathBuf::from("/tmp/bitcask-merge-concurrent"),
+            data_file_size: 32 * 1024,
+            ..Default::default()
+        };
+        let _ = fs::remove_dir_all(&opts.dir_path);
+
+        let engine = Arc::new(Engine::open(opts.clone()).expect("failed to open engine"));
+        for i in 0..5000 {
+            engine.put(get_test_key(i), get_test_value(i)).unwrap();
+        }
+
+        // merge 的同时写入和删除数据
+        let writer = {
+            let engine = engine.clone();
+            thread::spawn(move || {
+                for i in 0..2000 {
+                    engine
+                        .put(get_test_key(i), Bytes::from("new-value"))
+                        .unwrap();
+                }
+                for i in 2000..3000 {
+                    engine.delete(get_test_key(i)).unwrap();
+                }
+            })
+        };
+        assert!(engine.merge().is_ok());
+        writer.join().unwrap();
+
+        let check = |engine: &Engine| {
+            for i in 0..2000 {
+                assert_eq!(
+                    engine.get(get_test_key(i)).unwrap(),
+                    Bytes::from("new-value")
+                );
+            }
+            for i in 2000..3000 {
+                assert_eq!(engine.get(get_test_key(i)).err(), Some(Errors::KeyNotFound));
+            }
+            for i in 3000..5000 {
+                assert_eq!(engine.get(get_test_key(i)).unwrap(), get_test_value(i));
+            }
+        };
+        check(&engine);
+        drop(engine);
+
+        let engine = Engine::open(opts.clone()).expect("failed to reopen engine");
+        check(&engine);
+
+        fs::remove_dir_all(opts.dir_path).expect("failed to remove path");
+    }
+
+    #[test]
+    fn merge_interrupted_should_recover_on_open() {
+        let fs = MemoryFileSystem::new();
+        let opts = Options {
+            dir_path: PathBuf::from("/tmp/bitcask-merge-interrupted"),
+            data_file_size: 32 * 1024,
+            io_type: IoType::Memory(fs.clone()),
+            ..Default::default()
+        };
+        let merge_path = get_merge_path(&opts.dir_path);
+
+        let engine = Engine::open(opts.clone()).expect("failed to open engine");
+        for i in 0..3000 {
+            engine.put(get_test_key(i), get_test_value(i)).unwrap();
+        }
+        for i in 0..1000 {
+            engine.delete(get_test_key(i)).unwrap();
+        }
+
+        // 模拟写入 merge 完成标识之后, 替换数据文件之前崩溃
+        fs.create_dir_all(&merge_path).unwrap();
+        let (merge_file_ids, non_merge_file_id) = engine.rotate_merge_files().unwrap();
+        engine
+            .write_merge_files(&merge_path, &merge_file_ids, non_merge_file_id, false)
+            .unwrap();
+        drop(engine);
+
+        // 重启时完成文件的替换
+        let engine = Engine::open(opts.clone()).expect("failed to reopen engine");
+        assert!(!fs.is_dir(&merge_path));
+        for i in 0..1000 {
+            assert_eq!(engine.get(get_test_key(i)).err(), Some(Errors::KeyNotFound));
+        }
+        for i in 1000..3000 {
+            assert_eq!(engine.get(get_test_key(i)).unwrap(), get_test_value(i));
+        }
+
+        // 模拟写入 merge 完成标识之前崩溃, 重启时丢弃 merge 的数据
+        fs.create_dir_all(&merge_path).unwrap();
+        let mut writer = MergeWriter::new(
+            Arc::new(fs.clone()),
+            merge_path.clone(),
+            engine.active_file.read().get_file_id(),
+            opts.data_file_size,
+            opts.compression,
+            None,
+        )
+        .unwrap();
+        writer
+            .append(&mut LogRecord::new(
+                get_test_key(0).to_vec(),
+                get_test_value(0).to_vec(),
+            ))
+            .unwrap();
+        drop(engine);
+
+        let engine = Engine::open(opts.clone()).expect("failed to reopen engine");
+        assert!(!fs.is_dir(&merge_path));
+        assert_eq!(engine.get(get_test_key(0)).err(), Some(Errors::KeyNotFound));
+        for i in 1000..3000 {
+            assert_eq!(engine.get(get_test_key(i)).unwrap(), get_test_value(i));
+        }
+    }
+
+    #[test]
+    fn merge_swap_failure_should_poison_engine() {
+        let fs = MemoryFileSystem::new();
+        let opts = Options {
+            dir_path: PathBuf::from("/tmp/bitcask-merge-swap-failure"),
+            data_file_size: 32 * 1024,
+            io_type: IoType::Memory(fs.clone()),
+            ..Default::default()
+        };
+        let merge_path = get_merge_path(&opts.dir_path);
+
+        let engine = Engine::open(opts.clone()).expect("failed to open engine");
+        for i in 0..3000 {
+            engine.put(get_test_key(i), get_test_value(i)).unwrap();
+        }
+        for i in 0..1000 {
+            engine.delete(get_test_key(i)).unwrap();
+        }
+
+        // 第二个 hint 文件无法替换, 此时第一个文件已经被替换
+        let blocker = get_hint_file_name(&opts.dir_path, INITIAL_FILE_ID + 1);
+        fs.create_dir_all(&blocker).unwrap();
+        assert_eq!(engine.merge().err(), Some(Errors::FailedToSwapMergeFiles));
+
+        // 内存中的数据文件已经和磁盘不一致, 不能再读写
+        assert_eq!(
+            engine.get(get_test_key(1000)).err(),
+            Some(Errors::EnginePoisoned)
+        );
+        assert_eq!(
+            engine.put(get_test_key(0), get_test_value(0)).err(),
+            Some(Errors::EnginePoisoned)
+        );
+        drop(engine);
+
+        // 重新打开时完成替换
+        fs.remove_dir_all(&blocker).unwrap();
+        let engine = Engine::open(opts.clone()).expect("failed to reopen engine");
+        assert!(!fs.is_dir(&merge_path));
+        for i in 0..1000 {
+            assert_eq!(engine.get(get_test_key(i)).err(), Some(Errors::KeyNotFound));
+        }
+        for i in 1000..3000 {
+            assert_eq!(engine.get(get_test_key(i)).unwrap(), get_test_value(i));
+        }
+    }
+
+    #[test]
+    fn merge_should_rotate_encryption_key() {
+        let opts = Options {
+            dir_path: PathBuf::from("/tmp/bitcask-merge-rotate-key"),
+            data_file_size: 32 * 1024,
+            encryption_key: Some([1; crate::ENCRYPTION_KEY_SIZE]),
+            ..Default::default()
+        };
+        let _ = fs::remove_dir_all(&opts.dir_path);
+
+        let engine = Engine::open(opts.clone()).expect("failed to open engine");
+        for i in 0..1000 {
+            engine.put(get_test_key(i), get_test_value(i)).unwrap();
+        }
+        drop(engine);
+
+        // 轮换密钥, 旧密钥仍然可以解密旧的数据, 新的数据使用新密钥加密
+        let rotated = Options {
+            encryption_key: Some([2; crate::ENCRYPTION_KEY_SIZE]),
+            old_encryption_keys: vec![[1; crate::ENCRYPTION_KEY_SIZE]],
+            ..opts.clone()
+        };
+        let engine = Engine::open(rotated.clone()).expect("failed to open engine");
+        for i in 1000..1500 {
+            engine.put(get_test_key(i), get_test_value(i)).unwrap();
+        }
+        engine.merge().expect("failed to merge");
+        drop(engine);
+
+        // merge 之后所有的数据都使用新密钥加密, 不再需要旧密钥
+        let engine = Engine::open(Options {
+            old_encryption_keys: Vec::new(),
+            ..rotated
+        })
+        .expect("failed to reopen engine");
+        for i in 0..1500 {
+            assert_eq!(engine.get(get_test_key(i)).unwrap(), get_test_value(i));
+        }
+        drop(engine);
+
+        assert_eq!(
+            Engine::open(opts.clone()).err(),
+            Some(Errors::InvalidEncryptionKey)
+        );
+
+        fs::remove_dir_all(opts.dir_path).expect("failed to remove path");
+    }
+
+    #[test]
+    fn merge_on_memory_file_system_should_survive_power_loss() {
+        let fs = MemoryFileSystem::new();
+        let opts = Options {
+            dir_path: PathBuf::from("/tmp/bitcask-merge-memory"),
+            data_file_size: 32 * 1024,
+            io_type: IoType::Memory(fs.clone()),
+            ..Default::default()
+        };
+
+        let engine = Engine::open(opts.clone()).expect("failed to open engine");
+        for i in 0..3000 {
+            engine.put(get_test_key(i), get_test_value(i)).unwrap();
+        }
+        for i in 0..1000 {
+            engine.delete(get_test_key(i)).unwrap();
+        }
+        engine.merge().expect("failed to merge");
+        assert!(!fs.is_dir(&get_merge_path(&opts.dir_path)));
+        assert!(fs.is_file(&get_hint_file_name(&opts.dir_path, INITIAL_FILE_ID)));
+
+        // merge 的文件在替换之前已经持久化, 掉电之后不会丢失
+        std::mem::forget(engine);
+        fs.power_loss();
+
+        let engine = Engine::open(opts.clone()).expect("failed to reopen engine");
+        assert_eq!(engine.stat().unwrap().key_num, 2000);
+        for i in 0..1000 {
+            assert_eq!(engine.get(get_test_key(i)).err(), Some(Errors::KeyNotFound));
+        }
+        for i in 1000..3000 {
+            assert_eq!(engine.get(get_test_key(i)).unwrap(), get_test_value(i));
+        }
+    }
+
+    #[test]
+    fn merge_with_smaller_data_file_size_should_not_overwrite_newer_files() {
+        let fs = MemoryFileSystem::new();
+        let opts = Options {
+            dir_path: PathBuf::from("/tmp/bitcask-merge-shrink"),
+            data_file_size: 64 * 1024,
+            io_type: IoType::Memory(fs.clone()),
+            ..Default::default()
+        };
+
+        let value = Bytes::from("x".repeat(1024));
+        let engine = Engine::open(opts.clone()).expect("failed to open engine");
+        for i in 0..300 {
+            engine.put(get_test_key(i), value.clone()).unwrap();
+        }
+        drop(engine);
+
+        // 调小文件大小之后, merge 的数据需要比原来更多的文件
+        let opts = Options {
+            data_file_size: 4 * 1024,
+            ..opts
+        };
+        let engine = Engine::open(opts.clone()).expect("failed to reopen engine");
+        let non_merge_file_id = engine.active_file.read().get_file_id() + 1;
+        engine.merge().expect("failed to merge");
+        assert!(
+            engine
+                .older_files
+                .read()
+                .keys()
+                .all(|fid| *fid < non_merge_file_id)
+        );
+        for i in 0..300 {
+            assert_eq!(engine.get(get_test_key(i)).unwrap(), value);
+        }
+
+        // merge 之后的写入在重启之后仍然存在
+        engine.put(get_test_key(300), value.clone()).unwrap();
+        drop(engine);
+
+        let engine = Engine::open(opts.clone()).expect("failed to reopen engine");
+        assert_eq!(engine.stat().unwrap().key_num, 301);
+        for i in 0..301 {
+            assert_eq!(engine.get(get_test_key(i)).unwrap(), value);
+        }
+    }
+}
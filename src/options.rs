@@ -1,4 +1,8 @@
-use std::path::PathBuf;
+use crate::{data::ENCRYPTION_KEY_SIZE, fio::MemoryFileSystem};
+use std::{
+    path::PathBuf,
+    time::{Duration, SystemTime},
+};
 
 #[derive(Clone)]
 pub struct Options {
@@ -8,11 +12,42 @@ pub struct Options {
     // 数据文件大小
     pub data_file_size: u64,
 
-    // 是否每次写入持久化
-    pub sync_write: bool,
+    // 写入数据时的持久化策略
+    pub sync_policy: SyncPolicy,
 
     // 索引类型
     pub index_type: IndexType,
+
+    // value 的压缩类型, 只影响新写入的数据, 已有的数据保持原来的压缩类型
+    pub compression: CompressionType,
+
+    // 数据文件的加密密钥, 设置之后新写入的记录使用该密钥加密, 为空时写入明文
+    pub encryption_key: Option<[u8; ENCRYPTION_KEY_SIZE]>,
+
+    // 轮换之前的旧密钥, 只用于解密, merge 时旧密钥加密的记录会使用当前密钥重新加密
+    pub old_encryption_keys: Vec<[u8; ENCRYPTION_KEY_SIZE]>,
+
+    // 打开数据库时遇到损坏或者写入不完整的记录的处理方式
+    pub recovery_policy: RecoveryPolicy,
+
+    // 是否使用 mmap 打开旧的数据文件, 加快启动时的索引加载以及随机读, 只对标准文件 IO 生效
+    pub mmap_older_files: bool,
+
+    // 数据目录使用的 IO 类型, 备份和恢复的目录使用同一个文件系统
+    pub io_type: IoType,
+
+    // 后台 compaction 的配置
+    pub compaction: CompactionOptions,
+}
+
+/// 数据目录使用的 IO 类型, 打开数据库, 列出和重命名文件以及目录锁都通过对应的文件系统
+#[derive(Clone)]
+pub enum IoType {
+    /// 标准文件 IO
+    StandardFile,
+
+    /// 内存中的文件系统, 数据不会持久化, 可以注入故障, 用于测试崩溃恢复
+    Memory(MemoryFileSystem),
 }
 
 #[derive(Clone)]
@@ -23,3 +58,165 @@ pub enum IndexType {
     /// 跳表 索引
     SkipList,
 }
+
+/// value 的压缩类型, 记录在每条数据的头部中
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CompressionType {
+    /// 不压缩
+    None = 0,
+
+    /// lz4 压缩, 速度快
+    Lz4 = 1,
+
+    /// zstd 压缩, 压缩率高
+    Zstd = 2,
+}
+
+/// 写入数据时的持久化策略
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SyncPolicy {
+    /// 每次写入都持久化
+    Always,
+
+    /// 累计写入指定字节数之后持久化一次
+    EveryNBytes(u64),
+
+    /// 由后台线程按照指定的时间间隔持久化
+    Interval(Duration),
+
+    /// 不主动持久化, 由操作系统决定, 可以通过 Engine::sync 手动持久化
+    Never,
+}
+
+/// 打开数据库时遇到损坏或者写入不完整的记录的处理方式, 丢弃的数据通过 Engine::discarded_data 获取
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RecoveryPolicy {
+    /// 在最后一条有效的记录处截断活跃文件, 只处理写入中断留下的不完整的尾部以及头部
+    /// 活跃文件中损坏的记录之后还有有效的记录, 或者旧的数据文件中有错误时打开失败, 可以使用 Skip 跳过损坏的记录
+    Truncate,
+
+    /// 跳过损坏的记录并打印警告, 数据文件保持不变, 跳过的数据在 merge 时回收
+    Skip,
+
+    /// 遇到任何损坏的记录都打开失败
+    Fail,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Self {
+            dir_path: std::env::temp_dir().join("bitcask-rs"),
+            data_file_size: 256 * 1024 * 1024, // 256MB
+            sync_policy: SyncPolicy::Never,
+            index_type: IndexType::BTree,
+            compression: CompressionType::None,
+            encryption_key: None,
+            old_encryption_keys: Vec::new(),
+            recovery_policy: RecoveryPolicy::Truncate,
+            mmap_older_files: false,
+            io_type: IoType::StandardFile,
+            compaction: CompactionOptions::default(),
+        }
+    }
+}
+
+/// 从备份恢复时的目标位置, 用于 Engine::restore
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RestoreTarget {
+    /// 恢复到最后一个备份
+    Latest,
+
+    /// 恢复到该序列号的批量写入或事务提交时的状态, 保留数据文件中该批次完成标记以及之前的数据
+    /// 只能定位到批次, 两个批次之间的普通写入按照在数据文件中的位置保留或丢弃, 需要定位到普通写入时按时间恢复
+    /// 该批次不在备份中时返回 NoBackupToRestore, merge 重写之后的数据不再保留序列号
+    BatchSeqNo(u64),
+
+    /// 恢复到该时间的状态, 保留数据文件中第一条在该时间之后提交的记录之前的数据
+    /// 同一次组提交的记录使用相同的提交时间, 批量写入和事务不会被拆开
+    /// 需要有在该时间及之后创建的备份, 否则返回 NoBackupToRestore
+    /// merge 重写的数据保留原来的提交时间, 但被覆盖的旧版本已经被回收, 恢复到 merge 之前的时间点时这些 key 的旧值无法恢复
+    /// 格式版本 7 之前写入的记录没有提交时间, 总是被保留
+    Timestamp(SystemTime),
+}
+
+/// 后台 compaction 的配置项, 默认不启用
+///
+/// 后台线程需要持有 Engine 的弱引用, Engine::open 不会自动启动
+/// 设置 enabled 之后将 Engine 放入 Arc 中, 再调用 Engine::start_compaction 启动
+#[derive(Clone)]
+pub struct CompactionOptions {
+    // 是否启用后台 compaction, 没有启用时 Engine::start_compaction 返回 CompactionNotEnabled
+    pub enabled: bool,
+
+    // 检查是否需要 compaction 的时间间隔
+    pub check_interval: Duration,
+
+    // 旧的数据文件中失效数据的占比达到该值时触发 merge, 取值范围 (0, 1]
+    pub dead_ratio_threshold: f64,
+
+    // 所有数据文件中失效数据的总大小达到该值时触发 merge, 0 表示不根据总大小触发
+    pub dead_bytes_threshold: u64,
+
+    // 后台 merge 时每秒读写的最大字节数, 0 表示不限制
+    pub rate_limit_bytes_per_sec: u64,
+}
+
+impl Default for CompactionOptions {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            check_interval: Duration::from_secs(60),
+            dead_ratio_threshold: 0.5,
+            dead_bytes_threshold: 1024 * 1024 * 1024, // 1GB
+            rate_limit_bytes_per_sec: 0,
+        }
+    }
+}
+
+/// 批量写入的配置项
+#[derive(Clone)]
+pub struct WriteBatchOptions {
+    // 一个批次当中的最大数据量
+    pub max_batch_num: usize,
+
+    // 提交时是否持久化
+    pub sync_writes: bool,
+}
+
+impl Default for WriteBatchOptions {
+    fn default() -> Self {
+        Self {
+            max_batch_num: 10000,
+            sync_writes: true,
+        }
+    }
+}
+
+/// 事务的配置项
+#[derive(Clone)]
+pub struct TransactionOptions {
+    // 提交时是否持久化
+    pub sync_writes: bool,
+}
+
+impl Default for TransactionOptions {
+    fn default() -> Self {
+        Self { sync_writes: true }
+    }
+}
+
+/// 迭代器的配置项
+#[derive(Clone, Default)]
+pub struct IteratorOptions {
+    // 遍历前缀为指定值的 key, 默认为空
+    pub prefix: Vec<u8>,
+
+    // 是否反向遍历, 默认 false 是正向
+    pub reverse: bool,
+
+    // 遍历范围的起始 key, 包含该 key, 默认不限制
+    pub start: Option<Vec<u8>>,
+
+    // 遍历范围的结束 key, 不包含该 key, 默认不限制
+    pub end: Option<Vec<u8>>,
+}
@@ -0,0 +1,466 @@
+use crate::{
+    Engine, EngineIterator, Errors, Indexer, LogRecord, LogRecordPos, LogRecordType, Result,
+    index::{KeyRange, SnapshotIterator, key_range},
+    options::IteratorOptions,
+};
+use bytes::Bytes;
+use parking_lot::Mutex;
+use std::{
+    collections::{BTreeMap, HashMap},
+    ops::Bound,
+};
+
+/// 数据库在某个提交序号上的只读快照, 只能看到该序号及之前提交的数据
+///
+/// 快照存在期间, 被覆盖或删除的旧版本保留在内存中, merge 也会保留这些版本对应的数据
+/// 快照在释放时失效, 不会在重启之后保留
+pub struct Snapshot<'a> {
+    engine: &'a Engine,
+    // 快照对应的提交序号
+    seq: u64,
+}
+
+/// 记录每次提交的序号, 以及事务和快照存在期间 key 的修改历史
+pub(crate) struct CommitTracker {
+    inner: Mutex<CommitTrackerInner>,
+}
+
+struct CommitTrackerInner {
+    // 最新的提交序号, 每次组提交递增
+    commit_seq: u64,
+    // 被事务或快照固定的提交序号以及对应的数量
+    pinned: BTreeMap<u64, usize>,
+    // key 最后一次被修改的提交序号, 用于事务的冲突检查
+    last_commit: HashMap<Vec<u8>, u64>,
+    // key 每次被修改的提交序号以及修改之前的位置, 按照提交序号递增排列
+    // 位置为 None 表示修改之前 key 不存在
+    history: BTreeMap<Vec<u8>, Vec<(u64, Option<LogRecordPos>)>>,
+}
+
+impl Engine {
+    /// 创建当前时间点的快照
+    pub fn snapshot(&self) -> Snapshot<'_> {
+        Snapshot {
+            engine: self,
+            seq: self.pin_commit_seq(),
+        }
+    }
+
+    /// 固定当前的提交序号, 持有活跃文件的锁, 保证不会固定在一次提交的中间
+    pub(crate) fn pin_commit_seq(&self) -> u64 {
+        let _active_file = self.active_file.read();
+        self.commits.pin()
+    }
+}
+
+impl Snapshot<'_> {
+    /// 读取快照中 key 对应的数据
+    pub fn get(&self, key: Bytes) -> Result<Bytes> {
+        if key.is_empty() {
+            return Err(Errors::KeyIsEmpty);
+        }
+
+        self.engine.get_value_at(&key, Some(self.seq))
+    }
+
+    /// 获取快照的迭代器, 遍历快照中的数据
+    pub fn iter(&self, options: IteratorOptions) -> EngineIterator<'_> {
+        // 不持有数据文件的锁, 避免拷贝索引时阻塞写入
+        // 提交时先记录修改历史再更新索引, 先拷贝索引再读取修改历史, 快照中的 key 一定在其中之一
+        // 遍历时根据快照的提交序号重新读取每个 key 的位置, 快照之后新增的 key 会被跳过
+        let mut items: BTreeMap<Vec<u8>, LogRecordPos> = BTreeMap::new();
+        let mut index_iter = self.engine.index.iterator(options.clone());
+        while let Some((key, pos)) = index_iter.next() {
+            items.insert(key.clone(), *pos);
+        }
+
+        // 快照之后被修改的 key 使用快照中的版本
+        if let Some(range) = key_range(&options) {
+            for (key, pos) in self.engine.commits.versions_at(self.seq, range) {
+                if !key.starts_with(&options.prefix) {
+                    continue;
+                }
+                match pos {
+                    Some(pos) => items.insert(key, pos),
+                    None => items.remove(&key),
+                };
+            }
+        }
+
+        EngineIterator::new(
+            self.engine,
+            Box::new(SnapshotIterator::new(
+                items.into_iter().collect(),
+                options.reverse,
+            )),
+            Some(self.seq),
+        )
+    }
+}
+
+impl Drop for Snapshot<'_> {
+    fn drop(&mut self) {
+        self.engine.commits.unpin(self.seq);
+    }
+}
+
+impl CommitTracker {
+    pub(crate) fn new() -> Self {
+        Self {
+            inner: Mutex::new(CommitTrackerInner {
+                commit_seq: 0,
+                pinned: BTreeMap::new(),
+                last_commit: HashMap::new(),
+                history: BTreeMap::new(),
+            }),
+        }
+    }
+
+    /// 固定当前的提交序号
+    fn pin(&self) -> u64 {
+        let mut inner = self.inner.lock();
+        let seq = inner.commit_seq;
+        *inner.pinned.entry(seq).or_default() += 1;
+        seq
+    }
+
+    /// 释放固定的提交序号, 清理不再需要的修改记录
+    pub(crate) fn unpin(&self, seq: u64) {
+        let mut inner = self.inner.lock();
+        if let Some(count) = inner.pinned.get_mut(&seq) {
+            *count -= 1;
+            if *count == 0 {
+                inner.pinned.remove(&seq);
+            }
+        }
+
+        match inner.pinned.keys().next().copied() {
+            Some(oldest) => {
+                inner
+                    .last_commit
+                    .retain(|_, commit_seq| *commit_seq > oldest);
+                inner.history.retain(|_, versions| {
+                    versions.retain(|(commit_seq, _)| *commit_seq > oldest);
+                    !versions.is_empty()
+                });
+            }
+            None => {
+                inner.last_commit.clear();
+                inner.history.clear();
+            }
+        }
+    }
+
+    /// 是否存在被固定的提交序号
+    #[cfg(test)]
+    pub(crate) fn has_pinned(&self) -> bool {
+        !self.inner.lock().pinned.is_empty()
+    }
+
+    /// key 在 seq 之后是否被修改过
+    pub(crate) fn changed_since(&self, key: &[u8], seq: u64) -> bool {
+        let inner = self.inner.lock();
+        inner
+            .last_commit
+            .get(key)
+            .is_some_and(|commit_seq| *commit_seq > seq)
+    }
+
+    /// 记录一次提交修改的 key 以及修改之前的位置, 需要在更新内存索引之前调用
+    pub(crate) fn commit(&self, records: &[LogRecord], index: &dyn Indexer) {
+        let mut inner = self.inner.lock();
+        inner.commit_seq += 1;
+
+        // 没有事务和快照时不需要记录
+        if inner.pinned.is_empty() {
+            return;
+        }
+
+        let commit_seq = inner.commit_seq;
+        for record in records {
+            if record.rec_type == LogRecordType::BATCHFINISHED {
+                continue;
+            }
+
+            inner.last_commit.insert(record.key.clone(), commit_seq);
+            // 同一次提交中多次修改同一个 key 时, 只记录这次提交之前的位置
+            let versions = inner.history.entry(record.key.clone()).or_default();
+            if versions.last().is_none_or(|(seq, _)| *seq != commit_seq) {
+                versions.push((commit_seq, index.get(record.key.clone())));
+            }
+        }
+    }
+
+    /// key 在 seq 时的位置, seq 之后没有被修改过时返回 None, 此时以内存索引为准
+    pub(crate) fn version_at(&self, key: &[u8], seq: u64) -> Option<Option<LogRecordPos>> {
+        let inner = self.inner.lock();
+        let versions = inner.history.get(key)?;
+        versions
+            .iter()
+            .find(|(commit_seq, _)| *commit_seq > seq)
+            .map(|(_, pos)| *pos)
+    }
+
+    /// 范围内 seq 之后被修改过的 key 在 seq 时的位置
+    fn versions_at(&self, seq: u64, range: KeyRange) -> Vec<(Vec<u8>, Option<LogRecordPos>)> {
+        let inner = self.inner.lock();
+        let range: (Bound<&[u8]>, Bound<&[u8]>) = (
+            range.0.as_ref().map(|key| key.as_slice()),
+            range.1.as_ref().map(|key| key.as_slice()),
+        );
+        inner
+            .history
+            .range::<[u8], _>(range)
+            .filter_map(|(key, versions)| {
+                versions
+                    .iter()
+                    .find(|(commit_seq, _)| *commit_seq > seq)
+                    .map(|(_, pos)| (key.clone(), *pos))
+            })
+            .collect()
+    }
+
+    /// key 的修改历史中是否引用了 pos 对应的数据
+    pub(crate) fn is_referenced(&self, key: &[u8], pos: &LogRecordPos) -> bool {
+        let inner = self.inner.lock();
+        inner.history.get(key).is_some_and(|versions| {
+            versions.iter().any(|(_, version)| {
+                version.is_some_and(|version| {
+                    version.get_file_id() == pos.get_file_id()
+                        && version.get_offset() == pos.get_offset()
+                })
+            })
+        })
+    }
+
+    /// merge 替换数据文件之后, 将修改历史中的位置更新为 merge 之后的位置
+    pub(crate) fn relocate(&self, relocated: &HashMap<(u32, u64), Option<LogRecordPos>>) {
+        let mut inner = self.inner.lock();
+        for (_, pos) in inner.history.values_mut().flatten() {
+            if let Some(old_pos) = pos
+                && let Some(new_pos) = relocated.get(&(old_pos.get_file_id(), old_pos.get_offset()))
+            {
+                *pos = *new_pos;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        IoManger, IoType, MemoryFileSystem, Options,
+        data::{DATA_FILE_HEADER_SIZE, get_data_file_name},
+        db::INITIAL_FILE_ID,
+    };
+    use std::{fs, path::PathBuf, thread};
+
+    #[test]
+    fn snapshot_should_see_data_at_creation() {
+        let opts = Options {
+            dir_path: PathBuf::from("/tmp/bitcask-snapshot"),
+            ..Default::default()
+        };
+        let _ = fs::remove_dir_all(&opts.dir_path);
+
+        let engine = Engine::open(opts.clone()).expect("failed to open engine");
+        for key in ["a", "b", "c"] {
+            engine.put(Bytes::from(key), Bytes::from("1")).unwrap();
+        }
+
+        let snapshot = engine.snapshot();
+        engine.put(Bytes::from("a"), Bytes::from("2")).unwrap();
+        engine.put(Bytes::from("a"), Bytes::from("3")).unwrap();
+        engine.delete(Bytes::from("b")).unwrap();
+        engine.put(Bytes::from("d"), Bytes::from("1")).unwrap();
+        let wb = engine
+            .new_write_batch(crate::WriteBatchOptions::default())
+            .unwrap();
+        wb.put(Bytes::from("c"), Bytes::from("2")).unwrap();
+        wb.put(Bytes::from("e"), Bytes::from("1")).unwrap();
+        wb.commit().unwrap();
+
+        // 快照中看不到之后的修改
+        assert_eq!(snapshot.get(Bytes::from("a")).unwrap(), Bytes::from("1"));
+        assert_eq!(snapshot.get(Bytes::from("b")).unwrap(), Bytes::from("1"));
+        assert_eq!(snapshot.get(Bytes::from("c")).unwrap(), Bytes::from("1"));
+        assert_eq!(
+            snapshot.get(Bytes::from("d")).err(),
+            Some(Errors::KeyNotFound)
+        );
+
+        let collect = |iter: EngineIterator| {
+            iter.map(|item| {
+                let (key, value) = item.unwrap();
+                format!(
+                    "{}={}",
+                    String::from_utf8_lossy(&key),
+                    String::from_utf8_lossy(&value)
+                )
+            })
+            .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            collect(snapshot.iter(IteratorOptions::default())),
+            vec!["a=1", "b=1", "c=1"]
+        );
+        assert_eq!(
+            collect(snapshot.iter(IteratorOptions {
+                start: Some(b"b".to_vec()),
+                reverse: true,
+                ..Default::default()
+            })),
+            vec!["c=1", "b=1"]
+        );
+
+        // 新的快照可以看到最新的数据
+        let latest = engine.snapshot();
+        assert_eq!(
+            collect(latest.iter(IteratorOptions::default())),
+            vec!["a=3", "c=2", "d=1", "e=1"]
+        );
+
+        // 迭代器创建之后的修改不可见
+        let mut iter = latest.iter(IteratorOptions::default());
+        engine.delete(Bytes::from("a")).unwrap();
+        assert_eq!(iter.next().unwrap().unwrap().1, Bytes::from("3"));
+
+        // 快照全部释放之后不再保留修改历史
+        drop(iter);
+        drop(latest);
+        drop(snapshot);
+        assert!(engine.commits.inner.lock().history.is_empty());
+
+        drop(engine);
+        fs::remove_dir_all(opts.dir_path).expect("failed to remove path");
+    }
+
+    #[test]
+    fn merge_should_keep_versions_visible_to_snapshot() {
+        let opts = Options {
+            dir_path: PathBuf::from("/tmp/bitcask-snapshot-merge"),
+            data_file_size: 32 * 1024,
+            ..Default::default()
+        };
+        let _ = fs::remove_dir_all(&opts.dir_path);
+
+        let engine = Engine::open(opts.clone()).expect("failed to open engine");
+        let value = |i: usize, round: usize| Bytes::from(format!("value-{}-{}", i, round));
+        for i in 0..500 {
+            engine
+                .put(Bytes::from(format!("key-{}", i)), value(i, 0))
+                .unwrap();
+        }
+
+        let snapshot = engine.snapshot();
+        for i in 0..500 {
+            let key = Bytes::from(format!("key-{}", i));
+            match i % 2 {
+                0 => engine.put(key, value(i, 1)).unwrap(),
+                _ => engine.delete(key).unwrap(),
+            }
+        }
+
+        // merge 之后快照中的旧版本仍然可以读取
+        engine.merge().expect("failed to merge");
+        for i in 0..500 {
+            assert_eq!(
+                snapshot.get(Bytes::from(format!("key-{}", i))).unwrap(),
+                value(i, 0)
+            );
+        }
+        assert_eq!(snapshot.iter(IteratorOptions::default()).count(), 500);
+
+        // 再次 merge 时仍然保留快照需要的旧版本
+        engine.merge().expect("failed to merge");
+        for i in 0..500 {
+            assert_eq!(
+                snapshot.get(Bytes::from(format!("key-{}", i))).unwrap(),
+                value(i, 0)
+            );
+        }
+        drop(snapshot);
+
+        // 为快照保留的旧版本不会在重启之后被加载, 再次 merge 时被回收
+        let check = |engine: &Engine| {
+            assert_eq!(engine.list_keys().unwrap().len(), 250);
+            for i in 0..500 {
+                let res = engine.get(Bytes::from(format!("key-{}", i)));
+                match i % 2 {
+                    0 => assert_eq!(res.unwrap(), value(i, 1)),
+                    _ => assert_eq!(res.err(), Some(Errors::KeyNotFound)),
+                }
+            }
+        };
+        drop(engine);
+        let engine = Engine::open(opts.clone()).expect("failed to reopen engine");
+        check(&engine);
+
+        // 没有 hint 文件时从数据文件重建索引, 被删除的 key 不会因为保留的旧版本重新出现
+        drop(engine);
+        for entry in fs::read_dir(&opts.dir_path).unwrap() {
+            let path = entry.unwrap().path();
+            if path.extension().is_some_and(|ext| ext == "hint") {
+                fs::remove_file(path).unwrap();
+            }
+        }
+        let engine = Engine::open(opts.clone()).expect("failed to reopen engine");
+        check(&engine);
+        engine.merge().expect("failed to merge");
+        check(&engine);
+
+        drop(engine);
+        fs::remove_dir_all(opts.dir_path).expect("failed to remove path");
+    }
+
+    #[test]
+    fn merge_should_keep_versions_pinned_while_merging() {
+        let fs = MemoryFileSystem::new();
+        let opts = Options {
+            dir_path: PathBuf::from("/tmp/bitcask-snapshot-merge-race"),
+            io_type: IoType::Memory(fs.clone()),
+            ..Default::default()
+        };
+
+        let engine = Engine::open(opts.clone()).expect("failed to open engine");
+        for i in 0..100 {
+            engine
+                .put(Bytes::from(format!("key-{}", i)), Bytes::from("value-0"))
+                .unwrap();
+        }
+
+        // 暂停之后后台 merge 在重写第一条记录之后等待
+        engine.pause_compaction();
+        let merge_file = get_data_file_name(
+            &PathBuf::from("/tmp/bitcask-snapshot-merge-race-merge"),
+            INITIAL_FILE_ID,
+        );
+        thread::scope(|s| {
+            let handle = s.spawn(|| engine.merge_with(true));
+            while fs
+                .file(&merge_file)
+                .is_none_or(|file| file.size().unwrap() <= DATA_FILE_HEADER_SIZE)
+            {
+                thread::yield_now();
+            }
+
+            // merge 开始之后创建快照, 并覆盖还没有被扫描到的 key
+            let snapshot = engine.snapshot();
+            engine
+                .put(Bytes::from("key-50"), Bytes::from("value-1"))
+                .unwrap();
+            engine.resume_compaction();
+            handle.join().unwrap().expect("failed to merge");
+
+            assert_eq!(
+                snapshot.get(Bytes::from("key-50")).unwrap(),
+                Bytes::from("value-0")
+            );
+            assert_eq!(
+                engine.get(Bytes::from("key-50")).unwrap(),
+                Bytes::from("value-1")
+            );
+        });
+    }
+}
@@ -0,0 +1,326 @@
+use crate::{
+    Engine, Errors, LogRecord, LogRecordType, Result, TransactionOptions,
+    data::{NEVER_EXPIRE, NO_COMMIT_TIME, NON_BATCH_SEQ_NO},
+};
+use bytes::Bytes;
+use parking_lot::Mutex;
+use std::collections::{HashMap, HashSet};
+
+/// 乐观事务, 读取基于事务开始时的快照, 写入暂存在事务中, 提交时检查冲突
+///
+/// 事务开始之后, 其他写入者修改了事务读取或写入过的 key, 提交时返回 TransactionConflict
+/// 没有提交的事务在释放时被丢弃
+pub struct Transaction<'a> {
+    engine: &'a Engine,
+    // 事务开始时的提交序号, 读取时只能看到该序号及之前提交的数据
+    start: u64,
+    // 暂存事务中的写入
+    pending_writes: Mutex<HashMap<Vec<u8>, LogRecord>>,
+    // 事务中读取过的 key
+    reads: Mutex<HashSet<Vec<u8>>>,
+    // 事务的配置项
+    options: TransactionOptions,
+}
+
+/// 提交时需要进行的冲突检查, 在组提交中持有活跃文件的锁执行
+pub(crate) struct ConflictCheck {
+    pub(crate) start: u64,
+    pub(crate) keys: Vec<Vec<u8>>,
+}
+
+impl Engine {
+    /// 开始一个乐观事务
+    pub fn begin_transaction(&self, options: TransactionOptions) -> Result<Transaction<'_>> {
+        self.check_open()?;
+        Ok(Transaction {
+            engine: self,
+            start: self.pin_commit_seq(),
+            pending_writes: Mutex::new(HashMap::new()),
+            reads: Mutex::new(HashSet::new()),
+            options,
+        })
+    }
+}
+
+impl Transaction<'_> {
+    /// 读取数据, 优先读取事务中暂存的写入, 否则读取事务开始时的数据
+    pub fn get(&self, key: Bytes) -> Result<Bytes> {
+        if key.is_empty() {
+            return Err(Errors::KeyIsEmpty);
+        }
+
+        if let Some(record) = self.pending_writes.lock().get(key.as_ref()) {
+            return match record.rec_type {
+                LogRecordType::DELETED => Err(Errors::KeyNotFound),
+                _ => Ok(Bytes::from(record.value.clone())),
+            };
+        }
+
+        self.reads.lock().insert(key.to_vec());
+        self.engine.get_value_at(&key, Some(self.start))
+    }
+
+    /// 写入数据, 提交之前对其他读取者不可见
+    pub fn put(&self, key: Bytes, value: Bytes) -> Result<()> {
+        if key.is_empty() {
+            return Err(Errors::KeyIsEmpty);
+        }
+
+        let record = LogRecord::new(key.to_vec(), value.to_vec());
+        self.pending_writes.lock().insert(key.to_vec(), record);
+
+        Ok(())
+    }
+
+    /// 删除数据, 提交之前对其他读取者不可见
+    pub fn delete(&self, key: Bytes) -> Result<()> {
+        if key.is_empty() {
+            return Err(Errors::KeyIsEmpty);
+        }
+
+        let mut pending_writes = self.pending_writes.lock();
+        // 如果数据不存在则丢弃暂存的写入, 同时作为一次读取, 提交时检查 key 是否被其他写入者创建
+        if self.engine.get_pos_at(&key, Some(self.start)).is_none() {
+            pending_writes.remove(key.as_ref());
+            self.reads.lock().insert(key.to_vec());
+            return Ok(());
+        }
+
+        let record = LogRecord {
+            key: key.to_vec(),
+            value: Default::default(),
+            rec_type: LogRecordType::DELETED,
+            seq_no: NON_BATCH_SEQ_NO,
+            expire_at: NEVER_EXPIRE,
+            commit_at: NO_COMMIT_TIME,
+        };
+        pending_writes.insert(key.to_vec(), record);
+
+        Ok(())
+    }
+
+    /// 提交事务, 事务中的写入作为一个批次原子地写入
+    /// 事务开始之后读取或写入过的 key 被其他写入者修改时返回 TransactionConflict
+    pub fn commit(self) -> Result<()> {
+        let pending_writes = self.pending_writes.lock();
+        // 只读的事务不需要检查冲突
+        if pending_writes.is_empty() {
+            return Ok(());
+        }
+
+        let mut keys: Vec<Vec<u8>> = self.reads.lock().iter().cloned().collect();
+        keys.extend(pending_writes.keys().cloned());
+        let check = ConflictCheck {
+            start: self.start,
+            keys,
+        };
+
+        let records = self.engine.batch_log_records(pending_writes.values());
+        self.engine
+            .group_commit_batch(records, self.options.sync_writes, Some(check))?;
+
+        Ok(())
+    }
+}
+
+impl Drop for Transaction<'_> {
+    fn drop(&mut self) {
+        self.engine.commits.unpin(self.start);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{IoType, MemoryFileSystem, Options, SyncPolicy};
+    use std::{fs, path::PathBuf, sync::Arc, thread};
+
+    #[test]
+    fn transaction_commit_should_work() {
+        let opts = Options {
+            dir_path: PathBuf::from("/tmp/bitcask-txn-commit"),
+            ..Default::default()
+        };
+        let _ = fs::remove_dir_all(&opts.dir_path);
+
+        let engine = Engine::open(opts.clone()).expect("failed to open engine");
+        engine.put(Bytes::from("a"), Bytes::from("1")).unwrap();
+        engine.put(Bytes::from("b"), Bytes::from("2")).unwrap();
+
+        let txn = engine
+            .begin_transaction(TransactionOptions::default())
+            .unwrap();
+        assert_eq!(txn.get(Bytes::from("a")).unwrap(), Bytes::from("1"));
+        txn.put(Bytes::from("a"), Bytes::from("10")).unwrap();
+        txn.delete(Bytes::from("b")).unwrap();
+        txn.delete(Bytes::from("missing")).unwrap();
+
+        // 事务中可以读取到自己的写入, 提交之前对其他读取者不可见
+        assert_eq!(txn.get(Bytes::from("a")).unwrap(), Bytes::from("10"));
+        assert_eq!(txn.get(Bytes::from("b")).err(), Some(Errors::KeyNotFound));
+        assert_eq!(engine.get(Bytes::from("a")).unwrap(), Bytes::from("1"));
+
+        // 没有修改的 key 不会冲突
+        engine.put(Bytes::from("c"), Bytes::from("3")).unwrap();
+        txn.commit().expect("failed to commit");
+        assert_eq!(engine.get(Bytes::from("a")).unwrap(), Bytes::from("10"));
+        assert_eq!(
+            engine.get(Bytes::from("b")).err(),
+            Some(Errors::KeyNotFound)
+        );
+        assert!(!engine.commits.has_pinned());
+        drop(engine);
+
+        // 事务以批次的方式写入, 重启之后仍然存在
+        let engine = Engine::open(opts.clone()).expect("failed to reopen engine");
+        assert_eq!(engine.get(Bytes::from("a")).unwrap(), Bytes::from("10"));
+        assert_eq!(
+            engine.get(Bytes::from("b")).err(),
+            Some(Errors::KeyNotFound)
+        );
+
+        drop(engine);
+        fs::remove_dir_all(opts.dir_path).expect("failed to remove path");
+    }
+
+    #[test]
+    fn transaction_conflict_should_fail() {
+        let opts = Options {
+            dir_path: PathBuf::from("/tmp/bitcask-txn-conflict"),
+            ..Default::default()
+        };
+        let _ = fs::remove_dir_all(&opts.dir_path);
+
+        let engine = Engine::open(opts.clone()).expect("failed to open engine");
+        engine.put(Bytes::from("a"), Bytes::from("1")).unwrap();
+
+        // 读取的 key 被其他写入者修改
+        let txn1 = engine
+            .begin_transaction(TransactionOptions::default())
+            .unwrap();
+        let txn2 = engine
+            .begin_transaction(TransactionOptions::default())
+            .unwrap();
+        txn1.get(Bytes::from("a")).unwrap();
+        txn1.put(Bytes::from("b"), Bytes::from("1")).unwrap();
+        txn2.put(Bytes::from("a"), Bytes::from("2")).unwrap();
+        txn2.commit().expect("failed to commit");
+        assert_eq!(txn1.commit().err(), Some(Errors::TransactionConflict));
+        assert_eq!(
+            engine.get(Bytes::from("b")).err(),
+            Some(Errors::KeyNotFound)
+        );
+
+        // 写入的 key 被普通的写入修改
+        let txn = engine
+            .begin_transaction(TransactionOptions::default())
+            .unwrap();
+        txn.put(Bytes::from("a"), Bytes::from("3")).unwrap();
+        engine.delete(Bytes::from("a")).unwrap();
+        assert_eq!(txn.commit().err(), Some(Errors::TransactionConflict));
+
+        // 删除时不存在的 key 被其他写入者创建
+        let txn = engine
+            .begin_transaction(TransactionOptions::default())
+            .unwrap();
+        txn.delete(Bytes::from("a")).unwrap();
+        txn.put(Bytes::from("c"), Bytes::from("1")).unwrap();
+        engine.put(Bytes::from("a"), Bytes::from("4")).unwrap();
+        // 事务中读取的是开始时的数据
+        assert_eq!(txn.get(Bytes::from("a")).err(), Some(Errors::KeyNotFound));
+        assert_eq!(txn.commit().err(), Some(Errors::TransactionConflict));
+
+        drop(engine);
+        fs::remove_dir_all(opts.dir_path).expect("failed to remove path");
+    }
+
+    #[test]
+    fn transaction_concurrent_increment_should_not_lose_updates() {
+        let opts = Options {
+            dir_path: PathBuf::from("/tmp/bitcask-txn-concurrent"),
+            ..Default::default()
+        };
+        let _ = fs::remove_dir_all(&opts.dir_path);
+
+        let engine = Arc::new(Engine::open(opts.clone()).expect("failed to open engine"));
+        engine
+            .put(Bytes::from("counter"), Bytes::from("0"))
+            .unwrap();
+
+        let mut handles = Vec::new();
+        for _ in 0..4 {
+            let engine = engine.clone();
+            handles.push(thread::spawn(move || {
+                let mut done = 0;
+                while done < 25 {
+                    let txn = engine
+                        .begin_transaction(TransactionOptions::default())
+                        .unwrap();
+                    let value = txn.get(Bytes::from("counter")).unwrap();
+                    let n: u64 = String::from_utf8(value.to_vec()).unwrap().parse().unwrap();
+                    txn.put(Bytes::from("counter"), Bytes::from((n + 1).to_string()))
+                        .unwrap();
+                    match txn.commit() {
+                        Ok(()) => done += 1,
+                        Err(Errors::TransactionConflict) => {}
+                        Err(e) => panic!("unexpected error: {}", e),
+                    }
+                }
+            }));
+        }
+        for handle in handles {
+            handle.join().unwrap();
+        }
+
+        assert_eq!(
+            engine.get(Bytes::from("counter")).unwrap(),
+            Bytes::from("100")
+        );
+
+        drop(engine);
+        fs::remove_dir_all(opts.dir_path).expect("failed to remove path");
+    }
+
+    #[test]
+    fn transaction_commit_should_sync_by_options() {
+        let fs = MemoryFileSystem::new();
+        let opts = Options {
+            dir_path: PathBuf::from("/tmp/bitcask-txn-sync"),
+            io_type: IoType::Memory(fs.clone()),
+            sync_policy: SyncPolicy::Never,
+            ..Default::default()
+        };
+
+        let engine = Engine::open(opts.clone()).expect("failed to open engine");
+        let txn = engine
+            .begin_transaction(TransactionOptions::default())
+            .unwrap();
+        txn.put(Bytes::from("a"), Bytes::from("1")).unwrap();
+        txn.commit().expect("failed to commit");
+
+        let txn = engine
+            .begin_transaction(TransactionOptions { sync_writes: false })
+            .unwrap();
+        txn.put(Bytes::from("b"), Bytes::from("2")).unwrap();
+        txn.commit().expect("failed to commit");
+
+        // 默认提交时持久化, 掉电之后只丢失没有持久化的事务
+        std::mem::forget(engine);
+        fs.power_loss();
+        let engine = Engine::open(opts.clone()).expect("failed to reopen engine");
+        assert_eq!(engine.get(Bytes::from("a")).unwrap(), Bytes::from("1"));
+        assert_eq!(
+            engine.get(Bytes::from("b")).err(),
+            Some(Errors::KeyNotFound)
+        );
+
+        // 关闭之后不能开始事务
+        engine.close().expect("failed to close engine");
+        assert_eq!(
+            engine
+                .begin_transaction(TransactionOptions::default())
+                .err(),
+            Some(Errors::EngineClosed)
+        );
+    }
+}